@@ -0,0 +1,180 @@
+use std::fmt;
+use std::str::FromStr;
+
+use ed25519_dalek::{ Keypair, Signature, KEYPAIR_LENGTH, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH, };
+use x25519_dalek::{ EphemeralSecret, PublicKey as DhPublicKey, };
+
+
+pub const SIGNATURE_SIZE: usize = SIGNATURE_LENGTH;     // 64
+pub const PUBLIC_KEY_SIZE: usize = PUBLIC_KEY_LENGTH;   // 32
+pub const DH_KEY_SIZE: usize = 32;
+
+
+/// 节点的长期身份密钥 (Ed25519)
+pub struct Identity {
+    keypair: Keypair,
+}
+
+impl Identity {
+    pub fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        Self { keypair: Keypair::generate(&mut rng) }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.keypair.public.to_bytes())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_SIZE] {
+        self.keypair.sign(message).to_bytes()
+    }
+
+    /// 导出私钥（base64），可通过 `FromStr` 重新解析。
+    /// 注意: 返回值包含私钥，不要写入日志
+    pub fn to_secret_base64(&self) -> String {
+        base64::encode(&self.keypair.to_bytes()[..])
+    }
+}
+
+impl Clone for Identity {
+    fn clone(&self) -> Self {
+        // NOTE: `Keypair` 没有实现 `Clone`
+        Self { keypair: Keypair::from_bytes(&self.keypair.to_bytes()).unwrap() }
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 不输出私钥
+        write!(f, "Identity({})", self.public_key())
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 只输出公钥，私钥请使用 `to_secret_base64`
+        write!(f, "{}", self.public_key())
+    }
+}
+
+impl FromStr for Identity {
+    type Err = base64::DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = base64::decode(s)?;
+        if bytes.len() != KEYPAIR_LENGTH {
+            return Err(base64::DecodeError::InvalidLength);
+        }
+
+        Keypair::from_bytes(&bytes)
+            .map(|keypair| Self { keypair })
+            .map_err(|_| base64::DecodeError::InvalidLength)
+    }
+}
+
+
+/// 节点身份公钥
+#[derive(Clone, Copy, Eq, PartialEq, Hash)]
+pub struct PublicKey(pub [u8; PUBLIC_KEY_SIZE]);
+
+impl PublicKey {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != PUBLIC_KEY_SIZE {
+            return None;
+        }
+
+        let mut key = [0u8; PUBLIC_KEY_SIZE];
+        key.copy_from_slice(bytes);
+        Some(PublicKey(key))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; PUBLIC_KEY_SIZE] {
+        &self.0
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let public_key = match ed25519_dalek::PublicKey::from_bytes(&self.0) {
+            Ok(public_key) => public_key,
+            Err(_) => return false,
+        };
+        let signature = match Signature::from_bytes(signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+
+        public_key.verify(message, &signature).is_ok()
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", base64::encode(&self.0[..]))
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = base64::DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = base64::decode(s)?;
+        Self::from_bytes(&bytes).ok_or(base64::DecodeError::InvalidLength)
+    }
+}
+
+
+/// 用于单次握手的临时 X25519 密钥
+pub struct EphemeralKey {
+    secret: EphemeralSecret,
+    public: DhPublicKey,
+}
+
+impl EphemeralKey {
+    pub fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        let secret = EphemeralSecret::new(&mut rng);
+        let public = DhPublicKey::from(&secret);
+
+        Self { secret, public }
+    }
+
+    pub fn public_key(&self) -> [u8; DH_KEY_SIZE] {
+        *self.public.as_bytes()
+    }
+
+    /// 计算共享密钥，临时密钥在使用后即被销毁
+    pub fn diffie_hellman(self, their_public: &[u8; DH_KEY_SIZE]) -> [u8; DH_KEY_SIZE] {
+        let their_public = DhPublicKey::from(*their_public);
+        *self.secret.diffie_hellman(&their_public).as_bytes()
+    }
+}
+
+
+#[test]
+fn test_sign_verify() {
+    let identity = Identity::generate();
+    let public_key = identity.public_key();
+    let signature = identity.sign(b"exodus");
+
+    assert!(public_key.verify(b"exodus", &signature));
+    assert!(!public_key.verify(b"exodus!", &signature));
+
+    let s = identity.to_secret_base64();
+    assert_eq!(s.parse::<Identity>().unwrap().public_key(), public_key);
+    assert_eq!(identity.to_string(), public_key.to_string());
+}
+
+#[test]
+fn test_diffie_hellman() {
+    let a = EphemeralKey::generate();
+    let b = EphemeralKey::generate();
+    let a_public = a.public_key();
+    let b_public = b.public_key();
+
+    assert_eq!(a.diffie_hellman(&b_public), b.diffie_hellman(&a_public));
+}
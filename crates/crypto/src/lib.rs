@@ -3,12 +3,24 @@ extern crate base64;
 extern crate chacha20_poly1305_aead;
 extern crate miscreant;
 extern crate ed25519_dalek;
+extern crate x25519_dalek;
+extern crate sha2;
+extern crate hkdf;
+
+mod identity;
+
+pub use self::identity::{
+    Identity, PublicKey, EphemeralKey,
+    SIGNATURE_SIZE, PUBLIC_KEY_SIZE, DH_KEY_SIZE,
+};
 
 use std::io;
 use std::fmt;
 use std::str::FromStr;
 
 use rand::RngCore;
+use sha2::Sha256;
+use hkdf::Hkdf;
 use miscreant::IV_SIZE; // 16
 use miscreant::aead::{Aead, Aes256SivAead};
 
@@ -61,6 +73,25 @@ impl FromStr for Aes256Key {
     }
 }
 
+impl Aes256Key {
+    /// 使用 HKDF-SHA256 从共享密钥中派生会话密钥
+    pub fn derive(ikm: &[u8], salt: &[u8], info: &[u8]) -> Self {
+        let mut skey = [0u8; SKEY_SIZE];
+        Hkdf::<Sha256>::new(Some(salt), ikm)
+            .expand(info, &mut skey)
+            .expect("HKDF output length is valid");
+
+        let mut key   = [0u8; KEY_SIZE];
+        let mut ad    = [0u8; IV_SIZE];
+        let mut nonce = [0u8; IV_SIZE];
+        key.copy_from_slice(&skey[..KEY_SIZE]);
+        ad.copy_from_slice(&skey[KEY_SIZE..KEY_SIZE+IV_SIZE]);
+        nonce.copy_from_slice(&skey[KEY_SIZE+IV_SIZE..]);
+
+        Self { key, ad, nonce }
+    }
+}
+
 
 pub struct AesSiv256 {
     key: Aes256Key,
@@ -99,6 +130,17 @@ impl AesSiv256 {
         self.siv.open(&self.key.nonce, &self.key.ad, ciphertext.as_ref())
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
+
+    /// 使用调用方提供的 nonce 和附加数据 (Associated Data) 加密，
+    /// 同一个密钥下 nonce 不应该重复使用
+    pub fn seal_with<T: AsRef<[u8]>>(&mut self, nonce: &[u8], ad: &[u8], plaintext: T) -> Vec<u8> {
+        self.siv.seal(nonce, ad, plaintext.as_ref())
+    }
+
+    pub fn open_with<T: AsRef<[u8]>>(&mut self, nonce: &[u8], ad: &[u8], ciphertext: T) -> Result<Vec<u8>, io::Error> {
+        self.siv.open(nonce, ad, ciphertext.as_ref())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
 }
 
 
@@ -132,3 +174,41 @@ fn test_open() {
 }
 
 
+
+#[test]
+fn test_derive() {
+    let key = Aes256Key::derive(b"shared secret", b"salt", b"info");
+    assert_eq!(key.to_string(), Aes256Key::derive(b"shared secret", b"salt", b"info").to_string());
+    assert_eq!(key.to_string().parse::<Aes256Key>().unwrap().to_string(), key.to_string());
+
+    assert_ne!(key.to_string(), Aes256Key::derive(b"shared secret!", b"salt", b"info").to_string());
+    assert_ne!(key.to_string(), Aes256Key::derive(b"shared secret", b"salt!", b"info").to_string());
+    assert_ne!(key.to_string(), Aes256Key::derive(b"shared secret", b"salt", b"info!").to_string());
+}
+
+#[test]
+fn test_seal_with_open_with() {
+    let key = Aes256Key::derive(b"shared secret", b"salt", b"info");
+    let mut siv = AesSiv256::new(key);
+    let nonce = 1u64.to_be_bytes();
+    let ad = b"header";
+
+    let ciphertext = siv.seal_with(&nonce, ad, b"exodus");
+    assert_ne!(&ciphertext[..], &b"exodus"[..]);
+    assert_eq!(siv.open_with(&nonce, ad, &ciphertext).unwrap(), b"exodus");
+
+    // nonce 不同，密文也不同
+    assert_ne!(siv.seal_with(&2u64.to_be_bytes(), ad, b"exodus"), ciphertext);
+
+    // nonce、附加数据或者密文被篡改
+    assert!(siv.open_with(&2u64.to_be_bytes(), ad, &ciphertext).is_err());
+    assert!(siv.open_with(&nonce, b"header!", &ciphertext).is_err());
+    let mut tampered = ciphertext.clone();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 0x01;
+    assert!(siv.open_with(&nonce, ad, &tampered).is_err());
+
+    // 其它密钥
+    let mut other = AesSiv256::new(Aes256Key::derive(b"shared secret", b"salt", b"other"));
+    assert!(other.open_with(&nonce, ad, &ciphertext).is_err());
+}
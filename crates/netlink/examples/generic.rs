@@ -0,0 +1,25 @@
+extern crate netlink;
+
+use std::io;
+
+// cargo run --example generic [family name]
+fn main() -> Result<(), io::Error> {
+    let mut buffer = netlink::packet::alloc();
+    let mut controller = netlink::generic::GenericController::new()?;
+
+    match std::env::args().nth(1) {
+        Some(name) => {
+            let family = controller.family(&name, &mut buffer)?;
+            println!("{:#?}", family);
+        },
+        None => {
+            for x in controller.families(&mut buffer)? {
+                let family = x?;
+                let groups = family.mcast_groups.iter().map(|group| group.name.as_str()).collect::<Vec<&str>>();
+                println!("{:>4} {:16} version={} ops={} groups={:?}", family.id, family.name, family.version, family.ops.len(), groups);
+            }
+        },
+    }
+
+    Ok(())
+}
@@ -70,13 +70,16 @@ fn list() -> Result<(), io::Error> {
 fn del(dst_addr: IpAddr, prefix_len: u8) -> Result<(), io::Error> {
     let mut buffer = netlink::packet::alloc();
     let mut socket = netlink::route::RouteController::new()?;
-    socket.remove_route(dst_addr, prefix_len, &mut buffer)
+    socket.remove_route(dst_addr, prefix_len, netlink::packet::RouteTable::RT_TABLE_MAIN, &mut buffer)
 }
 
 fn add(dst_addr: IpAddr, prefix_len: u8, gateway: Option<IpAddr>, ifindex: Option<u32>) -> Result<(), io::Error> {
     let mut buffer = netlink::packet::alloc();
     let mut socket = netlink::route::RouteController::new()?;
-    socket.add_route(dst_addr, prefix_len, gateway, ifindex, &mut buffer)
+    let mut req = netlink::route::route::RouteRequest::new(dst_addr, prefix_len);
+    req.gateway = gateway;
+    req.ifindex = ifindex;
+    socket.add_route(&req, &mut buffer)
 }
 
 fn get(dst_addr: IpAddr, prefix_len: u8) -> Result<netlink::route::route::Route, io::Error> {
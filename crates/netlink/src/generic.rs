@@ -0,0 +1,408 @@
+// Generic netlink (NETLINK_GENERIC)
+//
+// Families such as nl80211, ethtool or wireguard are registered at runtime with a dynamic ID,
+// which is resolved by name through the controller family (`GENL_ID_CTRL`).
+//
+// /usr/include/linux/genetlink.h
+use crate::packet;
+use crate::packet::Kind;
+use crate::packet::Flags;
+use crate::packet::CtrlCmd;
+use crate::packet::CtrlAttrType;
+use crate::packet::NetlinkPacket;
+use crate::packet::NetlinkErrorPacket;
+use crate::packet::GenericPacket;
+use crate::packet::NetlinkAttrs;
+use crate::packet::NetlinkAttrBuilder;
+use crate::socket::NetlinkSocket;
+
+use byteorder::{ByteOrder, NativeEndian};
+
+use std::io;
+use std::convert::TryFrom;
+
+
+/// A generic netlink message, `attrs` is the payload after the `genlmsghdr` header
+/// (families which have a `hdr_size` put their own header before the attributes).
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub family_id: u16,
+    pub cmd: u8,
+    pub version: u8,
+    pub attrs: Vec<u8>,
+}
+
+impl Message {
+    pub fn attrs(&self) -> NetlinkAttrs<'_> {
+        NetlinkAttrs::new(&self.attrs)
+    }
+}
+
+/// A command of the family (`CTRL_ATTR_OPS`), `flags` are the `GENL_ADMIN_PERM`, `GENL_CMD_CAP_*` bits.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Op {
+    pub id: u32,
+    pub flags: u32,
+}
+
+/// A multicast group of the family (`CTRL_ATTR_MCAST_GROUPS`),
+/// see `NetlinkSocket::set_mcast_groups`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct McastGroup {
+    pub id: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Family {
+    pub id: u16,
+    pub name: String,
+    pub version: u32,
+    pub hdr_size: u32,
+    pub max_attr: u32,
+    pub ops: Vec<Op>,
+    pub mcast_groups: Vec<McastGroup>,
+}
+
+impl Family {
+    pub fn mcast_group(&self, name: &str) -> Option<u32> {
+        self.mcast_groups.iter().find(|group| group.name == name).map(|group| group.id)
+    }
+}
+
+fn parse_str(data: &[u8]) -> String {
+    let data = data.split(|b| *b == 0).next().unwrap_or(&[]);
+    String::from_utf8_lossy(data).to_string()
+}
+
+impl TryFrom<&Message> for Family {
+    type Error = io::Error;
+
+    fn try_from(value: &Message) -> Result<Self, Self::Error> {
+        let mut family = Family {
+            id: 0,
+            name: String::new(),
+            version: 0,
+            hdr_size: 0,
+            max_attr: 0,
+            ops: Vec::new(),
+            mcast_groups: Vec::new(),
+        };
+
+        for attr in value.attrs() {
+            let attr = attr?;
+            let attr_kind = CtrlAttrType(attr.attr_type());
+            let attr_data = attr.payload();
+
+            if attr_kind == CtrlAttrType::CTRL_ATTR_FAMILY_ID {
+                family.id = NativeEndian::read_u16(&attr_data);
+            } else if attr_kind == CtrlAttrType::CTRL_ATTR_FAMILY_NAME {
+                family.name = parse_str(attr_data);
+            } else if attr_kind == CtrlAttrType::CTRL_ATTR_VERSION {
+                family.version = NativeEndian::read_u32(&attr_data);
+            } else if attr_kind == CtrlAttrType::CTRL_ATTR_HDRSIZE {
+                family.hdr_size = NativeEndian::read_u32(&attr_data);
+            } else if attr_kind == CtrlAttrType::CTRL_ATTR_MAXATTR {
+                family.max_attr = NativeEndian::read_u32(&attr_data);
+            } else if attr_kind == CtrlAttrType::CTRL_ATTR_OPS {
+                // NOTE: A list of nested attributes, the type of each element is its index.
+                for elem in NetlinkAttrs::new(attr_data) {
+                    let mut op = Op { id: 0, flags: 0 };
+                    for op_attr in NetlinkAttrs::new(elem?.payload()) {
+                        let op_attr = op_attr?;
+                        match op_attr.attr_type() {
+                            packet::CTRL_ATTR_OP_ID => op.id = NativeEndian::read_u32(op_attr.payload()),
+                            packet::CTRL_ATTR_OP_FLAGS => op.flags = NativeEndian::read_u32(op_attr.payload()),
+                            _ => { },
+                        }
+                    }
+                    family.ops.push(op);
+                }
+            } else if attr_kind == CtrlAttrType::CTRL_ATTR_MCAST_GROUPS {
+                for elem in NetlinkAttrs::new(attr_data) {
+                    let mut group = McastGroup { id: 0, name: String::new() };
+                    for group_attr in NetlinkAttrs::new(elem?.payload()) {
+                        let group_attr = group_attr?;
+                        match group_attr.attr_type() {
+                            packet::CTRL_ATTR_MCAST_GRP_ID => group.id = NativeEndian::read_u32(group_attr.payload()),
+                            packet::CTRL_ATTR_MCAST_GRP_NAME => group.name = parse_str(group_attr.payload()),
+                            _ => { },
+                        }
+                    }
+                    family.mcast_groups.push(group);
+                }
+            } else {
+                trace!("Droped Family Attr: type={:15} data={:?}", format!("{:?}", attr_kind), attr_data);
+            }
+        }
+
+        Ok(family)
+    }
+}
+
+
+pub struct Messages<'a, 'b> {
+    pub(crate) socket: &'a mut NetlinkSocket,
+    pub(crate) buffer: &'b mut [u8],
+    pub(crate) family_id: u16,
+    pub(crate) is_done: bool,
+    pub(crate) buffer_len: usize,
+    pub(crate) offset: usize,
+}
+
+impl<'a, 'b> Messages<'a, 'b> {
+    fn next_packet(&mut self) -> Result<Option<NetlinkPacket<&[u8]>>, io::Error> {
+        if self.offset >= self.buffer_len {
+            let amt = self.socket.recv(&mut self.buffer)?;
+            trace!("read {} bytes from netlink socket.", amt);
+            self.buffer_len = amt;
+            self.offset = 0;
+        }
+
+        if self.buffer_len < NetlinkPacket::<&[u8]>::MIN_SIZE {
+            self.is_done = true;
+            return Ok(None);
+        }
+
+        let start = self.offset;
+        let pkt = NetlinkPacket::new_checked(&self.buffer[self.offset..self.buffer_len])?;
+        let pkt_len = pkt.total_len();
+        self.offset += pkt_len;
+        let end = self.offset;
+
+        let pkt = NetlinkPacket::new_unchecked(&self.buffer[start..end]);
+        match pkt.kind() {
+            Kind::NLMSG_NOOP     => Ok(None),
+            Kind::NLMSG_ERROR    => {
+                // NOTE: The ACK of a request which is not a dump.
+                self.is_done = true;
+                let err_pkt = NetlinkErrorPacket::new_checked(pkt.payload())?;
+                if err_pkt.errorno() != 0 {
+                    return Err(err_pkt.err_with_ext_ack(pkt.flags()));
+                }
+                Ok(None)
+            },
+            Kind::NLMSG_DONE     => {
+                self.is_done = true;
+                Ok(None)
+            },
+            Kind::NLMSG_OVERRUN  => Err(io::Error::new(io::ErrorKind::InvalidData, "Overrun")),
+            kind if kind.0 == self.family_id => Ok(Some(pkt)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Netlink Message Type is not `{}`", self.family_id))),
+        }
+    }
+}
+
+impl<'a, 'b> Iterator for Messages<'a, 'b> {
+    type Item = Result<Message, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.is_done {
+                return None;
+            }
+
+            let pkt = match self.next_packet() {
+                Ok(Some(pkt)) => pkt,
+                Ok(None) => continue,
+                Err(e) => {
+                    self.is_done = true;
+                    return Some(Err(e));
+                },
+            };
+
+            let family_id = pkt.kind().0;
+            let genl_pkt = match GenericPacket::new_checked(pkt.payload()) {
+                Ok(genl_pkt) => genl_pkt,
+                Err(e) => return Some(Err(e)),
+            };
+
+            return Some(Ok(Message {
+                family_id,
+                cmd: genl_pkt.cmd(),
+                version: genl_pkt.version(),
+                attrs: genl_pkt.payload().to_vec(),
+            }));
+        }
+    }
+}
+
+pub struct Families<'a, 'b> {
+    messages: Messages<'a, 'b>,
+}
+
+impl<'a, 'b> Iterator for Families<'a, 'b> {
+    type Item = Result<Family, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.messages.next()? {
+            Ok(message) => Some(Family::try_from(&message)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+
+/// Generic netlink families (`genl ctrl list`) and their commands.
+///
+/// ```no_run
+/// use netlink::generic::GenericController;
+/// use netlink::packet::NetlinkAttrBuilder;
+///
+/// const WG_CMD_GET_DEVICE: u8 = 0;
+/// const WGDEVICE_A_IFNAME: u16 = 2;
+///
+/// let mut buffer = netlink::packet::alloc();
+/// let mut controller = GenericController::new().unwrap();
+/// let family = controller.family("wireguard", &mut buffer).unwrap();
+///
+/// let mut attrs = NetlinkAttrBuilder::new();
+/// attrs.put_strz(WGDEVICE_A_IFNAME, "wg0");
+/// let flags = netlink::packet::Flags::NLM_F_DUMP;
+/// for message in controller.request(family.id, WG_CMD_GET_DEVICE, family.version as u8, flags, attrs.as_bytes(), &mut buffer).unwrap() {
+///     println!("{:?}", message);
+/// }
+/// ```
+pub struct GenericController {
+    nl_socket: NetlinkSocket,
+}
+
+impl GenericController {
+    pub fn new() -> Result<Self, io::Error> {
+        let mut nl_socket = NetlinkSocket::new(packet::Protocol::NETLINK_GENERIC.into())?;
+
+        let pid    = 0;
+        let groups = 0;
+        nl_socket.bind(pid, groups)?;
+
+        Ok(Self { nl_socket })
+    }
+
+    /// Every registered family.
+    pub fn families<'a, 'b>(&'a mut self, buffer: &'b mut [u8]) -> Result<Families<'a, 'b>, io::Error> {
+        let messages = self.request(packet::GENL_ID_CTRL.0,
+                                    CtrlCmd::CTRL_CMD_GETFAMILY.into(),
+                                    1,
+                                    Flags::NLM_F_DUMP,
+                                    &[],
+                                    buffer)?;
+
+        Ok(Families { messages })
+    }
+
+    /// Resolve the family by name (`CTRL_CMD_GETFAMILY`), fails with `NotFound` if it is not registered
+    /// (the kernel module may not be loaded yet).
+    pub fn family(&mut self, name: &str, buffer: &mut [u8]) -> Result<Family, io::Error> {
+        if name.is_empty() || name.len() >= packet::GENL_NAMSIZ {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid family name"));
+        }
+
+        let mut attrs = NetlinkAttrBuilder::new();
+        attrs.put_strz(CtrlAttrType::CTRL_ATTR_FAMILY_NAME.into(), name);
+
+        let messages = self.request(packet::GENL_ID_CTRL.0,
+                                    CtrlCmd::CTRL_CMD_GETFAMILY.into(),
+                                    1,
+                                    Flags::empty(),
+                                    attrs.as_bytes(),
+                                    buffer)?;
+
+        // NOTE: Read until the ACK, so the next request does not see it.
+        let mut family = None;
+        for message in messages {
+            let message = message?;
+            if family.is_none() {
+                family = Some(Family::try_from(&message)?);
+            }
+        }
+
+        family.ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    /// Send a command to the family, and iterate over the replies.
+    ///
+    /// `NLM_F_REQUEST` and `NLM_F_ACK` are always set, use `NLM_F_DUMP` for the commands which list objects.
+    pub fn request<'a, 'b>(&'a mut self,
+                           family_id: u16,
+                           cmd: u8,
+                           version: u8,
+                           flags: Flags,
+                           attrs: &[u8],
+                           buffer: &'b mut [u8]) -> Result<Messages<'a, 'b>, io::Error> {
+        let len = emit_message(family_id, cmd, version, flags | Flags::NLM_F_ACK, attrs, buffer)?;
+        self.nl_socket.send_messages(&mut buffer[..len])?;
+
+        Ok(Messages {
+            socket: &mut self.nl_socket,
+            buffer: buffer,
+            family_id,
+            is_done: false,
+            buffer_len: 0,
+            offset: 0,
+        })
+    }
+
+    /// Send a command which changes something, and wait for the kernel to acknowledge it.
+    pub fn execute(&mut self, family_id: u16, cmd: u8, version: u8, attrs: &[u8], buffer: &mut [u8]) -> Result<(), io::Error> {
+        let len = emit_message(family_id, cmd, version, Flags::empty(), attrs, buffer)?;
+
+        self.nl_socket.request(buffer, len)
+    }
+}
+
+fn emit_message(family_id: u16, cmd: u8, version: u8, flags: Flags, attrs: &[u8], buffer: &mut [u8]) -> Result<usize, io::Error> {
+    let header_len = NetlinkPacket::<&[u8]>::MIN_SIZE + GenericPacket::<&[u8]>::MIN_SIZE;
+    let nl_packet_len = header_len + attrs.len();
+    if buffer.len() < nl_packet_len {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is too small."));
+    }
+
+    let mut nl_packet = NetlinkPacket::new_unchecked(&mut buffer[..nl_packet_len]);
+    nl_packet.set_len(nl_packet_len as u32);
+    nl_packet.set_kind(Kind(family_id));
+    nl_packet.set_flags(flags | Flags::NLM_F_REQUEST);
+    nl_packet.set_seq(0);
+    nl_packet.set_pid(0);
+
+    let mut genl_packet = GenericPacket::new_unchecked(nl_packet.payload_mut());
+    genl_packet.set_cmd(cmd);
+    genl_packet.set_version(version);
+    genl_packet.set_reserved();
+    genl_packet.payload_mut().copy_from_slice(attrs);
+
+    {
+        let pkt = NetlinkPacket::new_unchecked(&buffer[..nl_packet_len]);
+        trace!("try send netlink message:\n{}", pkt);
+        let genl_pkt = GenericPacket::new_unchecked(pkt.payload());
+        trace!("{}", genl_pkt);
+    }
+
+    Ok(nl_packet_len)
+}
+
+
+#[test]
+fn test_family_parse() {
+    // The reply of `CTRL_CMD_GETFAMILY` for `nlctrl`, after the `genlmsghdr` header.
+    let attrs = vec![
+        11, 0, 2, 0, 110, 108, 99, 116, 114, 108, 0, 0, 6, 0, 1, 0, 16, 0, 0, 0, 8, 0, 3, 0, 2, 0, 0, 0, 8, 0, 4, 0,
+        0, 0, 0, 0, 8, 0, 5, 0, 0, 0, 0, 0, 44, 0, 6, 0, 20, 0, 1, 0, 8, 0, 1, 0, 3, 0, 0, 0, 8, 0, 2, 0, 14, 0, 0, 0,
+        20, 0, 2, 0, 8, 0, 1, 0, 10, 0, 0, 0, 8, 0, 2, 0, 12, 0, 0, 0, 28, 0, 7, 0, 24, 0, 1, 0, 8, 0, 2, 0, 16, 0,
+        0, 0, 11, 0, 1, 0, 110, 111, 116, 105, 102, 121, 0, 0,
+    ];
+    let message = Message { family_id: packet::GENL_ID_CTRL.0, cmd: 1, version: 2, attrs };
+
+    let family = Family::try_from(&message).unwrap();
+    assert_eq!(family.id, packet::GENL_ID_CTRL.0);
+    assert_eq!(family.name, "nlctrl");
+    assert_eq!(family.version, 2);
+    assert_eq!(family.hdr_size, 0);
+    assert_eq!(family.ops, vec![Op { id: 3, flags: 14 }, Op { id: 10, flags: 12 }]);
+    assert_eq!(family.mcast_groups, vec![McastGroup { id: 16, name: "notify".to_string() }]);
+    assert_eq!(family.mcast_group("notify"), Some(16));
+    assert_eq!(family.mcast_group("config"), None);
+
+    // A truncated reply is an error.
+    let message = Message { attrs: message.attrs[..50].to_vec(), ..message };
+    assert!(Family::try_from(&message).is_err());
+}
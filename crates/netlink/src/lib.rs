@@ -5,8 +5,13 @@ extern crate libc;
 extern crate bitflags;
 extern crate byteorder;
 extern crate smoltcp;
+#[cfg(feature = "mio")]
+extern crate mio;
 
 
 pub mod packet;
 pub mod route;
+pub mod netfilter;
 pub mod socket;
+pub mod netns;
+pub mod generic;
@@ -0,0 +1,220 @@
+use crate::packet::NetlinkAttrBuilder;
+
+use super::ProtoFamily;
+
+
+const NFTA_EXPR_NAME: u16 = 1;
+const NFTA_EXPR_DATA: u16 = 2;
+
+const NFTA_DATA_VALUE: u16   = 1;
+const NFTA_DATA_VERDICT: u16 = 2;
+
+const NFTA_VERDICT_CODE: u16  = 1;
+const NFTA_VERDICT_CHAIN: u16 = 2;
+
+const NFTA_META_DREG: u16 = 1;
+const NFTA_META_KEY: u16  = 2;
+
+const NFTA_CMP_SREG: u16 = 1;
+const NFTA_CMP_OP: u16   = 2;
+const NFTA_CMP_DATA: u16 = 3;
+
+const NFTA_PAYLOAD_DREG: u16   = 1;
+const NFTA_PAYLOAD_BASE: u16   = 2;
+const NFTA_PAYLOAD_OFFSET: u16 = 3;
+const NFTA_PAYLOAD_LEN: u16    = 4;
+
+const NFTA_BITWISE_SREG: u16 = 1;
+const NFTA_BITWISE_DREG: u16 = 2;
+const NFTA_BITWISE_LEN: u16  = 3;
+const NFTA_BITWISE_MASK: u16 = 4;
+const NFTA_BITWISE_XOR: u16  = 5;
+
+const NFTA_IMMEDIATE_DREG: u16 = 1;
+const NFTA_IMMEDIATE_DATA: u16 = 2;
+
+const NFTA_NAT_TYPE: u16           = 1;
+const NFTA_NAT_FAMILY: u16         = 2;
+const NFTA_NAT_REG_ADDR_MIN: u16   = 3;
+const NFTA_NAT_REG_PROTO_MIN: u16  = 5;
+
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Register(pub u32);
+
+impl Register {
+    pub const NFT_REG_VERDICT: Self = Self(0);
+    // 16 bytes registers
+    pub const NFT_REG_1: Self = Self(1);
+    pub const NFT_REG_2: Self = Self(2);
+    pub const NFT_REG_3: Self = Self(3);
+    pub const NFT_REG_4: Self = Self(4);
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct MetaKey(pub u32);
+
+impl MetaKey {
+    pub const NFT_META_LEN: Self      = Self(0);
+    pub const NFT_META_PROTOCOL: Self = Self(1);
+    pub const NFT_META_PRIORITY: Self = Self(2);
+    pub const NFT_META_MARK: Self     = Self(3);
+    pub const NFT_META_IIF: Self      = Self(4);
+    pub const NFT_META_OIF: Self      = Self(5);
+    pub const NFT_META_IIFNAME: Self  = Self(6);
+    pub const NFT_META_OIFNAME: Self  = Self(7);
+    pub const NFT_META_NFPROTO: Self  = Self(15);
+    pub const NFT_META_L4PROTO: Self  = Self(16);
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct CmpOp(pub u32);
+
+impl CmpOp {
+    pub const NFT_CMP_EQ: Self  = Self(0);
+    pub const NFT_CMP_NEQ: Self = Self(1);
+    pub const NFT_CMP_LT: Self  = Self(2);
+    pub const NFT_CMP_LTE: Self = Self(3);
+    pub const NFT_CMP_GT: Self  = Self(4);
+    pub const NFT_CMP_GTE: Self = Self(5);
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct PayloadBase(pub u32);
+
+impl PayloadBase {
+    pub const NFT_PAYLOAD_LL_HEADER: Self        = Self(0);
+    pub const NFT_PAYLOAD_NETWORK_HEADER: Self   = Self(1);
+    pub const NFT_PAYLOAD_TRANSPORT_HEADER: Self = Self(2);
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct NatType(pub u32);
+
+impl NatType {
+    pub const NFT_NAT_SNAT: Self = Self(0);
+    pub const NFT_NAT_DNAT: Self = Self(1);
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Verdict {
+    Accept,
+    Drop,
+    Continue,
+    Return,
+    Jump(String),
+    Goto(String),
+}
+
+impl Verdict {
+    pub fn code(&self) -> i32 {
+        match *self {
+            Verdict::Drop => 0,        // NF_DROP
+            Verdict::Accept => 1,      // NF_ACCEPT
+            Verdict::Continue => -1,   // NFT_CONTINUE
+            Verdict::Jump(_) => -3,    // NFT_JUMP
+            Verdict::Goto(_) => -4,    // NFT_GOTO
+            Verdict::Return => -5,     // NFT_RETURN
+        }
+    }
+}
+
+
+/// nftables expressions, a rule is a list of expressions evaluated in order.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Expr {
+    /// Load packet meta information into the register.
+    Meta { key: MetaKey, dreg: Register },
+    /// Compare the register with the data, stops evaluating the rule if it does not match.
+    Cmp { sreg: Register, op: CmpOp, data: Vec<u8> },
+    /// Load `len` bytes at `offset` of the header into the register.
+    Payload { dreg: Register, base: PayloadBase, offset: u32, len: u32 },
+    /// `dreg = (sreg & mask) ^ xor`
+    Bitwise { sreg: Register, dreg: Register, mask: Vec<u8>, xor: Vec<u8> },
+    Immediate { dreg: Register, data: Vec<u8> },
+    Verdict(Verdict),
+    Counter,
+    Masquerade,
+    Nat { kind: NatType, family: ProtoFamily, addr_reg: Option<Register>, proto_reg: Option<Register> },
+}
+
+impl Expr {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Expr::Meta { .. } => "meta",
+            Expr::Cmp { .. } => "cmp",
+            Expr::Payload { .. } => "payload",
+            Expr::Bitwise { .. } => "bitwise",
+            Expr::Immediate { .. } | Expr::Verdict(_) => "immediate",
+            Expr::Counter => "counter",
+            Expr::Masquerade => "masq",
+            Expr::Nat { .. } => "nat",
+        }
+    }
+
+    /// Append the expression (NFTA_EXPR_NAME and NFTA_EXPR_DATA attributes) to the buffer.
+    pub fn emit<T: AsMut<Vec<u8>>>(&self, attrs: &mut NetlinkAttrBuilder<T>) {
+        attrs.put_strz(NFTA_EXPR_NAME, self.name());
+
+        let data = attrs.nest_start(NFTA_EXPR_DATA);
+        match *self {
+            Expr::Meta { key, dreg } => {
+                attrs.put_be32(NFTA_META_DREG, dreg.0);
+                attrs.put_be32(NFTA_META_KEY, key.0);
+            },
+            Expr::Cmp { sreg, op, ref data } => {
+                attrs.put_be32(NFTA_CMP_SREG, sreg.0);
+                attrs.put_be32(NFTA_CMP_OP, op.0);
+                put_data(attrs, NFTA_CMP_DATA, data);
+            },
+            Expr::Payload { dreg, base, offset, len } => {
+                attrs.put_be32(NFTA_PAYLOAD_DREG, dreg.0);
+                attrs.put_be32(NFTA_PAYLOAD_BASE, base.0);
+                attrs.put_be32(NFTA_PAYLOAD_OFFSET, offset);
+                attrs.put_be32(NFTA_PAYLOAD_LEN, len);
+            },
+            Expr::Bitwise { sreg, dreg, ref mask, ref xor } => {
+                debug_assert_eq!(mask.len(), xor.len());
+                attrs.put_be32(NFTA_BITWISE_SREG, sreg.0);
+                attrs.put_be32(NFTA_BITWISE_DREG, dreg.0);
+                attrs.put_be32(NFTA_BITWISE_LEN, mask.len() as u32);
+                put_data(attrs, NFTA_BITWISE_MASK, mask);
+                put_data(attrs, NFTA_BITWISE_XOR, xor);
+            },
+            Expr::Immediate { dreg, ref data } => {
+                attrs.put_be32(NFTA_IMMEDIATE_DREG, dreg.0);
+                put_data(attrs, NFTA_IMMEDIATE_DATA, data);
+            },
+            Expr::Verdict(ref verdict) => {
+                attrs.put_be32(NFTA_IMMEDIATE_DREG, Register::NFT_REG_VERDICT.0);
+
+                let immediate_data = attrs.nest_start(NFTA_IMMEDIATE_DATA);
+                let verdict_data = attrs.nest_start(NFTA_DATA_VERDICT);
+                attrs.put_be32(NFTA_VERDICT_CODE, verdict.code() as u32);
+                if let Verdict::Jump(chain) | Verdict::Goto(chain) = verdict {
+                    attrs.put_strz(NFTA_VERDICT_CHAIN, chain);
+                }
+                attrs.nest_end(verdict_data);
+                attrs.nest_end(immediate_data);
+            },
+            Expr::Counter | Expr::Masquerade => { },
+            Expr::Nat { kind, family, addr_reg, proto_reg } => {
+                attrs.put_be32(NFTA_NAT_TYPE, kind.0);
+                attrs.put_be32(NFTA_NAT_FAMILY, family.0 as u32);
+                if let Some(addr_reg) = addr_reg {
+                    attrs.put_be32(NFTA_NAT_REG_ADDR_MIN, addr_reg.0);
+                }
+                if let Some(proto_reg) = proto_reg {
+                    attrs.put_be32(NFTA_NAT_REG_PROTO_MIN, proto_reg.0);
+                }
+            },
+        }
+        attrs.nest_end(data);
+    }
+}
+
+fn put_data<T: AsMut<Vec<u8>>>(attrs: &mut NetlinkAttrBuilder<T>, kind: u16, data: &[u8]) {
+    let nest = attrs.nest_start(kind);
+    attrs.put(NFTA_DATA_VALUE, data);
+    attrs.nest_end(nest);
+}
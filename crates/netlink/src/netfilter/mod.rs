@@ -1,2 +1,372 @@
+// nftables over netlink (NETLINK_NETFILTER, nfnetlink subsystem NFNL_SUBSYS_NFTABLES)
+//
+// /usr/include/linux/netfilter/nfnetlink.h
+// /usr/include/linux/netfilter/nf_tables.h
+use crate::packet;
+use crate::packet::NetlinkAttrBuilder;
+use crate::socket::NetlinkSocket;
 
+use std::io;
 
+mod expr;
+mod rule;
+
+pub use self::expr::*;
+pub use self::rule::*;
+
+
+pub const NFNETLINK_V0: u8          = 0;
+pub const NFNL_SUBSYS_NFTABLES: u16 = 10;
+// NLMSG_MIN_TYPE
+pub const NFNL_MSG_BATCH_BEGIN: u16 = 0x10;
+pub const NFNL_MSG_BATCH_END: u16   = 0x11;
+
+pub const NFT_MSG_NEWTABLE: u16 = 0;
+pub const NFT_MSG_GETTABLE: u16 = 1;
+pub const NFT_MSG_DELTABLE: u16 = 2;
+pub const NFT_MSG_NEWCHAIN: u16 = 3;
+pub const NFT_MSG_GETCHAIN: u16 = 4;
+pub const NFT_MSG_DELCHAIN: u16 = 5;
+pub const NFT_MSG_NEWRULE: u16  = 6;
+pub const NFT_MSG_GETRULE: u16  = 7;
+pub const NFT_MSG_DELRULE: u16  = 8;
+
+const NFTA_TABLE_NAME: u16 = 1;
+
+const NFTA_CHAIN_TABLE: u16  = 1;
+const NFTA_CHAIN_NAME: u16   = 3;
+const NFTA_CHAIN_HOOK: u16   = 4;
+const NFTA_CHAIN_POLICY: u16 = 5;
+const NFTA_CHAIN_TYPE: u16   = 7;
+
+const NFTA_HOOK_HOOKNUM: u16  = 1;
+const NFTA_HOOK_PRIORITY: u16 = 2;
+
+const NFTA_RULE_TABLE: u16       = 1;
+const NFTA_RULE_CHAIN: u16       = 2;
+const NFTA_RULE_EXPRESSIONS: u16 = 4;
+
+const NFTA_LIST_ELEM: u16 = 1;
+
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct ProtoFamily(pub u8);
+
+impl ProtoFamily {
+    pub const NFPROTO_UNSPEC: Self = Self(0);
+    pub const NFPROTO_INET: Self   = Self(1);
+    pub const NFPROTO_IPV4: Self   = Self(2);
+    pub const NFPROTO_ARP: Self    = Self(3);
+    pub const NFPROTO_NETDEV: Self = Self(5);
+    pub const NFPROTO_BRIDGE: Self = Self(7);
+    pub const NFPROTO_IPV6: Self   = Self(10);
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Hook(pub u32);
+
+impl Hook {
+    pub const NF_INET_PRE_ROUTING: Self  = Self(0);
+    pub const NF_INET_LOCAL_IN: Self     = Self(1);
+    pub const NF_INET_FORWARD: Self      = Self(2);
+    pub const NF_INET_LOCAL_OUT: Self    = Self(3);
+    pub const NF_INET_POST_ROUTING: Self = Self(4);
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum ChainType {
+    Filter,
+    Nat,
+    Route,
+}
+
+impl ChainType {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ChainType::Filter => "filter",
+            ChainType::Nat => "nat",
+            ChainType::Route => "route",
+        }
+    }
+}
+
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Table {
+    pub family: ProtoFamily,
+    pub name: String,
+}
+
+impl Table {
+    pub fn new(family: ProtoFamily, name: &str) -> Self {
+        Self { family, name: name.to_string() }
+    }
+}
+
+/// A base chain when `hook` is set, a regular chain (only reachable by jump/goto) otherwise.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Chain {
+    pub table: Table,
+    pub name: String,
+    pub kind: ChainType,
+    // hook number and priority
+    pub hook: Option<(Hook, i32)>,
+    pub policy: Option<Verdict>,
+}
+
+impl Chain {
+    pub fn new(table: &Table, name: &str) -> Self {
+        Self {
+            table: table.clone(),
+            name: name.to_string(),
+            kind: ChainType::Filter,
+            hook: None,
+            policy: None,
+        }
+    }
+
+    pub fn base(table: &Table, name: &str, kind: ChainType, hook: Hook, priority: i32, policy: Verdict) -> Self {
+        Self {
+            table: table.clone(),
+            name: name.to_string(),
+            kind,
+            hook: Some((hook, priority)),
+            policy: Some(policy),
+        }
+    }
+}
+
+
+/// A list of nftables messages which the kernel applies atomically:
+/// either every message succeeds or none of them takes effect.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    buffer: Vec<u8>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        let mut batch = Self { buffer: Vec::with_capacity(4096) };
+        let start = batch.begin_message(NFNL_MSG_BATCH_BEGIN, packet::Flags::NLM_F_REQUEST, ProtoFamily::NFPROTO_UNSPEC, NFNL_SUBSYS_NFTABLES);
+        batch.end_message(start);
+        batch
+    }
+
+    fn begin_message(&mut self, kind: u16, flags: packet::Flags, family: ProtoFamily, res_id: u16) -> usize {
+        let start = self.buffer.len();
+        self.buffer.resize(start + packet::NetlinkPacket::<&[u8]>::MIN_SIZE, 0);
+
+        let mut nl_packet = packet::NetlinkPacket::new_unchecked(&mut self.buffer[start..]);
+        nl_packet.set_kind(packet::Kind(kind));
+        nl_packet.set_flags(flags);
+        // NOTE: Sequence numbers are assigned by the socket when the batch is sent.
+        nl_packet.set_seq(0);
+        nl_packet.set_pid(0);
+
+        // struct nfgenmsg
+        self.buffer.push(family.0);
+        self.buffer.push(NFNETLINK_V0);
+        self.buffer.extend_from_slice(&res_id.to_be_bytes());
+
+        start
+    }
+
+    fn begin_nftables_message(&mut self, msg: u16, flags: packet::Flags, family: ProtoFamily) -> usize {
+        self.begin_message((NFNL_SUBSYS_NFTABLES << 8) | msg, flags | packet::Flags::NLM_F_REQUEST | packet::Flags::NLM_F_ACK, family, 0)
+    }
+
+    fn end_message(&mut self, start: usize) {
+        let len = self.buffer.len() - start;
+        let mut nl_packet = packet::NetlinkPacket::new_unchecked(&mut self.buffer[start..]);
+        nl_packet.set_len(len as u32);
+    }
+
+    /// Create the table, does nothing if the table already exists.
+    pub fn add_table(&mut self, table: &Table) {
+        let start = self.begin_nftables_message(NFT_MSG_NEWTABLE, packet::Flags::NLM_F_CREATE, table.family);
+        NetlinkAttrBuilder::with_buffer(&mut self.buffer).put_strz(NFTA_TABLE_NAME, &table.name);
+        self.end_message(start);
+    }
+
+    /// Delete the table with all of its chains and rules.
+    pub fn delete_table(&mut self, table: &Table) {
+        let start = self.begin_nftables_message(NFT_MSG_DELTABLE, packet::Flags::empty(), table.family);
+        NetlinkAttrBuilder::with_buffer(&mut self.buffer).put_strz(NFTA_TABLE_NAME, &table.name);
+        self.end_message(start);
+    }
+
+    pub fn add_chain(&mut self, chain: &Chain) {
+        let start = self.begin_nftables_message(NFT_MSG_NEWCHAIN, packet::Flags::NLM_F_CREATE, chain.table.family);
+        let mut attrs = NetlinkAttrBuilder::with_buffer(&mut self.buffer);
+        attrs.put_strz(NFTA_CHAIN_TABLE, &chain.table.name);
+        attrs.put_strz(NFTA_CHAIN_NAME, &chain.name);
+
+        if let Some((hook, priority)) = chain.hook {
+            let nest = attrs.nest_start(NFTA_CHAIN_HOOK);
+            attrs.put_be32(NFTA_HOOK_HOOKNUM, hook.0);
+            attrs.put_be32(NFTA_HOOK_PRIORITY, priority as u32);
+            attrs.nest_end(nest);
+
+            attrs.put_strz(NFTA_CHAIN_TYPE, chain.kind.as_str());
+        }
+
+        if let Some(ref policy) = chain.policy {
+            attrs.put_be32(NFTA_CHAIN_POLICY, policy.code() as u32);
+        }
+
+        self.end_message(start);
+    }
+
+    /// Append the rule to the end of its chain.
+    pub fn add_rule(&mut self, rule: &Rule) {
+        let flags = packet::Flags::NLM_F_CREATE | packet::Flags::NLM_F_APPEND;
+        let start = self.begin_nftables_message(NFT_MSG_NEWRULE, flags, rule.table.family);
+        let mut attrs = NetlinkAttrBuilder::with_buffer(&mut self.buffer);
+        attrs.put_strz(NFTA_RULE_TABLE, &rule.table.name);
+        attrs.put_strz(NFTA_RULE_CHAIN, &rule.chain);
+
+        let exprs = attrs.nest_start(NFTA_RULE_EXPRESSIONS);
+        for expr in rule.exprs.iter() {
+            let elem = attrs.nest_start(NFTA_LIST_ELEM);
+            expr.emit(&mut attrs);
+            attrs.nest_end(elem);
+        }
+        attrs.nest_end(exprs);
+
+        self.end_message(start);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let start = self.begin_message(NFNL_MSG_BATCH_END, packet::Flags::NLM_F_REQUEST, ProtoFamily::NFPROTO_UNSPEC, NFNL_SUBSYS_NFTABLES);
+        self.end_message(start);
+
+        self.buffer
+    }
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+pub struct NetfilterController {
+    nl_socket: NetlinkSocket,
+}
+
+impl NetfilterController {
+    pub fn new() -> Result<Self, io::Error> {
+        let mut nl_socket = NetlinkSocket::new(packet::Protocol::NETLINK_NETFILTER.into())?;
+
+        let pid    = 0;
+        let groups = 0;
+        nl_socket.bind(pid, groups)?;
+
+        Ok(Self { nl_socket })
+    }
+
+    /// Send the batch and wait for the kernel to acknowledge every message in it.
+    pub fn execute(&mut self, batch: Batch, buffer: &mut [u8]) -> Result<(), io::Error> {
+        if unsafe { libc::getuid() != 0 } {
+            return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        }
+
+        let mut message = batch.finish();
+        let seqs = self.nl_socket.send_messages(&mut message)?;
+
+        self.nl_socket.wait_acks(seqs, buffer)
+    }
+}
+
+
+#[cfg(test)]
+fn test_messages(buffer: &[u8]) -> Vec<packet::NetlinkPacket<&[u8]>> {
+    let mut messages = Vec::new();
+    let mut offset = 0;
+    while offset < buffer.len() {
+        let nl_packet = packet::NetlinkPacket::new_checked(&buffer[offset..]).unwrap();
+        let len = nl_packet.total_len();
+        messages.push(packet::NetlinkPacket::new_checked(&buffer[offset..offset + len]).unwrap());
+        offset += len;
+    }
+    messages
+}
+
+#[test]
+fn test_batch_layout() {
+    let table = Table::new(ProtoFamily::NFPROTO_INET, "exodus");
+    let chain = Chain::base(&table, "output", ChainType::Filter, Hook::NF_INET_LOCAL_OUT, -10, Verdict::Accept);
+    let mut batch = Batch::default();
+    batch.add_table(&table);
+    batch.add_chain(&chain);
+    let buffer = batch.finish();
+
+    let messages = test_messages(&buffer);
+    let kinds = messages.iter().map(|pkt| pkt.kind().0).collect::<Vec<u16>>();
+    assert_eq!(kinds, vec![
+        NFNL_MSG_BATCH_BEGIN,
+        (NFNL_SUBSYS_NFTABLES << 8) | NFT_MSG_NEWTABLE,
+        (NFNL_SUBSYS_NFTABLES << 8) | NFT_MSG_NEWCHAIN,
+        NFNL_MSG_BATCH_END,
+    ]);
+
+    for pkt in messages.iter() {
+        // NOTE: Sequence numbers are assigned by the socket.
+        assert_eq!(pkt.seq(), 0);
+        assert_eq!(pkt.payload()[1], NFNETLINK_V0);
+    }
+
+    // BEGIN and END carry the subsystem in `res_id` (network byte order), and are not acknowledged.
+    for pkt in [&messages[0], &messages[3]].iter() {
+        assert_eq!(pkt.flags(), packet::Flags::NLM_F_REQUEST);
+        assert_eq!(pkt.payload(), &[ProtoFamily::NFPROTO_UNSPEC.0, NFNETLINK_V0, 0, NFNL_SUBSYS_NFTABLES as u8]);
+    }
+    assert!(messages[1].flags().contains(packet::Flags::NLM_F_REQUEST | packet::Flags::NLM_F_ACK | packet::Flags::NLM_F_CREATE));
+    assert_eq!(messages[1].payload()[0], ProtoFamily::NFPROTO_INET.0);
+
+    let attr = packet::NetlinkAttrs::new(&messages[1].payload()[4..]).next().unwrap().unwrap();
+    assert_eq!(attr.kind(), NFTA_TABLE_NAME);
+    // NOTE: The payload is padded to `NLA_ALIGNTO`.
+    assert_eq!(attr.payload(), b"exodus\0\0");
+
+    let attrs = packet::NetlinkAttrs::new(&messages[2].payload()[4..]).collect::<Result<Vec<_>, _>>().unwrap();
+    let kinds = attrs.iter().map(|attr| attr.attr_type()).collect::<Vec<u16>>();
+    assert_eq!(kinds, vec![NFTA_CHAIN_TABLE, NFTA_CHAIN_NAME, NFTA_CHAIN_HOOK, NFTA_CHAIN_TYPE, NFTA_CHAIN_POLICY]);
+    assert!(attrs[2].is_nested());
+    let hook = packet::NetlinkAttrs::new(attrs[2].payload()).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(hook[0].attr_type(), NFTA_HOOK_HOOKNUM);
+    assert_eq!(hook[0].payload(), &Hook::NF_INET_LOCAL_OUT.0.to_be_bytes());
+    assert_eq!(hook[1].attr_type(), NFTA_HOOK_PRIORITY);
+    assert_eq!(hook[1].payload(), &(-10i32).to_be_bytes());
+    assert_eq!(attrs[3].payload(), b"filter\0\0");
+    assert_eq!(attrs[4].payload(), &(Verdict::Accept.code() as u32).to_be_bytes());
+}
+
+#[test]
+fn test_batch_seq() {
+    let mut nl_socket = match NetlinkSocket::new(packet::Protocol::NETLINK_NETFILTER.into()) {
+        Ok(nl_socket) => nl_socket,
+        // nfnetlink is not available
+        Err(_) => return,
+    };
+    nl_socket.bind(0, 0).unwrap();
+
+    // NOTE: Deleting a table which does not exist aborts the whole batch, nothing is changed.
+    let table = Table::new(ProtoFamily::NFPROTO_INET, "exodus-test-not-exists");
+    let mut batch = Batch::new();
+    batch.delete_table(&table);
+    batch.delete_table(&table);
+    let mut buffer = batch.finish();
+
+    let seqs = nl_socket.send_messages(&mut buffer).unwrap();
+    let messages = test_messages(&buffer);
+    let first = messages[0].seq();
+    let msg_seqs = messages.iter().map(|pkt| pkt.seq()).collect::<Vec<u32>>();
+    assert_eq!(msg_seqs, vec![first, first + 1, first + 2, first + 3]);
+    // Only the messages between BEGIN and END request an ACK.
+    assert_eq!(seqs, vec![first + 1, first + 2]);
+
+    let mut reply = packet::alloc();
+    assert!(nl_socket.wait_acks(seqs, &mut reply).is_err());
+}
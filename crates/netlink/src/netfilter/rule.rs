@@ -0,0 +1,167 @@
+use super::{ Table, ProtoFamily, Expr, Verdict, Register, MetaKey, CmpOp, PayloadBase, NatType, };
+
+use smoltcp::wire::IpCidr;
+
+use std::net::IpAddr;
+
+
+const IFNAMSIZ: usize = 16;
+
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+
+
+fn prefix_mask(len: usize, prefix_len: u8) -> Vec<u8> {
+    let mut mask = vec![0u8; len];
+    for i in 0..prefix_len as usize {
+        mask[i / 8] |= 0x80 >> (i % 8);
+    }
+
+    mask
+}
+
+fn ifname_data(ifname: &str) -> Vec<u8> {
+    let mut data = vec![0u8; IFNAMSIZ];
+    let len = std::cmp::min(ifname.len(), IFNAMSIZ - 1);
+    data[..len].copy_from_slice(&ifname.as_bytes()[..len]);
+
+    data
+}
+
+fn addr_data(addr: IpAddr) -> (ProtoFamily, Vec<u8>) {
+    match addr {
+        IpAddr::V4(addr) => (ProtoFamily::NFPROTO_IPV4, addr.octets().to_vec()),
+        IpAddr::V6(addr) => (ProtoFamily::NFPROTO_IPV6, addr.octets().to_vec()),
+    }
+}
+
+
+/// A rule in the chain, built by chaining the matches and finishing with a statement:
+///
+/// ```no_run
+/// use netlink::netfilter::{ Table, Rule, ProtoFamily, };
+///
+/// let table = Table::new(ProtoFamily::NFPROTO_INET, "exodus");
+/// // nft add rule inet exodus forward iifname "utun9" accept
+/// let rule = Rule::new(&table, "forward").iifname("utun9").accept();
+/// ```
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Rule {
+    pub table: Table,
+    pub chain: String,
+    pub exprs: Vec<Expr>,
+}
+
+impl Rule {
+    pub fn new(table: &Table, chain: &str) -> Self {
+        Self { table: table.clone(), chain: chain.to_string(), exprs: Vec::new() }
+    }
+
+    pub fn push(mut self, expr: Expr) -> Self {
+        self.exprs.push(expr);
+        self
+    }
+
+    fn match_meta(self, key: MetaKey, data: Vec<u8>) -> Self {
+        self.push(Expr::Meta { key, dreg: Register::NFT_REG_1 })
+            .push(Expr::Cmp { sreg: Register::NFT_REG_1, op: CmpOp::NFT_CMP_EQ, data })
+    }
+
+    /// Match the layer 3 protocol, required before matching on the network header in `inet` tables.
+    pub fn nfproto(self, family: ProtoFamily) -> Self {
+        self.match_meta(MetaKey::NFT_META_NFPROTO, vec![family.0])
+    }
+
+    pub fn iifname(self, ifname: &str) -> Self {
+        self.match_meta(MetaKey::NFT_META_IIFNAME, ifname_data(ifname))
+    }
+
+    pub fn oifname(self, ifname: &str) -> Self {
+        self.match_meta(MetaKey::NFT_META_OIFNAME, ifname_data(ifname))
+    }
+
+    fn match_addr(self, cidr: &IpCidr, is_source: bool) -> Self {
+        let (family, offset, addr) = match cidr {
+            IpCidr::Ipv4(cidr) => (ProtoFamily::NFPROTO_IPV4, if is_source { 12 } else { 16 }, cidr.address().0.to_vec()),
+            IpCidr::Ipv6(cidr) => (ProtoFamily::NFPROTO_IPV6, if is_source { 8 } else { 24 }, cidr.address().0.to_vec()),
+            _ => unreachable!(),
+        };
+        let len = addr.len();
+        let prefix_len = cidr.prefix_len();
+
+        let mut rule = self.nfproto(family)
+            .push(Expr::Payload { dreg: Register::NFT_REG_1, base: PayloadBase::NFT_PAYLOAD_NETWORK_HEADER, offset, len: len as u32 });
+
+        let mut network = addr;
+        if (prefix_len as usize) < len * 8 {
+            let mask = prefix_mask(len, prefix_len);
+            for (x, m) in network.iter_mut().zip(mask.iter()) {
+                *x &= *m;
+            }
+            rule = rule.push(Expr::Bitwise { sreg: Register::NFT_REG_1, dreg: Register::NFT_REG_1, mask, xor: vec![0u8; len] });
+        }
+
+        rule.push(Expr::Cmp { sreg: Register::NFT_REG_1, op: CmpOp::NFT_CMP_EQ, data: network })
+    }
+
+    pub fn saddr(self, cidr: &IpCidr) -> Self {
+        self.match_addr(cidr, true)
+    }
+
+    pub fn daddr(self, cidr: &IpCidr) -> Self {
+        self.match_addr(cidr, false)
+    }
+
+    /// Match the transport protocol (`IPPROTO_TCP`, `IPPROTO_UDP`, ...) and the destination port.
+    pub fn dport(self, protocol: u8, port: u16) -> Self {
+        self.match_meta(MetaKey::NFT_META_L4PROTO, vec![protocol])
+            .push(Expr::Payload { dreg: Register::NFT_REG_1, base: PayloadBase::NFT_PAYLOAD_TRANSPORT_HEADER, offset: 2, len: 2 })
+            .push(Expr::Cmp { sreg: Register::NFT_REG_1, op: CmpOp::NFT_CMP_EQ, data: port.to_be_bytes().to_vec() })
+    }
+
+    pub fn counter(self) -> Self {
+        self.push(Expr::Counter)
+    }
+
+    pub fn verdict(self, verdict: Verdict) -> Self {
+        self.push(Expr::Verdict(verdict))
+    }
+
+    pub fn accept(self) -> Self {
+        self.verdict(Verdict::Accept)
+    }
+
+    pub fn drop(self) -> Self {
+        self.verdict(Verdict::Drop)
+    }
+
+    /// Only valid in `nat` chains hooked at postrouting.
+    pub fn masquerade(self) -> Self {
+        self.push(Expr::Masquerade)
+    }
+
+    /// Only valid in `nat` chains hooked at postrouting.
+    pub fn snat(self, addr: IpAddr) -> Self {
+        let (family, data) = addr_data(addr);
+        self.nfproto(family)
+            .push(Expr::Immediate { dreg: Register::NFT_REG_1, data })
+            .push(Expr::Nat { kind: NatType::NFT_NAT_SNAT, family, addr_reg: Some(Register::NFT_REG_1), proto_reg: None })
+    }
+
+    /// Only valid in `nat` chains hooked at prerouting (or output).
+    pub fn dnat(self, addr: IpAddr, port: Option<u16>) -> Self {
+        let (family, data) = addr_data(addr);
+        let mut rule = self.nfproto(family)
+            .push(Expr::Immediate { dreg: Register::NFT_REG_1, data });
+
+        let proto_reg = match port {
+            Some(port) => {
+                rule = rule.push(Expr::Immediate { dreg: Register::NFT_REG_2, data: port.to_be_bytes().to_vec() });
+                Some(Register::NFT_REG_2)
+            },
+            None => None,
+        };
+
+        rule.push(Expr::Nat { kind: NatType::NFT_NAT_DNAT, family, addr_reg: Some(Register::NFT_REG_1), proto_reg })
+    }
+}
@@ -0,0 +1,199 @@
+// Network namespaces (`ip netns`)
+//
+// A named network namespace is a bind mount of `/proc/<tid>/ns/net` under `/run/netns`,
+// which keeps the namespace alive after the thread which created it exits.
+use std::io;
+use std::fs::{self, File, OpenOptions};
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+
+pub const NETNS_RUN_DIR: &str = "/run/netns";
+
+
+/// The network namespace a link is moved into, see `RouteController::set_link_netns`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Target {
+    /// A file descriptor which refers to the namespace (`IFLA_NET_NS_FD`).
+    Fd(RawFd),
+    /// The namespace of the process (`IFLA_NET_NS_PID`).
+    Pid(u32),
+}
+
+
+/// A handle to a network namespace, the namespace is alive as long as the handle is open.
+#[derive(Debug)]
+pub struct NetNs {
+    file: File,
+}
+
+impl NetNs {
+    /// Open the named network namespace (`/run/netns/<name>`).
+    pub fn open(name: &str) -> Result<Self, io::Error> {
+        Self::open_path(path(name)?)
+    }
+
+    pub fn open_path<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let file = File::open(path)?;
+        Ok(Self { file })
+    }
+
+    /// The network namespace of the calling thread.
+    pub fn current() -> Result<Self, io::Error> {
+        Self::open_path(thread_ns_path())
+    }
+
+    /// The network namespace of the process.
+    pub fn from_pid(pid: u32) -> Result<Self, io::Error> {
+        Self::open_path(format!("/proc/{}/ns/net", pid))
+    }
+
+    /// Move the calling thread into the network namespace (`ip netns exec`).
+    ///
+    /// NOTE: Only the calling thread is moved, sockets keep the namespace they were created in.
+    pub fn enter(&self) -> Result<(), io::Error> {
+        if unsafe { libc::setns(self.file.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Run the closure inside the network namespace, then move the calling thread back.
+    pub fn run<F, T>(&self, f: F) -> Result<T, io::Error> where F: FnOnce() -> T {
+        let origin = Self::current()?;
+        self.enter()?;
+
+        let ret = f();
+
+        if let Err(e) = origin.enter() {
+            // NOTE: The thread would keep doing everything in the wrong namespace.
+            panic!("Failed to restore the network namespace of the thread: {:?}", e);
+        }
+
+        Ok(ret)
+    }
+
+    pub fn target(&self) -> Target {
+        Target::Fd(self.file.as_raw_fd())
+    }
+}
+
+impl AsRawFd for NetNs {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+
+fn path(name: &str) -> Result<PathBuf, io::Error> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid network namespace name"));
+    }
+
+    Ok(Path::new(NETNS_RUN_DIR).join(name))
+}
+
+fn thread_ns_path() -> String {
+    // NOTE: `/proc/self` refers to the main thread, `/proc/thread-self` requires Linux 3.17.
+    let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+    format!("/proc/self/task/{}/ns/net", tid)
+}
+
+fn mount(src: &Path, dst: &Path, flags: libc::c_ulong) -> Result<(), io::Error> {
+    let src = CString::new(src.as_os_str().as_bytes())?;
+    let dst = CString::new(dst.as_os_str().as_bytes())?;
+    let fstype = b"none\0";
+
+    let ret = unsafe {
+        libc::mount(src.as_ptr(), dst.as_ptr(), fstype.as_ptr() as *const _, flags, std::ptr::null())
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+// Make the mounts under `/run/netns` propagate to the other mount namespaces,
+// otherwise a namespace created after `unshare -m` can not be deleted from outside.
+fn make_run_dir_shared() -> Result<(), io::Error> {
+    let run_dir = Path::new(NETNS_RUN_DIR);
+    fs::create_dir_all(run_dir)?;
+
+    match mount(Path::new(""), run_dir, libc::MS_SHARED | libc::MS_REC) {
+        Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) => {
+            // NOTE: `/run/netns` is not a mount point yet.
+            mount(run_dir, run_dir, libc::MS_BIND | libc::MS_REC)?;
+            mount(Path::new(""), run_dir, libc::MS_SHARED | libc::MS_REC)
+        },
+        ret => ret,
+    }
+}
+
+/// Names of the network namespaces under `/run/netns` (`ip netns list`).
+pub fn list() -> Result<Vec<String>, io::Error> {
+    let entries = match fs::read_dir(NETNS_RUN_DIR) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        names.push(entry?.file_name().to_string_lossy().to_string());
+    }
+    names.sort();
+
+    Ok(names)
+}
+
+/// Create a named network namespace (`ip netns add <name>`), the calling thread stays where it is.
+pub fn create(name: &str) -> Result<NetNs, io::Error> {
+    if unsafe { libc::getuid() != 0 } {
+        return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+    }
+
+    let netns_path = path(name)?;
+    make_run_dir_shared()?;
+
+    OpenOptions::new().read(true).write(true).create_new(true).mode(0o0).open(&netns_path)?;
+
+    // NOTE: `unshare` moves the calling thread, do it in a thread which exits right after.
+    let dst = netns_path.clone();
+    let ret = std::thread::spawn(move || -> Result<(), io::Error> {
+        if unsafe { libc::unshare(libc::CLONE_NEWNET) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        mount(Path::new(&thread_ns_path()), &dst, libc::MS_BIND)
+    }).join().unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "thread panicked")));
+
+    if let Err(e) = ret {
+        let _ = fs::remove_file(&netns_path);
+        return Err(e);
+    }
+
+    NetNs::open_path(&netns_path)
+}
+
+/// Delete a named network namespace (`ip netns del <name>`).
+///
+/// NOTE: The namespace itself is destroyed once the last process and handle in it are gone.
+pub fn delete(name: &str) -> Result<(), io::Error> {
+    let netns_path = path(name)?;
+    let dst = CString::new(netns_path.as_os_str().as_bytes())?;
+
+    if unsafe { libc::umount2(dst.as_ptr(), libc::MNT_DETACH) } < 0 {
+        let e = io::Error::last_os_error();
+        // NOTE: EINVAL: not a mount point, ENOENT: does not exist.
+        if e.raw_os_error() != Some(libc::EINVAL) {
+            return Err(e);
+        }
+    }
+
+    fs::remove_file(&netns_path)
+}
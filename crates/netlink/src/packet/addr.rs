@@ -67,6 +67,12 @@ impl AddrAttrType {
     pub const IFA_TARGET_NETNSID: Self = Self(10);
 }
 
+impl Into<u16> for AddrAttrType {
+    fn into(self) -> u16 {
+        self.0
+    }
+}
+
 impl std::fmt::Debug for AddrAttrType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match *self {
@@ -0,0 +1,235 @@
+// https://github.com/torvalds/linux/blob/master/include/uapi/linux/genetlink.h
+
+use crate::packet::Kind;
+
+use std::io;
+
+
+// The `nlmsg_type` of the generic netlink controller, other families get their IDs from it.
+pub const GENL_ID_CTRL: Kind = Kind(0x10);
+
+pub const GENL_NAMSIZ: usize = 16;    // length of family name
+
+// CTRL_ATTR_OPS
+pub const CTRL_ATTR_OP_UNSPEC: u16 = 0;
+pub const CTRL_ATTR_OP_ID: u16     = 1;
+pub const CTRL_ATTR_OP_FLAGS: u16  = 2;
+
+// CTRL_ATTR_MCAST_GROUPS
+pub const CTRL_ATTR_MCAST_GRP_UNSPEC: u16 = 0;
+pub const CTRL_ATTR_MCAST_GRP_NAME: u16   = 1;
+pub const CTRL_ATTR_MCAST_GRP_ID: u16     = 2;
+
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct genlmsghdr {
+    pub cmd: u8,
+    pub version: u8,
+    pub reserved: u16,
+}
+
+impl Default for genlmsghdr {
+    fn default() -> Self {
+        Self {
+            cmd: 0,
+            version: 0,
+            reserved: 0,
+        }
+    }
+}
+
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct CtrlCmd(pub u8);
+
+impl CtrlCmd {
+    pub const CTRL_CMD_UNSPEC: Self       = Self(0);
+    pub const CTRL_CMD_NEWFAMILY: Self    = Self(1);
+    pub const CTRL_CMD_DELFAMILY: Self    = Self(2);
+    pub const CTRL_CMD_GETFAMILY: Self    = Self(3);
+    pub const CTRL_CMD_NEWOPS: Self       = Self(4);
+    pub const CTRL_CMD_DELOPS: Self       = Self(5);
+    pub const CTRL_CMD_GETOPS: Self       = Self(6);
+    pub const CTRL_CMD_NEWMCAST_GRP: Self = Self(7);
+    pub const CTRL_CMD_DELMCAST_GRP: Self = Self(8);
+    pub const CTRL_CMD_GETMCAST_GRP: Self = Self(9); // unused
+    pub const CTRL_CMD_GETPOLICY: Self    = Self(10);
+}
+
+impl Into<u8> for CtrlCmd {
+    fn into(self) -> u8 {
+        self.0
+    }
+}
+
+impl std::fmt::Debug for CtrlCmd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::CTRL_CMD_UNSPEC => write!(f, "CTRL_CMD_UNSPEC"),
+            Self::CTRL_CMD_NEWFAMILY => write!(f, "CTRL_CMD_NEWFAMILY"),
+            Self::CTRL_CMD_DELFAMILY => write!(f, "CTRL_CMD_DELFAMILY"),
+            Self::CTRL_CMD_GETFAMILY => write!(f, "CTRL_CMD_GETFAMILY"),
+            Self::CTRL_CMD_NEWOPS => write!(f, "CTRL_CMD_NEWOPS"),
+            Self::CTRL_CMD_DELOPS => write!(f, "CTRL_CMD_DELOPS"),
+            Self::CTRL_CMD_GETOPS => write!(f, "CTRL_CMD_GETOPS"),
+            Self::CTRL_CMD_NEWMCAST_GRP => write!(f, "CTRL_CMD_NEWMCAST_GRP"),
+            Self::CTRL_CMD_DELMCAST_GRP => write!(f, "CTRL_CMD_DELMCAST_GRP"),
+            Self::CTRL_CMD_GETMCAST_GRP => write!(f, "CTRL_CMD_GETMCAST_GRP"),
+            Self::CTRL_CMD_GETPOLICY => write!(f, "CTRL_CMD_GETPOLICY"),
+            _ => write!(f, "CTRL_CMD_UNKNOW({})", self.0),
+        }
+    }
+}
+
+impl std::fmt::Display for CtrlCmd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct CtrlAttrType(pub u16);
+
+impl CtrlAttrType {
+    pub const CTRL_ATTR_UNSPEC: Self       = Self(0);
+    pub const CTRL_ATTR_FAMILY_ID: Self    = Self(1);
+    pub const CTRL_ATTR_FAMILY_NAME: Self  = Self(2);
+    pub const CTRL_ATTR_VERSION: Self      = Self(3);
+    pub const CTRL_ATTR_HDRSIZE: Self      = Self(4);
+    pub const CTRL_ATTR_MAXATTR: Self      = Self(5);
+    pub const CTRL_ATTR_OPS: Self          = Self(6);
+    pub const CTRL_ATTR_MCAST_GROUPS: Self = Self(7);
+    pub const CTRL_ATTR_POLICY: Self       = Self(8);
+    pub const CTRL_ATTR_OP_POLICY: Self    = Self(9);
+    pub const CTRL_ATTR_OP: Self           = Self(10);
+}
+
+impl Into<u16> for CtrlAttrType {
+    fn into(self) -> u16 {
+        self.0
+    }
+}
+
+impl std::fmt::Debug for CtrlAttrType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::CTRL_ATTR_UNSPEC => write!(f, "CTRL_ATTR_UNSPEC"),
+            Self::CTRL_ATTR_FAMILY_ID => write!(f, "CTRL_ATTR_FAMILY_ID"),
+            Self::CTRL_ATTR_FAMILY_NAME => write!(f, "CTRL_ATTR_FAMILY_NAME"),
+            Self::CTRL_ATTR_VERSION => write!(f, "CTRL_ATTR_VERSION"),
+            Self::CTRL_ATTR_HDRSIZE => write!(f, "CTRL_ATTR_HDRSIZE"),
+            Self::CTRL_ATTR_MAXATTR => write!(f, "CTRL_ATTR_MAXATTR"),
+            Self::CTRL_ATTR_OPS => write!(f, "CTRL_ATTR_OPS"),
+            Self::CTRL_ATTR_MCAST_GROUPS => write!(f, "CTRL_ATTR_MCAST_GROUPS"),
+            Self::CTRL_ATTR_POLICY => write!(f, "CTRL_ATTR_POLICY"),
+            Self::CTRL_ATTR_OP_POLICY => write!(f, "CTRL_ATTR_OP_POLICY"),
+            Self::CTRL_ATTR_OP => write!(f, "CTRL_ATTR_OP"),
+            _ => write!(f, "CTRL_ATTR_UNKNOW({})", self.0),
+        }
+    }
+}
+
+impl std::fmt::Display for CtrlAttrType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+
+const CMD: usize            = 0;
+const VERSION: usize        = 1;
+
+const PAYLOAD: usize        = 4;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct GenericPacket<T: AsRef<[u8]>> {
+    buffer: T
+}
+
+impl<T: AsRef<[u8]>> GenericPacket<T> {
+    pub const MIN_SIZE: usize = 4;
+
+    #[inline]
+    pub fn new_unchecked(buffer: T) -> GenericPacket<T> {
+        GenericPacket { buffer }
+    }
+
+    #[inline]
+    pub fn new_checked(buffer: T) -> Result<GenericPacket<T>, io::Error> {
+        let v = Self::new_unchecked(buffer);
+        v.check_len()?;
+
+        Ok(v)
+    }
+
+    #[inline]
+    pub fn check_len(&self) -> Result<(), io::Error> {
+        let data = self.buffer.as_ref();
+        if data.len() < Self::MIN_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "packet is too small."));
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
+    #[inline]
+    pub fn cmd(&self) -> u8 {
+        let data = self.buffer.as_ref();
+        data[CMD]
+    }
+
+    #[inline]
+    pub fn version(&self) -> u8 {
+        let data = self.buffer.as_ref();
+        data[VERSION]
+    }
+}
+
+impl<'a, T: AsRef<[u8]> + ?Sized> GenericPacket<&'a T> {
+    #[inline]
+    pub fn payload(&self) -> &'a [u8] {
+        let data = self.buffer.as_ref();
+        &data[PAYLOAD..]
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> GenericPacket<T> {
+    #[inline]
+    pub fn set_cmd(&mut self, value: u8) {
+        let data = self.buffer.as_mut();
+        data[CMD] = value;
+    }
+
+    #[inline]
+    pub fn set_version(&mut self, value: u8) {
+        let data = self.buffer.as_mut();
+        data[VERSION] = value;
+    }
+
+    #[inline]
+    pub fn set_reserved(&mut self) {
+        let data = self.buffer.as_mut();
+        data[2] = 0;
+        data[3] = 0;
+    }
+
+    #[inline]
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let data = self.buffer.as_mut();
+        &mut data[PAYLOAD..]
+    }
+}
+
+impl<'a, T: AsRef<[u8]> + ?Sized> std::fmt::Display for GenericPacket<&'a T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "GenericPacket {{ cmd: {}, version: {} }}",
+                self.cmd(),
+                self.version())
+    }
+}
@@ -43,10 +43,10 @@ pub struct LinkName {
 
 impl LinkName {
     pub fn new(data: [u8; IF_NAMESIZE as usize], len: usize) -> Self {
-        if data[len - 1] == 0 && data[len - 2] == 0 {
-            Self { data, len: len - 1, }
-        } else {
-            Self { data, len, }
+        // NOTE: The attribute payload is padded to `NLA_ALIGNTO`, the name ends at the first NUL.
+        match data[..len].iter().position(|b| *b == 0) {
+            Some(pos) => Self { data, len: pos + 1, },
+            None => Self { data, len, },
         }
     }
 }
@@ -377,6 +377,51 @@ impl Into<u16> for LinkAttrType {
     }
 }
 
+// Nested in `IFLA_LINKINFO`
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct LinkInfoAttrType(pub u16);
+
+impl LinkInfoAttrType {
+    pub const IFLA_INFO_UNSPEC: Self     = Self(0);
+    pub const IFLA_INFO_KIND: Self       = Self(1); // "veth", "bridge", "vlan", "dummy", ...
+    pub const IFLA_INFO_DATA: Self       = Self(2); // Kind specific attrs
+    pub const IFLA_INFO_XSTATS: Self     = Self(3);
+    pub const IFLA_INFO_SLAVE_KIND: Self = Self(4);
+    pub const IFLA_INFO_SLAVE_DATA: Self = Self(5);
+}
+
+impl std::fmt::Debug for LinkInfoAttrType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::IFLA_INFO_UNSPEC => write!(f, "IFLA_INFO_UNSPEC"),
+            Self::IFLA_INFO_KIND => write!(f, "IFLA_INFO_KIND"),
+            Self::IFLA_INFO_DATA => write!(f, "IFLA_INFO_DATA"),
+            Self::IFLA_INFO_XSTATS => write!(f, "IFLA_INFO_XSTATS"),
+            Self::IFLA_INFO_SLAVE_KIND => write!(f, "IFLA_INFO_SLAVE_KIND"),
+            Self::IFLA_INFO_SLAVE_DATA => write!(f, "IFLA_INFO_SLAVE_DATA"),
+            _ => write!(f, "IFLA_INFO_UNKNOW({})", self.0),
+        }
+    }
+}
+
+impl std::fmt::Display for LinkInfoAttrType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Into<u16> for LinkInfoAttrType {
+    fn into(self) -> u16 {
+        self.0
+    }
+}
+
+// Nested in `IFLA_INFO_DATA` of veth links, carries an `ifinfomsg` and the attrs of the peer.
+pub const VETH_INFO_PEER: u16     = 1;
+// Nested in `IFLA_INFO_DATA` of vlan links.
+pub const IFLA_VLAN_ID: u16       = 1;
+pub const IFLA_VLAN_PROTOCOL: u16 = 5;
+
 
 const FAMILY: usize         = 0;
 const KIND: Range<usize>    = 2..4;
@@ -4,12 +4,16 @@ mod neighbour;
 mod route;
 mod link;
 mod addr;
+mod rule;
+mod generic;
 
 pub use self::netlink::*;
 pub use self::neighbour::*;
 pub use self::route::*;
 pub use self::link::*;
 pub use self::addr::*;
+pub use self::rule::*;
+pub use self::generic::*;
 
 
 /// Max supported message length for netlink messages supported by the kernel
@@ -88,6 +92,8 @@ impl_as_ref_for_struct!(nl_mmap_hdr);
 impl_as_ref_for_struct!(nl_mmap_req);
 impl_as_ref_for_struct!(nlmsghdr);
 impl_as_ref_for_struct!(rtmsg);
+impl_as_ref_for_struct!(fib_rule_hdr);
+impl_as_ref_for_struct!(genlmsghdr);
         
 impl<H: Sized, P: Sized> AsRef<[u8]> for nlmsg<H, P> {
     fn as_ref(&self) -> &[u8] {
@@ -252,6 +252,12 @@ impl NeighbourAttrType {
     pub const NDA_PROTOCOL: Self     = Self(12); // Originator of entry
 }
 
+impl Into<u16> for NeighbourAttrType {
+    fn into(self) -> u16 {
+        self.0
+    }
+}
+
 impl std::fmt::Debug for NeighbourAttrType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match *self {
@@ -3,6 +3,7 @@ use super::align;
 use byteorder::{ByteOrder, NativeEndian};
 
 use std::io;
+use std::net::IpAddr;
 use core::ops::Range;
 
 // https://tools.ietf.org/html/rfc3549#section-2.2
@@ -112,6 +113,7 @@ pub struct Protocol(pub i32);
 impl Protocol {
     pub const NETLINK_ROUTE: Self     = Self(0);
     pub const NETLINK_NETFILTER: Self = Self(12);
+    pub const NETLINK_GENERIC: Self   = Self(16);
 }
 
 impl std::fmt::Debug for Protocol {
@@ -119,6 +121,7 @@ impl std::fmt::Debug for Protocol {
         match *self {
             Self::NETLINK_ROUTE => write!(f, "NETLINK_ROUTE"),
             Self::NETLINK_NETFILTER => write!(f, "NETLINK_NETFILTER"),
+            Self::NETLINK_GENERIC => write!(f, "NETLINK_GENERIC"),
             _ => write!(f, "NETLINK_PROTOCOL_UNKNOW({})", self.0),
         }
     }
@@ -260,6 +263,9 @@ bitflags! {
         const NLM_F_EXCL    = 0x200;   // Do not touch, if it exists
         const NLM_F_CREATE  = 0x400;   // Create, if it does not 
         const NLM_F_APPEND  = 0x800;   // Add to end of list
+        // Flags for ACK message
+        const NLM_F_CAPPED   = 0x100;  // request was capped
+        const NLM_F_ACK_TLVS = 0x200;  // extended ACK TVLs were included
     }
 }
 
@@ -270,6 +276,32 @@ impl Into<u16> for Flags {
 }
 
 
+bitflags! {
+    // RTnetlink multicast groups (legacy bitmask), used as `nl_groups` when binding the socket.
+    pub struct MulticastGroups: u32 {
+        const RTMGRP_LINK        = 0x1;
+        const RTMGRP_NOTIFY      = 0x2;
+        const RTMGRP_NEIGH       = 0x4;
+        const RTMGRP_TC          = 0x8;
+        const RTMGRP_IPV4_IFADDR = 0x10;
+        const RTMGRP_IPV4_MROUTE = 0x20;
+        const RTMGRP_IPV4_ROUTE  = 0x40;
+        const RTMGRP_IPV4_RULE   = 0x80;
+        const RTMGRP_IPV6_IFADDR = 0x100;
+        const RTMGRP_IPV6_MROUTE = 0x200;
+        const RTMGRP_IPV6_ROUTE  = 0x400;
+        const RTMGRP_IPV6_IFINFO = 0x800;
+        const RTMGRP_IPV6_PREFIX = 0x20000;
+    }
+}
+
+impl Into<u32> for MulticastGroups {
+    fn into(self) -> u32 {
+        self.bits()
+    }
+}
+
+
 const LEN:     Range<usize> = 0..4;
 const KIND:    Range<usize> = 4..6;
 const FLAGS:   Range<usize> = 6..8;
@@ -467,10 +499,70 @@ impl<T: AsRef<[u8]>> NetlinkErrorPacket<T> {
 
     #[inline]
     pub fn err(&self) -> std::io::Error {
-        std::io::Error::from_raw_os_error(self.errorno())
+        // NOTE: the kernel reports errors as negative errno values.
+        std::io::Error::from_raw_os_error(self.errorno().abs())
+    }
+}
+
+impl<'a, T: AsRef<[u8]> + ?Sized> NetlinkErrorPacket<&'a T> {
+    /// The extended ACK message (`NLMSGERR_ATTR_MSG`), `flags` are the flags of the `NLMSG_ERROR` message.
+    pub fn ext_ack_msg(&self, flags: Flags) -> Option<&'a str> {
+        if !flags.contains(Flags::NLM_F_ACK_TLVS) {
+            return None;
+        }
+
+        // struct nlmsgerr { int error; struct nlmsghdr msg; }, followed by the payload
+        // of the original request unless it was capped, then the TLVs.
+        let data = self.buffer.as_ref();
+        let orig = NetlinkPacket::new_checked(data.get(4..)?).ok()?;
+        let orig_len = if flags.contains(Flags::NLM_F_CAPPED) { orig.header_len() } else { orig.total_len() };
+
+        let mut attrs = data.get(4 + orig_len..)?;
+        while attrs.len() >= NetlinkAttrPacket::<&[u8]>::MIN_SIZE {
+            let attr = NetlinkAttrPacket::new_checked(attrs).ok()?;
+            if attr.kind() == NLMSGERR_ATTR_MSG {
+                let msg = attr.payload();
+                let end = msg.iter().position(|b| *b == 0).unwrap_or(msg.len());
+                return std::str::from_utf8(&msg[..end]).ok();
+            }
+
+            if attr.total_len() == 0 {
+                break;
+            }
+            attrs = attrs.get(attr.total_len()..)?;
+        }
+
+        None
+    }
+
+    /// The errno together with the extended ACK message if the kernel provides one,
+    /// the error kind is the same as the one of `err()`.
+    pub fn err_with_ext_ack(&self, flags: Flags) -> std::io::Error {
+        let err = self.err();
+        match self.ext_ack_msg(flags) {
+            Some(msg) => std::io::Error::new(err.kind(), ExtAckError { errno: self.errorno().abs(), message: msg.to_string() }),
+            None => err,
+        }
+    }
+}
+
+const NLMSGERR_ATTR_MSG: u16 = 1;
+
+/// An error reported by the kernel with the extended ACK message, e.g. `Invalid prefix for given prefix length`.
+#[derive(Debug, Clone)]
+pub struct ExtAckError {
+    pub errno: i32,
+    pub message: String,
+}
+
+impl std::fmt::Display for ExtAckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, std::io::Error::from_raw_os_error(self.errno))
     }
 }
 
+impl std::error::Error for ExtAckError { }
+
 impl<T: AsRef<[u8]> + AsMut<[u8]>> NetlinkErrorPacket<T> {
     #[inline]
     pub fn set_errorno(&mut self, value: i32) {
@@ -587,4 +679,279 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> NetlinkAttrPacket<T> {
         let data = self.buffer.as_mut();
         &mut data[4..len]
     }
-}
\ No newline at end of file
+}
+
+
+// nla_type flags, the remaining bits are the attribute type.
+pub const NLA_F_NESTED: u16        = 0x8000;
+pub const NLA_F_NET_BYTEORDER: u16 = 0x4000;
+pub const NLA_TYPE_MASK: u16       = !(NLA_F_NESTED | NLA_F_NET_BYTEORDER);
+
+impl<T: AsRef<[u8]>> NetlinkAttrPacket<T> {
+    /// The attribute type without the `NLA_F_NESTED` and `NLA_F_NET_BYTEORDER` flags.
+    #[inline]
+    pub fn attr_type(&self) -> u16 {
+        self.kind() & NLA_TYPE_MASK
+    }
+
+    /// The payload is a list of attributes. Older families don't set the flag on their nested attributes.
+    #[inline]
+    pub fn is_nested(&self) -> bool {
+        self.kind() & NLA_F_NESTED != 0
+    }
+}
+
+/// Iterate over a list of attributes, e.g. the payload of a message or of a nested attribute.
+///
+/// ```
+/// use netlink::packet::{ NetlinkAttrs, NetlinkAttrBuilder, };
+///
+/// let mut builder = NetlinkAttrBuilder::new();
+/// let nest = builder.nest_start(1);
+/// builder.put_u32(2, 1500);
+/// builder.nest_end(nest);
+///
+/// let attr = NetlinkAttrs::new(builder.as_bytes()).next().unwrap().unwrap();
+/// assert!(attr.is_nested());
+/// let inner = NetlinkAttrs::new(attr.payload()).next().unwrap().unwrap();
+/// assert_eq!(inner.attr_type(), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct NetlinkAttrs<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> NetlinkAttrs<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self { buffer }
+    }
+}
+
+impl<'a> Iterator for NetlinkAttrs<'a> {
+    type Item = Result<NetlinkAttrPacket<&'a [u8]>, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.len() < NetlinkAttrPacket::<&[u8]>::MIN_SIZE {
+            return None;
+        }
+
+        let attr = match NetlinkAttrPacket::new_checked(self.buffer) {
+            Ok(attr) => attr,
+            Err(e) => {
+                self.buffer = &[];
+                return Some(Err(e));
+            },
+        };
+        let (data, rest) = self.buffer.split_at(attr.total_len());
+        self.buffer = rest;
+
+        Some(Ok(NetlinkAttrPacket::new_unchecked(data)))
+    }
+}
+
+/// Append attributes to a buffer, the integers are written in native byte order.
+///
+/// The builder either owns its buffer or appends to an existing one, e.g. after a message header:
+///
+/// ```
+/// use netlink::packet::{ NetlinkAttrs, NetlinkAttrBuilder, };
+///
+/// let mut message = vec![0u8; 16];
+/// NetlinkAttrBuilder::with_buffer(&mut message).put_u32(1, 1500);
+/// assert_eq!(message.len(), 24);
+///
+/// let attr = NetlinkAttrs::new(&message[16..]).next().unwrap().unwrap();
+/// assert_eq!(attr.payload(), &1500u32.to_ne_bytes());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct NetlinkAttrBuilder<T: AsMut<Vec<u8>> = Vec<u8>> {
+    buffer: T,
+}
+
+impl NetlinkAttrBuilder {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+}
+
+impl<T: AsMut<Vec<u8>>> NetlinkAttrBuilder<T> {
+    pub fn with_buffer(buffer: T) -> Self {
+        Self { buffer }
+    }
+
+    pub fn put(&mut self, kind: u16, data: &[u8]) -> &mut Self {
+        let buffer = self.buffer.as_mut();
+        let start = buffer.len();
+        let len = NetlinkAttrPacket::<&[u8]>::MIN_SIZE + data.len();
+        buffer.resize(start + align(len), 0);
+
+        let mut attr = NetlinkAttrPacket::new_unchecked(&mut buffer[start..]);
+        attr.set_len(len as u16);
+        attr.set_kind(kind);
+        attr.payload_mut()[..data.len()].copy_from_slice(data);
+        self
+    }
+
+    pub fn put_u8(&mut self, kind: u16, value: u8) -> &mut Self {
+        self.put(kind, &[value])
+    }
+
+    pub fn put_u16(&mut self, kind: u16, value: u16) -> &mut Self {
+        self.put(kind, &value.to_ne_bytes())
+    }
+
+    pub fn put_u32(&mut self, kind: u16, value: u32) -> &mut Self {
+        self.put(kind, &value.to_ne_bytes())
+    }
+
+    pub fn put_u64(&mut self, kind: u16, value: u64) -> &mut Self {
+        self.put(kind, &value.to_ne_bytes())
+    }
+
+    /// A `u32` in network byte order, e.g. every integer attribute of nftables.
+    pub fn put_be32(&mut self, kind: u16, value: u32) -> &mut Self {
+        self.put(kind, &value.to_be_bytes())
+    }
+
+    /// A NUL terminated string (`NLA_NUL_STRING`), which most families expect.
+    pub fn put_strz(&mut self, kind: u16, value: &str) -> &mut Self {
+        let mut data = Vec::with_capacity(value.len() + 1);
+        data.extend_from_slice(value.as_bytes());
+        data.push(0);
+        self.put(kind, &data)
+    }
+
+    /// The address octets, 4 bytes for IPv4 and 16 bytes for IPv6.
+    pub fn put_addr(&mut self, kind: u16, addr: IpAddr) -> &mut Self {
+        match addr {
+            IpAddr::V4(addr) => self.put(kind, &addr.octets()),
+            IpAddr::V6(addr) => self.put(kind, &addr.octets()),
+        }
+    }
+
+    /// Start a nested attribute, the attributes added before `nest_end` are put inside it.
+    pub fn nest_start(&mut self, kind: u16) -> usize {
+        let buffer = self.buffer.as_mut();
+        let start = buffer.len();
+        buffer.resize(start + NetlinkAttrPacket::<&[u8]>::MIN_SIZE, 0);
+
+        let mut attr = NetlinkAttrPacket::new_unchecked(&mut buffer[start..]);
+        attr.set_kind(kind | NLA_F_NESTED);
+        start
+    }
+
+    pub fn nest_end(&mut self, start: usize) -> &mut Self {
+        let buffer = self.buffer.as_mut();
+        let len = buffer.len() - start;
+        let mut attr = NetlinkAttrPacket::new_unchecked(&mut buffer[start..]);
+        attr.set_len(len as u16);
+        self
+    }
+
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<Vec<u8>>> NetlinkAttrBuilder<T> {
+    /// The length of the whole buffer, including what was there before `with_buffer`.
+    pub fn len(&self) -> usize {
+        self.buffer.as_ref().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.as_ref().is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.buffer.as_ref()
+    }
+}
+
+
+#[test]
+fn test_err_with_ext_ack() {
+    // `ip route add 10.0.0.1/8 dev lo`, with `NETLINK_EXT_ACK` and `NETLINK_CAP_ACK` (the request is capped).
+    let capped: [u8; 80] = [
+        80, 0, 0, 0, 2, 0, 0, 3, 7, 0, 0, 0, 242, 96, 0, 0, 234, 255, 255, 255, 44, 0, 0, 0, 24, 0, 5, 6, 7, 0, 0, 0,
+        0, 0, 0, 0, 43, 0, 1, 0, 73, 110, 118, 97, 108, 105, 100, 32, 112, 114, 101, 102, 105, 120, 32, 102, 111, 114,
+        32, 103, 105, 118, 101, 110, 32, 112, 114, 101, 102, 105, 120, 32, 108, 101, 110, 103, 116, 104, 0, 0,
+    ];
+    // The same request with `NETLINK_EXT_ACK` only.
+    let uncapped: [u8; 108] = [
+        108, 0, 0, 0, 2, 0, 0, 2, 7, 0, 0, 0, 242, 96, 0, 0, 234, 255, 255, 255, 44, 0, 0, 0, 24, 0, 5, 6, 7, 0, 0, 0,
+        0, 0, 0, 0, 2, 8, 0, 0, 254, 3, 0, 1, 0, 0, 0, 0, 8, 0, 1, 0, 10, 0, 0, 1, 8, 0, 4, 0, 1, 0, 0, 0, 43, 0, 1, 0,
+        73, 110, 118, 97, 108, 105, 100, 32, 112, 114, 101, 102, 105, 120, 32, 102, 111, 114, 32, 103, 105, 118, 101,
+        110, 32, 112, 114, 101, 102, 105, 120, 32, 108, 101, 110, 103, 116, 104, 0, 0,
+    ];
+
+    for message in [&capped[..], &uncapped[..]].iter() {
+        let pkt = NetlinkPacket::new_checked(*message).unwrap();
+        assert_eq!(pkt.kind(), Kind::NLMSG_ERROR);
+        assert_eq!(pkt.seq(), 7);
+        assert!(pkt.flags().contains(Flags::NLM_F_ACK_TLVS));
+
+        let err_pkt = NetlinkErrorPacket::new_checked(pkt.payload()).unwrap();
+        assert_eq!(err_pkt.errorno(), -libc::EINVAL);
+        assert_eq!(err_pkt.ext_ack_msg(pkt.flags()), Some("Invalid prefix for given prefix length"));
+
+        let err = err_pkt.err_with_ext_ack(pkt.flags());
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let ext_ack = err.get_ref().unwrap().downcast_ref::<ExtAckError>().unwrap();
+        assert_eq!(ext_ack.errno, libc::EINVAL);
+        assert_eq!(ext_ack.message, "Invalid prefix for given prefix length");
+
+        // Without `NLM_F_ACK_TLVS` the TLVs are ignored.
+        let err = err_pkt.err_with_ext_ack(Flags::empty());
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+    }
+}
+
+#[test]
+fn test_netlink_attrs() {
+    let mut message = vec![0u8; 16];
+    let mut builder = NetlinkAttrBuilder::with_buffer(&mut message);
+    builder.put_u8(1, 0x7f)
+        .put_u16(2, 1500)
+        .put_strz(3, "exodus");
+    let nest = builder.nest_start(4);
+    builder.put_u32(1, 0xdead_beef)
+        .put_be32(2, 0x0a00_0001)
+        .put_u64(3, u64::max_value())
+        .put_addr(4, IpAddr::from([10, 0, 0, 1]))
+        .put_addr(5, IpAddr::from([0xfd00, 0, 0, 0, 0, 0, 0, 1]));
+    builder.nest_end(nest);
+    builder.put(5, &[]);
+    assert_eq!(builder.len(), 16 + 8 + 8 + 12 + (4 + 8 + 8 + 12 + 8 + 20) + 4);
+
+    // The bytes before `with_buffer` are untouched, and every attribute is aligned.
+    assert_eq!(&message[..16], &[0u8; 16]);
+    let attrs = NetlinkAttrs::new(&message[16..]).collect::<Result<Vec<_>, _>>().unwrap();
+    let kinds = attrs.iter().map(|attr| attr.attr_type()).collect::<Vec<_>>();
+    assert_eq!(kinds, vec![1, 2, 3, 4, 5]);
+
+    assert_eq!(attrs[0].len(), 5);
+    assert_eq!(attrs[0].payload(), &[0x7f, 0, 0, 0]);
+    assert_eq!(NativeEndian::read_u16(attrs[1].payload()), 1500);
+    assert_eq!(attrs[2].payload(), b"exodus\0\0");
+    assert!(!attrs[2].is_nested());
+    assert_eq!(attrs[4].len(), 4);
+    assert!(attrs[4].payload().is_empty());
+
+    assert!(attrs[3].is_nested());
+    assert_eq!(attrs[3].kind(), 4 | NLA_F_NESTED);
+    let nested = NetlinkAttrs::new(attrs[3].payload()).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(nested.len(), 5);
+    assert_eq!(NativeEndian::read_u32(nested[0].payload()), 0xdead_beef);
+    assert_eq!(nested[1].payload(), &[10, 0, 0, 1]);
+    assert_eq!(NativeEndian::read_u64(nested[2].payload()), u64::max_value());
+    assert_eq!(nested[3].payload(), &[10, 0, 0, 1]);
+    assert_eq!(nested[4].payload_len(), 16);
+    assert_eq!(nested[4].payload()[0], 0xfd);
+
+    // A truncated attribute is an error, and ends the iteration.
+    let mut attrs = NetlinkAttrs::new(&message[16..30]);
+    assert!(attrs.next().unwrap().is_ok());
+    assert!(attrs.next().unwrap().is_err());
+    assert!(attrs.next().is_none());
+}
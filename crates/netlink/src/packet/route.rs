@@ -199,7 +199,7 @@ impl std::fmt::Display for RouteScope {
 // rt_class (rt_table)
 // The user may assign arbitrary values between RT_TABLE_UNSPEC and RT_TABLE_DEFAULT.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
-pub struct RouteTable(pub u8);
+pub struct RouteTable(pub u32);
 
 impl RouteTable {
     // Reserved table identifiers
@@ -211,9 +211,16 @@ impl RouteTable {
     pub const RT_TABLE_LOCAL: Self   = Self(255); // the local table
 }
 
+impl Into<u32> for RouteTable {
+    fn into(self) -> u32 {
+        self.0
+    }
+}
+
+// rtm_table is only 8 bits, tables above 255 saturate to RT_TABLE_COMPAT.
 impl Into<u8> for RouteTable {
     fn into(self) -> u8 {
-        self.0
+        if self.0 > 255 { Self::RT_TABLE_COMPAT.0 as u8 } else { self.0 as u8 }
     }
 }
 
@@ -332,6 +339,87 @@ impl std::fmt::Display for RouteAttrType {
     }
 }
 
+// Nested in `RTA_METRICS`
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct RouteMetricType(pub u16);
+
+impl RouteMetricType {
+    pub const RTAX_UNSPEC: Self             = Self(0);
+    pub const RTAX_LOCK: Self               = Self(1);
+    pub const RTAX_MTU: Self                = Self(2);
+    pub const RTAX_WINDOW: Self             = Self(3);
+    pub const RTAX_RTT: Self                = Self(4);
+    pub const RTAX_RTTVAR: Self             = Self(5);
+    pub const RTAX_SSTHRESH: Self           = Self(6);
+    pub const RTAX_CWND: Self               = Self(7);
+    pub const RTAX_ADVMSS: Self             = Self(8);
+    pub const RTAX_REORDERING: Self         = Self(9);
+    pub const RTAX_HOPLIMIT: Self           = Self(10);
+    pub const RTAX_INITCWND: Self           = Self(11);
+    pub const RTAX_FEATURES: Self           = Self(12);
+    pub const RTAX_RTO_MIN: Self            = Self(13);
+    pub const RTAX_INITRWND: Self           = Self(14);
+    pub const RTAX_QUICKACK: Self           = Self(15);
+    pub const RTAX_CC_ALGO: Self            = Self(16);
+    pub const RTAX_FASTOPEN_NO_COOKIE: Self = Self(17);
+}
+
+impl Into<u16> for RouteMetricType {
+    fn into(self) -> u16 {
+        self.0
+    }
+}
+
+impl std::fmt::Debug for RouteMetricType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::RTAX_UNSPEC => write!(f, "RTAX_UNSPEC"),
+            Self::RTAX_LOCK => write!(f, "RTAX_LOCK"),
+            Self::RTAX_MTU => write!(f, "RTAX_MTU"),
+            Self::RTAX_WINDOW => write!(f, "RTAX_WINDOW"),
+            Self::RTAX_RTT => write!(f, "RTAX_RTT"),
+            Self::RTAX_RTTVAR => write!(f, "RTAX_RTTVAR"),
+            Self::RTAX_SSTHRESH => write!(f, "RTAX_SSTHRESH"),
+            Self::RTAX_CWND => write!(f, "RTAX_CWND"),
+            Self::RTAX_ADVMSS => write!(f, "RTAX_ADVMSS"),
+            Self::RTAX_REORDERING => write!(f, "RTAX_REORDERING"),
+            Self::RTAX_HOPLIMIT => write!(f, "RTAX_HOPLIMIT"),
+            Self::RTAX_INITCWND => write!(f, "RTAX_INITCWND"),
+            Self::RTAX_FEATURES => write!(f, "RTAX_FEATURES"),
+            Self::RTAX_RTO_MIN => write!(f, "RTAX_RTO_MIN"),
+            Self::RTAX_INITRWND => write!(f, "RTAX_INITRWND"),
+            Self::RTAX_QUICKACK => write!(f, "RTAX_QUICKACK"),
+            Self::RTAX_CC_ALGO => write!(f, "RTAX_CC_ALGO"),
+            Self::RTAX_FASTOPEN_NO_COOKIE => write!(f, "RTAX_FASTOPEN_NO_COOKIE"),
+            _ => write!(f, "RTAX_UNKNOW({})", self.0),
+        }
+    }
+}
+
+impl std::fmt::Display for RouteMetricType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+// rtnh_flags
+bitflags! {
+    pub struct NextHopFlags: u8 {
+        const RTNH_F_DEAD       =  1; // Nexthop is dead (used by multipath)
+        const RTNH_F_PERVASIVE  =  2; // Do recursive gateway lookup
+        const RTNH_F_ONLINK     =  4; // Gateway is forced on link
+        const RTNH_F_OFFLOAD    =  8; // offloaded route
+        const RTNH_F_LINKDOWN   = 16; // carrier-down on nexthop
+        const RTNH_F_UNRESOLVED = 32; // The entry is unresolved (ipmr)
+    }
+}
+
+impl Into<u8> for NextHopFlags {
+    fn into(self) -> u8 {
+        self.bits()
+    }
+}
+
 
 const FAMILY: usize         = 0;
 const DST_LEN: usize        = 1;
@@ -410,7 +498,7 @@ impl<T: AsRef<[u8]>> RoutePacket<T> {
     #[inline]
     pub fn table(&self) -> RouteTable {
         let data = self.buffer.as_ref();
-        RouteTable(data[TABLE])
+        RouteTable(data[TABLE] as u32)
     }
 
     #[inline]
@@ -475,7 +563,8 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> RoutePacket<T> {
     #[inline]
     pub fn set_table(&mut self, value: RouteTable) {
         let data = self.buffer.as_mut();
-        data[TABLE] = value.0;
+        // NOTE: Tables above 255 are only carried by the `RTA_TABLE` attribute.
+        data[TABLE] = value.into();
     }
 
     #[inline]
@@ -523,3 +612,124 @@ impl<'a, T: AsRef<[u8]> + ?Sized> std::fmt::Display for RoutePacket<&'a T> {
                 self.flags())
     }
 }
+
+
+// struct rtnexthop, the entries of `RTA_MULTIPATH`, each one is followed by its attrs (e.g. `RTA_GATEWAY`).
+const RTNH_LEN: Range<usize>     = 0..2;
+const RTNH_FLAGS: usize          = 2;
+const RTNH_HOPS: usize           = 3;
+const RTNH_IFINDEX: Range<usize> = 4..8;
+const RTNH_PAYLOAD: usize        = 8;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct NextHopPacket<T: AsRef<[u8]>> {
+    buffer: T
+}
+
+impl<T: AsRef<[u8]>> NextHopPacket<T> {
+    pub const MIN_SIZE: usize = 8;
+
+    #[inline]
+    pub fn new_unchecked(buffer: T) -> NextHopPacket<T> {
+        NextHopPacket { buffer }
+    }
+
+    #[inline]
+    pub fn new_checked(buffer: T) -> Result<NextHopPacket<T>, io::Error> {
+        let v = Self::new_unchecked(buffer);
+        v.check_len()?;
+
+        Ok(v)
+    }
+
+    #[inline]
+    pub fn check_len(&self) -> Result<(), io::Error> {
+        let data = self.buffer.as_ref();
+        if data.len() < Self::MIN_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "packet is too small."));
+        }
+
+        let len = NativeEndian::read_u16(&data[RTNH_LEN]) as usize;
+        if len < Self::MIN_SIZE || data.len() < len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "packet is too small."));
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
+    #[inline]
+    pub fn len(&self) -> u16 {
+        let data = self.buffer.as_ref();
+        NativeEndian::read_u16(&data[RTNH_LEN])
+    }
+
+    #[inline]
+    pub fn total_len(&self) -> usize {
+        super::align(self.len() as usize)
+    }
+
+    #[inline]
+    pub fn flags(&self) -> NextHopFlags {
+        let data = self.buffer.as_ref();
+        NextHopFlags::from_bits_truncate(data[RTNH_FLAGS])
+    }
+
+    /// The weight of the next hop minus one.
+    #[inline]
+    pub fn hops(&self) -> u8 {
+        let data = self.buffer.as_ref();
+        data[RTNH_HOPS]
+    }
+
+    #[inline]
+    pub fn ifindex(&self) -> i32 {
+        let data = self.buffer.as_ref();
+        NativeEndian::read_i32(&data[RTNH_IFINDEX])
+    }
+}
+
+impl<'a, T: AsRef<[u8]> + ?Sized> NextHopPacket<&'a T> {
+    #[inline]
+    pub fn payload(&self) -> &'a [u8] {
+        let data = self.buffer.as_ref();
+        let len = self.len() as usize;
+        &data[RTNH_PAYLOAD..len]
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> NextHopPacket<T> {
+    #[inline]
+    pub fn set_len(&mut self, value: u16) {
+        let data = self.buffer.as_mut();
+        NativeEndian::write_u16(&mut data[RTNH_LEN], value);
+    }
+
+    #[inline]
+    pub fn set_flags(&mut self, value: NextHopFlags) {
+        let data = self.buffer.as_mut();
+        data[RTNH_FLAGS] = value.bits();
+    }
+
+    #[inline]
+    pub fn set_hops(&mut self, value: u8) {
+        let data = self.buffer.as_mut();
+        data[RTNH_HOPS] = value;
+    }
+
+    #[inline]
+    pub fn set_ifindex(&mut self, value: i32) {
+        let data = self.buffer.as_mut();
+        NativeEndian::write_i32(&mut data[RTNH_IFINDEX], value);
+    }
+
+    #[inline]
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let data = self.buffer.as_mut();
+        &mut data[RTNH_PAYLOAD..]
+    }
+}
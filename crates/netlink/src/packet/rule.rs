@@ -0,0 +1,332 @@
+// https://github.com/torvalds/linux/blob/master/include/uapi/linux/fib_rules.h
+
+use crate::packet::AddressFamily;
+use crate::packet::RouteTable;
+
+use byteorder::{ByteOrder, NativeEndian};
+
+use std::io;
+use core::ops::Range;
+
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fib_rule_hdr {
+    pub family: u8,
+    pub dst_len: u8,
+    pub src_len: u8,
+    pub tos: u8,
+    pub table: u8,
+    pub res1: u8,   // reserved
+    pub res2: u8,   // reserved
+    pub action: u8,
+    pub flags: u32,
+}
+
+impl Default for fib_rule_hdr {
+    fn default() -> Self {
+        Self {
+            family: 0,
+            dst_len: 0,
+            src_len: 0,
+            tos: 0,
+            table: 0,
+            res1: 0,
+            res2: 0,
+            action: 0,
+            flags: 0,
+        }
+    }
+}
+
+
+// action
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct RuleAction(pub u8);
+
+impl RuleAction {
+    pub const FR_ACT_UNSPEC: Self      = Self(0);
+    pub const FR_ACT_TO_TBL: Self      = Self(1); // Pass to fixed table
+    pub const FR_ACT_GOTO: Self        = Self(2); // Jump to another rule
+    pub const FR_ACT_NOP: Self         = Self(3); // No operation
+    pub const FR_ACT_BLACKHOLE: Self   = Self(6); // Drop without notification
+    pub const FR_ACT_UNREACHABLE: Self = Self(7); // Drop with ENETUNREACH
+    pub const FR_ACT_PROHIBIT: Self    = Self(8); // Drop with EACCES
+}
+
+impl Into<u8> for RuleAction {
+    fn into(self) -> u8 {
+        self.0
+    }
+}
+
+impl std::fmt::Debug for RuleAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::FR_ACT_UNSPEC => write!(f, "FR_ACT_UNSPEC"),
+            Self::FR_ACT_TO_TBL => write!(f, "FR_ACT_TO_TBL"),
+            Self::FR_ACT_GOTO => write!(f, "FR_ACT_GOTO"),
+            Self::FR_ACT_NOP => write!(f, "FR_ACT_NOP"),
+            Self::FR_ACT_BLACKHOLE => write!(f, "FR_ACT_BLACKHOLE"),
+            Self::FR_ACT_UNREACHABLE => write!(f, "FR_ACT_UNREACHABLE"),
+            Self::FR_ACT_PROHIBIT => write!(f, "FR_ACT_PROHIBIT"),
+            _ => write!(f, "FR_ACT_UNKNOW({})", self.0),
+        }
+    }
+}
+
+impl std::fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+// rule flags
+bitflags! {
+    pub struct RuleFlags: u32 {
+        const FIB_RULE_PERMANENT     = 0x00000001;
+        const FIB_RULE_INVERT        = 0x00000002; // `ip rule add not ...`
+        const FIB_RULE_UNRESOLVED    = 0x00000004;
+        const FIB_RULE_IIF_DETACHED  = 0x00000008;
+        const FIB_RULE_DEV_DETACHED  = Self::FIB_RULE_IIF_DETACHED.bits;
+        const FIB_RULE_OIF_DETACHED  = 0x00000010;
+        // try to find source address in routing lookups
+        const FIB_RULE_FIND_SADDR    = 0x00010000;
+    }
+}
+
+impl Into<u32> for RuleFlags {
+    fn into(self) -> u32 {
+        self.bits()
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct RuleAttrType(pub u16);
+
+impl RuleAttrType {
+    pub const FRA_UNSPEC: Self              = Self(0);
+    pub const FRA_DST: Self                 = Self(1);  // destination address
+    pub const FRA_SRC: Self                 = Self(2);  // source address
+    pub const FRA_IIFNAME: Self             = Self(3);  // interface name
+    pub const FRA_GOTO: Self                = Self(4);  // target to jump to (FR_ACT_GOTO)
+    pub const FRA_PRIORITY: Self            = Self(6);  // priority/preference
+    pub const FRA_FWMARK: Self              = Self(10); // mark
+    pub const FRA_FLOW: Self                = Self(11); // flow/class id
+    pub const FRA_TUN_ID: Self              = Self(12);
+    pub const FRA_SUPPRESS_IFGROUP: Self    = Self(13);
+    pub const FRA_SUPPRESS_PREFIXLEN: Self  = Self(14);
+    pub const FRA_TABLE: Self               = Self(15); // Extended table id
+    pub const FRA_FWMASK: Self              = Self(16); // mask for netfilter mark
+    pub const FRA_OIFNAME: Self             = Self(17);
+    pub const FRA_PAD: Self                 = Self(18);
+    pub const FRA_L3MDEV: Self              = Self(19); // iif or oif is l3mdev goto its table
+    pub const FRA_UID_RANGE: Self           = Self(20); // UID range
+    pub const FRA_PROTOCOL: Self            = Self(21); // Originator of the rule
+    pub const FRA_IP_PROTO: Self            = Self(22); // ip proto
+    pub const FRA_SPORT_RANGE: Self         = Self(23); // sport
+    pub const FRA_DPORT_RANGE: Self         = Self(24); // dport
+}
+
+impl Into<u16> for RuleAttrType {
+    fn into(self) -> u16 {
+        self.0
+    }
+}
+
+impl std::fmt::Debug for RuleAttrType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::FRA_UNSPEC => write!(f, "FRA_UNSPEC"),
+            Self::FRA_DST => write!(f, "FRA_DST"),
+            Self::FRA_SRC => write!(f, "FRA_SRC"),
+            Self::FRA_IIFNAME => write!(f, "FRA_IIFNAME"),
+            Self::FRA_GOTO => write!(f, "FRA_GOTO"),
+            Self::FRA_PRIORITY => write!(f, "FRA_PRIORITY"),
+            Self::FRA_FWMARK => write!(f, "FRA_FWMARK"),
+            Self::FRA_FLOW => write!(f, "FRA_FLOW"),
+            Self::FRA_TUN_ID => write!(f, "FRA_TUN_ID"),
+            Self::FRA_SUPPRESS_IFGROUP => write!(f, "FRA_SUPPRESS_IFGROUP"),
+            Self::FRA_SUPPRESS_PREFIXLEN => write!(f, "FRA_SUPPRESS_PREFIXLEN"),
+            Self::FRA_TABLE => write!(f, "FRA_TABLE"),
+            Self::FRA_FWMASK => write!(f, "FRA_FWMASK"),
+            Self::FRA_OIFNAME => write!(f, "FRA_OIFNAME"),
+            Self::FRA_PAD => write!(f, "FRA_PAD"),
+            Self::FRA_L3MDEV => write!(f, "FRA_L3MDEV"),
+            Self::FRA_UID_RANGE => write!(f, "FRA_UID_RANGE"),
+            Self::FRA_PROTOCOL => write!(f, "FRA_PROTOCOL"),
+            Self::FRA_IP_PROTO => write!(f, "FRA_IP_PROTO"),
+            Self::FRA_SPORT_RANGE => write!(f, "FRA_SPORT_RANGE"),
+            Self::FRA_DPORT_RANGE => write!(f, "FRA_DPORT_RANGE"),
+            _ => write!(f, "FRA_UNKNOW({})", self.0),
+        }
+    }
+}
+
+impl std::fmt::Display for RuleAttrType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+
+const FAMILY: usize         = 0;
+const DST_LEN: usize        = 1;
+const SRC_LEN: usize        = 2;
+const TOS: usize            = 3;
+const TABLE: usize          = 4;
+const ACTION: usize         = 7;
+const FLAGS: Range<usize>   = 8..12;
+
+const PAYLOAD: usize        = 12;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RulePacket<T: AsRef<[u8]>> {
+    buffer: T
+}
+
+impl<T: AsRef<[u8]>> RulePacket<T> {
+    pub const MIN_SIZE: usize = 12;
+
+    #[inline]
+    pub fn new_unchecked(buffer: T) -> RulePacket<T> {
+        RulePacket { buffer }
+    }
+
+    #[inline]
+    pub fn new_checked(buffer: T) -> Result<RulePacket<T>, io::Error> {
+        let v = Self::new_unchecked(buffer);
+        v.check_len()?;
+
+        Ok(v)
+    }
+
+    #[inline]
+    pub fn check_len(&self) -> Result<(), io::Error> {
+        let data = self.buffer.as_ref();
+        if data.len() < Self::MIN_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "packet is too small."));
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
+    #[inline]
+    pub fn family(&self) -> AddressFamily {
+        let data = self.buffer.as_ref();
+        AddressFamily(data[FAMILY])
+    }
+
+    #[inline]
+    pub fn dst_len(&self) -> u8 {
+        let data = self.buffer.as_ref();
+        data[DST_LEN]
+    }
+
+    #[inline]
+    pub fn src_len(&self) -> u8 {
+        let data = self.buffer.as_ref();
+        data[SRC_LEN]
+    }
+
+    #[inline]
+    pub fn tos(&self) -> u8 {
+        let data = self.buffer.as_ref();
+        data[TOS]
+    }
+
+    #[inline]
+    pub fn table(&self) -> RouteTable {
+        let data = self.buffer.as_ref();
+        RouteTable(data[TABLE] as u32)
+    }
+
+    #[inline]
+    pub fn action(&self) -> RuleAction {
+        let data = self.buffer.as_ref();
+        RuleAction(data[ACTION])
+    }
+
+    #[inline]
+    pub fn flags(&self) -> RuleFlags {
+        let data = self.buffer.as_ref();
+        RuleFlags::from_bits_truncate(NativeEndian::read_u32(&data[FLAGS]))
+    }
+}
+
+impl<'a, T: AsRef<[u8]> + ?Sized> RulePacket<&'a T> {
+    #[inline]
+    pub fn payload(&self) -> &'a [u8] {
+        let data = self.buffer.as_ref();
+        &data[PAYLOAD..]
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> RulePacket<T> {
+    #[inline]
+    pub fn set_family(&mut self, value: AddressFamily) {
+        let data = self.buffer.as_mut();
+        data[FAMILY] = value.0;
+    }
+
+    #[inline]
+    pub fn set_dst_len(&mut self, value: u8) {
+        let data = self.buffer.as_mut();
+        data[DST_LEN] = value;
+    }
+
+    #[inline]
+    pub fn set_src_len(&mut self, value: u8) {
+        let data = self.buffer.as_mut();
+        data[SRC_LEN] = value;
+    }
+
+    #[inline]
+    pub fn set_tos(&mut self, value: u8) {
+        let data = self.buffer.as_mut();
+        data[TOS] = value;
+    }
+
+    #[inline]
+    pub fn set_table(&mut self, value: RouteTable) {
+        let data = self.buffer.as_mut();
+        // NOTE: Tables above 255 are only carried by the `FRA_TABLE` attribute.
+        data[TABLE] = if value.0 > 255 { RouteTable::RT_TABLE_UNSPEC.0 as u8 } else { value.0 as u8 };
+    }
+
+    #[inline]
+    pub fn set_action(&mut self, value: RuleAction) {
+        let data = self.buffer.as_mut();
+        data[ACTION] = value.0;
+    }
+
+    #[inline]
+    pub fn set_flags(&mut self, value: RuleFlags) {
+        let data = self.buffer.as_mut();
+        NativeEndian::write_u32(&mut data[FLAGS], value.bits());
+    }
+
+    #[inline]
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let data = self.buffer.as_mut();
+        &mut data[PAYLOAD..]
+    }
+}
+
+impl<'a, T: AsRef<[u8]> + ?Sized> std::fmt::Display for RulePacket<&'a T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RulePacket {{ family: {:?}, dst_len: {}, src_len: {}, tos: {}, table: {:?}, action: {:?}, flags: {:?} }}",
+                self.family(),
+                self.dst_len(),
+                self.src_len(),
+                self.tos(),
+                self.table(),
+                self.action(),
+                self.flags())
+    }
+}
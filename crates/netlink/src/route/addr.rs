@@ -10,16 +10,25 @@ use crate::packet::LinkName;
 use crate::packet::RouteScope;
 use crate::packet::AddressFamily;
 
+use crate::packet::NetlinkAttrBuilder;
+
 
 use libc::IF_NAMESIZE;
-use byteorder::{ByteOrder, NetworkEndian};
+use byteorder::{ByteOrder, NativeEndian, NetworkEndian};
 
 use std::io;
+use std::net::IpAddr;
+use std::convert::TryFrom;
+
+
+/// The address never expires.
+pub const INFINITY_LIFE_TIME: u32 = 0xFFFF_FFFF;
 
 
 #[derive(Debug, Clone, Copy)]
 pub struct Addr {
     pub ifindex: u32,
+    pub prefix_len: u8,
     pub flags: AddrFlags,
     pub scope: RouteScope,
     // Attrs
@@ -85,14 +94,20 @@ impl<'a, 'b> Iterator for Addrs<'a, 'b> {
             Err(e) => return Some(Err(e)),
         };
         
-        let packet = match AddrPacket::new_checked(pkt.payload()) {
-            Ok(pkt) => pkt,
-            Err(e) => return Some(Err(e)),
-        };
+        Some(Addr::try_from(pkt.payload()))
+    }
+}
+
+impl TryFrom<&[u8]> for Addr {
+    type Error = io::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let packet = AddrPacket::new_checked(value)?;
         
         let address_family = packet.family();
 
         let ifindex = packet.ifindex() as u32;
+        let prefix_len = packet.prefixlen();
         let flags = packet.flags();
         let scope = packet.scope();
 
@@ -108,10 +123,7 @@ impl<'a, 'b> Iterator for Addrs<'a, 'b> {
                 break;
             }
 
-            let attr = match NetlinkAttrPacket::new_checked(&payload) {
-                Ok(pkt) => pkt,
-                Err(e) => return Some(Err(e)),
-            };
+            let attr = NetlinkAttrPacket::new_checked(&payload)?;
 
             // let attr_payload_len = attr.payload_len();
             let attr_total_len = attr.total_len();
@@ -165,6 +177,203 @@ impl<'a, 'b> Iterator for Addrs<'a, 'b> {
             payload = &payload[attr_total_len..];
         }
 
-        Some(Ok(Addr{ ifindex, flags, scope, addr, local, broadcast, label, }))
+        Ok(Addr{ ifindex, prefix_len, flags, scope, addr, local, broadcast, label, })
+    }
+}
+
+/// Address to add to (`RTM_NEWADDR`) or remove from (`RTM_DELADDR`) an interface.
+///
+/// ```no_run
+/// use netlink::route::RouteController;
+/// use netlink::route::addr::AddrRequest;
+///
+/// // sudo ip addr add 10.0.0.2/24 broadcast 10.0.0.255 dev eth0 label eth0:vpn valid_lft 3600 preferred_lft 3600
+/// let ifindex = 2;
+/// let req = AddrRequest::new(ifindex, "10.0.0.2".parse().unwrap(), 24)
+///     .broadcast("10.0.0.255".parse().unwrap())
+///     .label("eth0:vpn")
+///     .lifetime(3600, 3600);
+///
+/// let mut buffer = netlink::packet::alloc();
+/// let mut route_controller = RouteController::new().unwrap();
+/// route_controller.add_addr(&req, &mut buffer).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct AddrRequest {
+    pub ifindex: u32,
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+    pub peer: Option<IpAddr>,
+    pub broadcast: Option<IpAddr>,
+    pub label: Option<String>,
+    pub scope: RouteScope,
+    pub flags: AddrFlags,
+    // (valid_lft, preferred_lft) in seconds
+    pub lifetime: Option<(u32, u32)>,
+}
+
+impl AddrRequest {
+    pub fn new(ifindex: u32, addr: IpAddr, prefix_len: u8) -> Self {
+        Self {
+            ifindex,
+            addr,
+            prefix_len,
+            peer: None,
+            broadcast: None,
+            label: None,
+            scope: RouteScope::RT_SCOPE_UNIVERSE,
+            flags: AddrFlags::from_bits_truncate(0),
+            lifetime: None,
+        }
+    }
+
+    /// The remote address of a point-to-point interface (`ip addr add <addr> peer <peer>`).
+    pub fn peer(mut self, peer: IpAddr) -> Self {
+        self.peer = Some(peer);
+        self
+    }
+
+    /// IPv4 only.
+    pub fn broadcast(mut self, broadcast: IpAddr) -> Self {
+        self.broadcast = Some(broadcast);
+        self
+    }
+
+    /// IPv4 only, the label has to start with the interface name (e.g. `eth0:1`).
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn scope(mut self, scope: RouteScope) -> Self {
+        self.scope = scope;
+        self
     }
-}
\ No newline at end of file
+
+    pub fn flags(mut self, flags: AddrFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Valid and preferred lifetime in seconds, `INFINITY_LIFE_TIME` means forever.
+    pub fn lifetime(mut self, valid_lft: u32, preferred_lft: u32) -> Self {
+        self.lifetime = Some((valid_lft, preferred_lft));
+        self
+    }
+
+    pub fn family(&self) -> AddressFamily {
+        match self.addr {
+            IpAddr::V4(_) => AddressFamily::AF_INET,
+            IpAddr::V6(_) => AddressFamily::AF_INET6,
+        }
+    }
+
+    fn check(&self) -> Result<(), io::Error> {
+        let max_prefix_len = if self.addr.is_ipv4() { 32 } else { 128 };
+        if self.prefix_len > max_prefix_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid prefix length"));
+        }
+
+        if let Some(peer) = self.peer {
+            if peer.is_ipv4() != self.addr.is_ipv4() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "peer address family mismatch"));
+            }
+        }
+
+        if let Some(broadcast) = self.broadcast {
+            if !broadcast.is_ipv4() || !self.addr.is_ipv4() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "broadcast address is only supported by IPv4"));
+            }
+        }
+
+        if let Some(ref label) = self.label {
+            if !self.addr.is_ipv4() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "address label is only supported by IPv4"));
+            }
+            if label.len() > IF_NAMESIZE - 1 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "address label is too long"));
+            }
+        }
+
+        if let Some((valid_lft, preferred_lft)) = self.lifetime {
+            if preferred_lft > valid_lft {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "preferred lifetime is greater than valid lifetime"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the `ifaddrmsg` header and the attributes into the buffer, returns the written length.
+    pub fn emit(&self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+        self.check()?;
+
+        let mut attrs = NetlinkAttrBuilder::new();
+        // NOTE: `IFA_LOCAL` is the address of the interface, `IFA_ADDRESS` is the peer address
+        //       of point-to-point interfaces, they are the same on broadcast interfaces.
+        attrs.put_addr(AddrAttrType::IFA_LOCAL.into(), self.addr);
+        attrs.put_addr(AddrAttrType::IFA_ADDRESS.into(), self.peer.unwrap_or(self.addr));
+        if let Some(broadcast) = self.broadcast {
+            attrs.put_addr(AddrAttrType::IFA_BROADCAST.into(), broadcast);
+        }
+        if let Some(ref label) = self.label {
+            attrs.put_strz(AddrAttrType::IFA_LABEL.into(), label);
+        }
+        if let Some((valid_lft, preferred_lft)) = self.lifetime {
+            // struct ifa_cacheinfo
+            let mut data = [0u8; 16];
+            NativeEndian::write_u32(&mut data[0..4], preferred_lft);
+            NativeEndian::write_u32(&mut data[4..8], valid_lft);
+            attrs.put(AddrAttrType::IFA_CACHEINFO.into(), &data);
+        }
+        // NOTE: `ifa_flags` has only 8 bits, newer flags are carried by `IFA_FLAGS`.
+        attrs.put_u32(AddrAttrType::IFA_FLAGS.into(), self.flags.bits() as u32);
+
+        let len = AddrPacket::<&[u8]>::MIN_SIZE + attrs.len();
+        if buffer.len() < len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is too small."));
+        }
+
+        let mut addr_packet = AddrPacket::new_unchecked(&mut buffer[..len]);
+        addr_packet.set_family(self.family());
+        addr_packet.set_prefixlen(self.prefix_len);
+        addr_packet.set_flags(self.flags);
+        addr_packet.set_scope(self.scope);
+        addr_packet.set_ifindex(self.ifindex as i32);
+        addr_packet.payload_mut().copy_from_slice(attrs.as_bytes());
+
+        Ok(len)
+    }
+}
+
+
+#[test]
+fn test_addr_request_emit() {
+    let mut buffer = [0u8; 1024];
+
+    let req = AddrRequest::new(2, "10.0.0.2".parse().unwrap(), 24)
+        .peer("10.0.0.1".parse().unwrap())
+        .broadcast("10.0.0.255".parse().unwrap())
+        .label("eth0:vpn")
+        .lifetime(3600, 1800);
+    let len = req.emit(&mut buffer).unwrap();
+    let addr = Addr::try_from(&buffer[..len]).unwrap();
+    assert_eq!(addr.ifindex, 2);
+    assert_eq!(addr.prefix_len, 24);
+    assert_eq!(addr.local, Some("10.0.0.2".parse().unwrap()));
+    assert_eq!(addr.addr, Some("10.0.0.1".parse().unwrap()));
+    assert_eq!(addr.broadcast, Some("10.0.0.255".parse().unwrap()));
+    assert_eq!(addr.label.unwrap().to_string(), "eth0:vpn");
+
+    let req = AddrRequest::new(2, "fd00::2".parse().unwrap(), 64).flags(AddrFlags::IFA_F_NODAD);
+    let len = req.emit(&mut buffer).unwrap();
+    let addr = Addr::try_from(&buffer[..len]).unwrap();
+    assert_eq!(addr.prefix_len, 64);
+    assert_eq!(addr.flags, AddrFlags::IFA_F_NODAD);
+    assert_eq!(addr.local, Some("fd00::2".parse().unwrap()));
+    assert_eq!(addr.addr, Some("fd00::2".parse().unwrap()));
+
+    assert!(AddrRequest::new(2, "fd00::2".parse().unwrap(), 129).emit(&mut buffer).is_err());
+    assert!(AddrRequest::new(2, "fd00::2".parse().unwrap(), 64).label("eth0:1").emit(&mut buffer).is_err());
+    assert!(AddrRequest::new(2, "10.0.0.2".parse().unwrap(), 24).lifetime(60, 120).emit(&mut buffer).is_err());
+}
@@ -4,6 +4,7 @@ use crate::packet::MacAddr;
 use crate::packet::NetlinkPacket;
 use crate::packet::NetlinkErrorPacket;
 use crate::packet::NetlinkAttrPacket;
+use crate::packet::NetlinkAttrBuilder;
 use crate::packet::LinkPacket;
 use crate::packet::LinkAttrType;
 use crate::packet::LinkFlags;
@@ -11,11 +12,16 @@ use crate::packet::LinkKind;
 use crate::packet::LinkMode;
 use crate::packet::LinkOperState;
 use crate::packet::LinkName;
+use crate::packet::LinkInfoAttrType;
+use crate::packet::AddressFamily;
+use crate::packet::{ VETH_INFO_PEER, IFLA_VLAN_ID, };
+
 
 use libc::IF_NAMESIZE;
 use byteorder::{ByteOrder, NativeEndian};
 
 use std::io;
+use std::convert::TryFrom;
 
 
 #[derive(Debug, Clone, Copy)]
@@ -30,6 +36,8 @@ pub struct Link {
     pub oper_state: Option<LinkOperState>,
     pub addr: Option<MacAddr>,
     pub broadcast: Option<MacAddr>,
+    // The bridge (or bond) the link is enslaved to.
+    pub master: Option<u32>,
 }
 
 pub struct Links<'a, 'b> {
@@ -88,10 +96,15 @@ impl<'a, 'b> Iterator for Links<'a, 'b> {
             Err(e) => return Some(Err(e)),
         };
         
-        let packet = match LinkPacket::new_checked(pkt.payload()) {
-            Ok(pkt) => pkt,
-            Err(e) => return Some(Err(e)),
-        };
+        Some(Link::try_from(pkt.payload()))
+    }
+}
+
+impl TryFrom<&[u8]> for Link {
+    type Error = io::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let packet = LinkPacket::new_checked(value)?;
         
         // let address_family = packet.family();
         let kind = packet.kind();
@@ -104,6 +117,7 @@ impl<'a, 'b> Iterator for Links<'a, 'b> {
         let mut oper_state = None;
         let mut addr = None;
         let mut broadcast = None;
+        let mut master = None;
 
         let mut payload = packet.payload();
 
@@ -112,10 +126,7 @@ impl<'a, 'b> Iterator for Links<'a, 'b> {
                 break;
             }
 
-            let attr = match NetlinkAttrPacket::new_checked(&payload) {
-                Ok(pkt) => pkt,
-                Err(e) => return Some(Err(e)),
-            };
+            let attr = NetlinkAttrPacket::new_checked(&payload)?;
 
             // let attr_payload_len = attr.payload_len();
             let attr_total_len = attr.total_len();
@@ -141,6 +152,8 @@ impl<'a, 'b> Iterator for Links<'a, 'b> {
                 broadcast = Some(MacAddr([
                                 attr_data[0], attr_data[1], attr_data[2],
                                 attr_data[3], attr_data[4], attr_data[5]]));
+            } else if attr_kind == LinkAttrType::IFLA_MASTER {
+                master = Some(NativeEndian::read_u32(&attr_data));
             } else {
                 trace!("Droped Link Attr: type={:15} data={:?}", format!("{:?}", attr_kind), attr_data);
             }
@@ -148,6 +161,325 @@ impl<'a, 'b> Iterator for Links<'a, 'b> {
             payload = &payload[attr_total_len..];
         }
 
-        Some(Ok(Link{ ifindex, kind, flags, ifname, mtu, mode, oper_state, addr, broadcast, }))
+        Ok(Link{ ifindex, kind, flags, ifname, mtu, mode, oper_state, addr, broadcast, master, })
+    }
+}
+
+/// The type of a virtual link (`ip link add <ifname> type <kind>`).
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum LinkInfo {
+    /// A pair of connected ethernet links, the peer is created together with the link.
+    Veth { peer: String },
+    Bridge,
+    /// 802.1Q VLAN on top of the parent link.
+    Vlan { link: u32, id: u16 },
+    Dummy,
+}
+
+impl LinkInfo {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LinkInfo::Veth { .. } => "veth",
+            LinkInfo::Bridge => "bridge",
+            LinkInfo::Vlan { .. } => "vlan",
+            LinkInfo::Dummy => "dummy",
+        }
+    }
+}
+
+/// Create a virtual link.
+///
+/// ```no_run
+/// use netlink::route::RouteController;
+/// use netlink::route::link::LinkRequest;
+///
+/// // sudo ip link add veth0 mtu 1400 up type veth peer name veth1
+/// let req = LinkRequest::veth("veth0", "veth1").mtu(1400).up();
+/// let mut buffer = [0u8; 1024];
+/// RouteController::new().unwrap().add_link(&req, &mut buffer).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct LinkRequest {
+    pub ifname: String,
+    pub info: LinkInfo,
+    pub addr: Option<MacAddr>,
+    pub mtu: Option<u32>,
+    pub master: Option<u32>,
+    pub up: bool,
+}
+
+impl LinkRequest {
+    pub fn new(ifname: &str, info: LinkInfo) -> Self {
+        Self {
+            ifname: ifname.to_string(),
+            info,
+            addr: None,
+            mtu: None,
+            master: None,
+            up: false,
+        }
+    }
+
+    pub fn veth(ifname: &str, peer: &str) -> Self {
+        Self::new(ifname, LinkInfo::Veth { peer: peer.to_string() })
+    }
+
+    pub fn bridge(ifname: &str) -> Self {
+        Self::new(ifname, LinkInfo::Bridge)
+    }
+
+    pub fn vlan(ifname: &str, link: u32, id: u16) -> Self {
+        Self::new(ifname, LinkInfo::Vlan { link, id })
+    }
+
+    pub fn dummy(ifname: &str) -> Self {
+        Self::new(ifname, LinkInfo::Dummy)
+    }
+
+    pub fn address(mut self, addr: MacAddr) -> Self {
+        self.addr = Some(addr);
+        self
+    }
+
+    pub fn mtu(mut self, mtu: u32) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// Enslave the link to a bridge (`ip link add ... master <bridge>`).
+    pub fn master(mut self, ifindex: u32) -> Self {
+        self.master = Some(ifindex);
+        self
+    }
+
+    pub fn up(mut self) -> Self {
+        self.up = true;
+        self
+    }
+
+    fn check(&self) -> Result<(), io::Error> {
+        check_ifname(&self.ifname)?;
+
+        match self.info {
+            LinkInfo::Veth { ref peer } => check_ifname(peer)?,
+            LinkInfo::Vlan { id, .. } => {
+                if id == 0 || id >= 4095 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid VLAN id"));
+                }
+            },
+            _ => { },
+        }
+
+        Ok(())
     }
-}
\ No newline at end of file
+
+    /// Write the `ifinfomsg` header and the attributes into the buffer, returns the written length.
+    pub fn emit(&self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+        self.check()?;
+
+        let mut attrs = NetlinkAttrBuilder::new();
+        attrs.put_strz(LinkAttrType::IFLA_IFNAME.into(), &self.ifname);
+        if let Some(addr) = self.addr {
+            attrs.put(LinkAttrType::IFLA_ADDRESS.into(), &addr.0);
+        }
+        if let Some(mtu) = self.mtu {
+            attrs.put_u32(LinkAttrType::IFLA_MTU.into(), mtu);
+        }
+        if let Some(master) = self.master {
+            attrs.put_u32(LinkAttrType::IFLA_MASTER.into(), master);
+        }
+
+        let mut info_data = NetlinkAttrBuilder::new();
+        match self.info {
+            LinkInfo::Veth { ref peer } => {
+                // struct ifinfomsg + attrs of the peer
+                let mut peer_data = NetlinkAttrBuilder::with_buffer(vec![0u8; LinkPacket::<&[u8]>::MIN_SIZE]);
+                peer_data.put_strz(LinkAttrType::IFLA_IFNAME.into(), peer);
+                info_data.put(VETH_INFO_PEER, peer_data.as_bytes());
+            },
+            LinkInfo::Vlan { link, id } => {
+                attrs.put_u32(LinkAttrType::IFLA_LINK.into(), link);
+                info_data.put_u16(IFLA_VLAN_ID, id);
+            },
+            LinkInfo::Bridge | LinkInfo::Dummy => { },
+        }
+
+        let mut link_info = NetlinkAttrBuilder::new();
+        link_info.put(LinkInfoAttrType::IFLA_INFO_KIND.into(), self.info.kind().as_bytes());
+        if !info_data.is_empty() {
+            link_info.put(LinkInfoAttrType::IFLA_INFO_DATA.into(), info_data.as_bytes());
+        }
+        attrs.put(LinkAttrType::IFLA_LINKINFO.into(), link_info.as_bytes());
+
+        let len = LinkPacket::<&[u8]>::MIN_SIZE + attrs.len();
+        if buffer.len() < len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is too small."));
+        }
+
+        let flags = if self.up { LinkFlags::IFF_UP } else { LinkFlags::from_bits_truncate(0) };
+
+        let mut link_packet = LinkPacket::new_unchecked(&mut buffer[..len]);
+        link_packet.set_family(AddressFamily::AF_UNSPEC);
+        link_packet.set_kind(LinkKind(0));
+        link_packet.set_ifindex(0);
+        link_packet.set_flags(flags);
+        link_packet.set_change(flags);
+        link_packet.payload_mut().copy_from_slice(attrs.as_bytes());
+
+        Ok(len)
+    }
+}
+
+pub(crate) fn check_ifname(ifname: &str) -> Result<(), io::Error> {
+    if ifname.is_empty() || ifname.len() > IF_NAMESIZE - 1 || ifname.contains('\0') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid interface name"));
+    }
+
+    Ok(())
+}
+
+
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum TunTapMode {
+    /// Layer 3 device, carries IP packets.
+    Tun,
+    /// Layer 2 device, carries Ethernet frames.
+    Tap,
+}
+
+const IFF_TUN: libc::c_short   = 0x0001;
+const IFF_TAP: libc::c_short   = 0x0002;
+const IFF_NO_PI: libc::c_short = 0x1000;
+
+// _IOW('T', 202, int), _IOW('T', 203, int), _IOW('T', 204, int)
+const TUNSETIFF: libc::c_ulong     = 0x400454ca;
+const TUNSETPERSIST: libc::c_ulong = 0x400454cb;
+const TUNSETOWNER: libc::c_ulong   = 0x400454cc;
+
+#[repr(C)]
+struct ifreq {
+    ifr_name: [u8; IF_NAMESIZE],
+    ifr_flags: libc::c_short,
+    _pad: [u8; 22],
+}
+
+/// Create a persistent tun/tap device (`ip tuntap add <ifname> mode <mode>`).
+///
+/// NOTE: The kernel does not support creating tun/tap devices through `RTM_NEWLINK`,
+///       it has to be done with the `TUNSETIFF` ioctl on `/dev/net/tun`.
+pub(crate) fn add_tuntap(ifname: &str, mode: TunTapMode, owner: Option<u32>) -> Result<(), io::Error> {
+    check_ifname(ifname)?;
+
+    let fd = unsafe { libc::open(b"/dev/net/tun\0".as_ptr() as *const _, libc::O_RDWR | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut req = ifreq { ifr_name: [0u8; IF_NAMESIZE], ifr_flags: 0, _pad: [0u8; 22] };
+    req.ifr_name[..ifname.len()].copy_from_slice(ifname.as_bytes());
+    req.ifr_flags = IFF_NO_PI | match mode {
+        TunTapMode::Tun => IFF_TUN,
+        TunTapMode::Tap => IFF_TAP,
+    };
+
+    // NOTE: The error is read before `close`, which may overwrite `errno`.
+    if unsafe { libc::ioctl(fd, TUNSETIFF as _, &mut req as *mut ifreq) } < 0 {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+
+    if let Some(uid) = owner {
+        if unsafe { libc::ioctl(fd, TUNSETOWNER as _, uid as libc::c_ulong) } < 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+    }
+
+    if unsafe { libc::ioctl(fd, TUNSETPERSIST as _, 1 as libc::c_ulong) } < 0 {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+
+    unsafe { libc::close(fd) };
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+fn test_link_info(link_packet: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    use crate::packet::NetlinkAttrs;
+
+    let link_packet = LinkPacket::new_checked(link_packet).unwrap();
+    let link_info = NetlinkAttrs::new(link_packet.payload())
+        .map(|attr| attr.unwrap())
+        .find(|attr| attr.kind() == LinkAttrType::IFLA_LINKINFO.0)
+        .unwrap();
+
+    let mut kind = Vec::new();
+    let mut info_data = Vec::new();
+    for attr in NetlinkAttrs::new(link_info.payload()) {
+        let attr = attr.unwrap();
+        if attr.kind() == LinkInfoAttrType::IFLA_INFO_KIND.0 {
+            kind = attr.payload().iter().cloned().take_while(|b| *b != 0).collect();
+        } else if attr.kind() == LinkInfoAttrType::IFLA_INFO_DATA.0 {
+            info_data = attr.payload().to_vec();
+        }
+    }
+    (kind, info_data)
+}
+
+#[test]
+fn test_link_request_emit() {
+    use crate::packet::NetlinkAttrs;
+
+    let mut buffer = [0u8; 1024];
+
+    let req = LinkRequest::veth("veth0", "veth1").address(MacAddr([2, 0, 0, 0, 0, 1])).mtu(1400).master(3).up();
+    let len = req.emit(&mut buffer).unwrap();
+    let link = Link::try_from(&buffer[..len]).unwrap();
+    assert_eq!(link.ifname.unwrap().to_string(), "veth0");
+    assert_eq!(link.addr.map(|addr| addr.0), Some([2, 0, 0, 0, 0, 1]));
+    assert_eq!(link.mtu, Some(1400));
+    assert_eq!(link.master, Some(3));
+    assert!(link.flags.contains(LinkFlags::IFF_UP));
+
+    let (kind, info_data) = test_link_info(&buffer[..len]);
+    assert_eq!(kind, b"veth");
+    // VETH_INFO_PEER carries an `ifinfomsg` followed by the attrs of the peer.
+    let peer = NetlinkAttrs::new(&info_data).next().unwrap().unwrap();
+    assert_eq!(peer.kind(), VETH_INFO_PEER);
+    let peer = Link::try_from(peer.payload()).unwrap();
+    assert_eq!(peer.ifname.unwrap().to_string(), "veth1");
+
+    let len = LinkRequest::vlan("eth0.100", 2, 100).emit(&mut buffer).unwrap();
+    let link = Link::try_from(&buffer[..len]).unwrap();
+    assert!(!link.flags.contains(LinkFlags::IFF_UP));
+    let (kind, info_data) = test_link_info(&buffer[..len]);
+    assert_eq!(kind, b"vlan");
+    let vlan_id = NetlinkAttrs::new(&info_data).next().unwrap().unwrap();
+    assert_eq!(vlan_id.kind(), IFLA_VLAN_ID);
+    assert_eq!(NativeEndian::read_u16(vlan_id.payload()), 100);
+    let parent = NetlinkAttrs::new(LinkPacket::new_checked(&buffer[..len]).unwrap().payload())
+        .map(|attr| attr.unwrap())
+        .find(|attr| attr.kind() == LinkAttrType::IFLA_LINK.0)
+        .unwrap();
+    assert_eq!(NativeEndian::read_u32(parent.payload()), 2);
+
+    for req in [LinkRequest::bridge("br0"), LinkRequest::dummy("dummy0")].iter() {
+        let len = req.emit(&mut buffer).unwrap();
+        let (kind, info_data) = test_link_info(&buffer[..len]);
+        assert_eq!(kind, req.info.kind().as_bytes());
+        assert!(info_data.is_empty());
+    }
+
+    assert!(LinkRequest::dummy("").emit(&mut buffer).is_err());
+    assert!(LinkRequest::dummy("a-very-long-ifname").emit(&mut buffer).is_err());
+    assert!(LinkRequest::veth("veth0", "a-very-long-ifname").emit(&mut buffer).is_err());
+    assert!(LinkRequest::vlan("eth0.4095", 2, 4095).emit(&mut buffer).is_err());
+    assert!(LinkRequest::dummy("dummy0").emit(&mut buffer[..16]).is_err());
+}
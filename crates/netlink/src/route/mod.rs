@@ -3,8 +3,7 @@
 // Adresses, links, neighbours, routing, traffic control, neighbour tables, …
 use crate::packet;
 use crate::socket::NetlinkSocket;
-
-use libc::IF_NAMESIZE;
+use crate::netns;
 
 use std::io;
 use std::net::IpAddr;
@@ -14,6 +13,8 @@ pub mod link;
 pub mod neigh;
 pub mod route;
 pub mod addr;
+pub mod rule;
+pub mod monitor;
 
 // Routing/neighbour discovery messages.
 pub struct RouteController {
@@ -119,71 +120,46 @@ impl RouteController {
         })
     }
 
-    pub fn add_link(&mut self, ifname: &str, mac_addr: packet::MacAddr, buffer: &mut [u8]) -> Result<(), io::Error> {
+    /// Create a virtual link, fails if the link already exists.
+    pub fn add_link(&mut self, req: &link::LinkRequest, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip link add br0 type bridge
         if unsafe { libc::getuid() != 0 } {
             return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
         }
 
-        if ifname.len() > IF_NAMESIZE - 1 {
-            return Err(io::Error::new(io::ErrorKind::Other, "Interface name is too long."));
+        let header_len = packet::NetlinkPacket::<&[u8]>::MIN_SIZE;
+        if buffer.len() < header_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is too small."));
         }
+        let payload_len = req.emit(&mut buffer[header_len..])?;
+        let nl_packet_len = header_len + payload_len;
 
-        let ifname_len  = ifname.len() + 1; // ends_with `\0`
-        let ifname_attr_len = packet::align(4 + ifname_len);
-        let hwaddr_attr_len = packet::align(4 + std::mem::size_of::<packet::MacAddr>());
-
-        let attrs_payload_len = ifname_attr_len + hwaddr_attr_len;
-        let nl_packet_len = packet::NetlinkPacket::<&[u8]>::MIN_SIZE + packet::LinkPacket::<&[u8]>::MIN_SIZE + attrs_payload_len;
-
-        let mut nl_packet = packet::NetlinkPacket::new_unchecked(buffer);
+        let mut nl_packet = packet::NetlinkPacket::new_unchecked(&mut buffer[..]);
         nl_packet.set_len(nl_packet_len as u32);
         nl_packet.set_kind(packet::Kind::RTM_NEWLINK);
-        nl_packet.set_flags(packet::Flags::NLM_F_CREATE | packet::Flags::NLM_F_EXCL | packet::Flags::NLM_F_ACK);
+        nl_packet.set_flags(packet::Flags::NLM_F_CREATE | packet::Flags::NLM_F_EXCL | packet::Flags::NLM_F_REQUEST
+            | packet::Flags::NLM_F_ACK);
         nl_packet.set_seq(0);
         nl_packet.set_pid(0);
 
-        let mut link_packet = packet::LinkPacket::new_unchecked(nl_packet.payload_mut());
-        link_packet.set_family(packet::AddressFamily::AF_UNSPEC);
-        link_packet.set_kind(packet::LinkKind::ARPHRD_ETHER);
-        link_packet.set_ifindex(0);
-        link_packet.set_flags(packet::LinkFlags::IFF_UP | packet::LinkFlags::IFF_RUNNING);
-        link_packet.set_change(packet::LinkFlags::from_bits_truncate(0));
-        
-        // Set attrs
-        let mut attrs_payload = link_packet.payload_mut();
-        debug_assert_eq!(attrs_payload_len, attrs_payload.len());
-
-        // ifname attr
-        let mut ifname_attr = packet::NetlinkAttrPacket::new_unchecked(&mut attrs_payload);
-        ifname_attr.set_len(ifname_attr_len as u16);
-        ifname_attr.set_kind(packet::LinkAttrType::IFLA_IFNAME.into());
-        let ifname_attr_payload = ifname_attr.payload_mut();
-        &mut ifname_attr_payload[..ifname.len()].copy_from_slice(ifname.as_bytes());
-        for x in &mut ifname_attr_payload[ifname.len()..] {
-            *x = 0;
+        {
+            let pkt = packet::NetlinkPacket::new_unchecked(&buffer[..nl_packet_len]);
+            trace!("try send netlink message:\n{}", pkt);
+            let link_pkt = packet::LinkPacket::new_unchecked(pkt.payload());
+            trace!("{}", link_pkt);
         }
 
-        // hwaddr attr
-        let mut hwaddr_attr = packet::NetlinkAttrPacket::new_unchecked(&mut attrs_payload[ifname_attr_len..]);
-        hwaddr_attr.set_len(hwaddr_attr_len as u16);
-        hwaddr_attr.set_kind(packet::LinkAttrType::IFLA_ADDRESS.into());
-        let hwaddr_attr_payload = hwaddr_attr.payload_mut();
+        self.nl_socket.request(buffer, nl_packet_len)
+    }
 
-        let mac_addr_len = std::mem::size_of::<packet::MacAddr>();  // 6
-        &mut hwaddr_attr_payload[..].copy_from_slice(&mac_addr.0);
-        for x in &mut hwaddr_attr_payload[mac_addr_len..] {
-            *x = 0;
+    /// Create a persistent tun/tap device, remove it with `remove_link`.
+    pub fn add_tuntap(&mut self, ifname: &str, mode: link::TunTapMode, owner: Option<u32>) -> Result<(), io::Error> {
+        // sudo ip tuntap add tun0 mode tun user 1000
+        if unsafe { libc::getuid() != 0 } {
+            return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
         }
 
-        let buffer = nl_packet.into_inner();
-
-        self.nl_socket.send(&buffer[..nl_packet_len])?;
-
-        let amt = self.nl_socket.recv(buffer)?;
-        trace!("read {} bytes from netlink socket.", amt);
-
-        // TODO: Check netlink message.
-        Ok(())
+        link::add_tuntap(ifname, mode, owner)
     }
 
     pub fn remove_link(&mut self, ifindex: i32, buffer: &mut [u8]) -> Result<(), io::Error> {
@@ -204,213 +180,342 @@ impl RouteController {
         let mut message = packet::nlmsg::new(header, ifinfo, payload);
         message.fill_size();
 
-        self.nl_socket.send(&message)?;
+        let nl_packet_len = message.size();
+        buffer[..nl_packet_len].copy_from_slice(message.as_ref());
 
-        let amt = self.nl_socket.recv(buffer)?;
-        trace!("read {} bytes from netlink socket.", amt);
+        self.nl_socket.request(buffer, nl_packet_len)
+    }
 
-        // TODO: Check netlink message.
-        Ok(())
+    pub fn set_link_up(&mut self, ifindex: u32, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip link set dev eth0 up
+        self.send_link_request(ifindex, packet::LinkFlags::IFF_UP, packet::LinkFlags::IFF_UP, &[], buffer)
+    }
+
+    pub fn set_link_down(&mut self, ifindex: u32, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip link set dev eth0 down
+        self.send_link_request(ifindex, packet::LinkFlags::from_bits_truncate(0), packet::LinkFlags::IFF_UP, &[], buffer)
+    }
+
+    pub fn set_link_mtu(&mut self, ifindex: u32, mtu: u32, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip link set dev eth0 mtu 1400
+        let mut attrs = packet::NetlinkAttrBuilder::new();
+        attrs.put_u32(packet::LinkAttrType::IFLA_MTU.into(), mtu);
+
+        self.send_link_request(ifindex, packet::LinkFlags::from_bits_truncate(0), packet::LinkFlags::from_bits_truncate(0), attrs.as_bytes(), buffer)
+    }
+
+    /// Rename the link, kernels before 6.2 require the link to be down.
+    pub fn set_link_name(&mut self, ifindex: u32, ifname: &str, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip link set dev eth0 name wan0
+        link::check_ifname(ifname)?;
+
+        let mut attrs = packet::NetlinkAttrBuilder::new();
+        attrs.put_strz(packet::LinkAttrType::IFLA_IFNAME.into(), ifname);
+
+        self.send_link_request(ifindex, packet::LinkFlags::from_bits_truncate(0), packet::LinkFlags::from_bits_truncate(0), attrs.as_bytes(), buffer)
+    }
+
+    pub fn set_link_address(&mut self, ifindex: u32, mac_addr: packet::MacAddr, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip link set dev eth0 address 02:00:00:00:00:01
+        let mut attrs = packet::NetlinkAttrBuilder::new();
+        attrs.put(packet::LinkAttrType::IFLA_ADDRESS.into(), &mac_addr.0);
+
+        self.send_link_request(ifindex, packet::LinkFlags::from_bits_truncate(0), packet::LinkFlags::from_bits_truncate(0), attrs.as_bytes(), buffer)
+    }
+
+    /// Enslave the link to a bridge, or release it with `None`.
+    pub fn set_link_master(&mut self, ifindex: u32, master: Option<u32>, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip link set dev eth0 master br0
+        // sudo ip link set dev eth0 nomaster
+        let mut attrs = packet::NetlinkAttrBuilder::new();
+        attrs.put_u32(packet::LinkAttrType::IFLA_MASTER.into(), master.unwrap_or(0));
+
+        self.send_link_request(ifindex, packet::LinkFlags::from_bits_truncate(0), packet::LinkFlags::from_bits_truncate(0), attrs.as_bytes(), buffer)
     }
 
-    pub fn add_addr(&mut self) -> Result<(), io::Error> {
-        // RTM_NEWADDR
+    /// Move the link into another network namespace (`ip link set dev <ifname> netns <name>`).
+    pub fn set_link_netns(&mut self, ifindex: u32, target: netns::Target, buffer: &mut [u8]) -> Result<(), io::Error> {
+        let mut attrs = packet::NetlinkAttrBuilder::new();
+        match target {
+            netns::Target::Fd(fd) => attrs.put_u32(packet::LinkAttrType::IFLA_NET_NS_FD.into(), fd as u32),
+            netns::Target::Pid(pid) => attrs.put_u32(packet::LinkAttrType::IFLA_NET_NS_PID.into(), pid),
+        };
+
+        self.send_link_request(ifindex, packet::LinkFlags::from_bits_truncate(0), packet::LinkFlags::from_bits_truncate(0), attrs.as_bytes(), buffer)
+    }
+
+    fn send_link_request(&mut self,
+                         ifindex: u32,
+                         flags: packet::LinkFlags,
+                         change: packet::LinkFlags,
+                         attrs: &[u8],
+                         buffer: &mut [u8]) -> Result<(), io::Error> {
         if unsafe { libc::getuid() != 0 } {
             return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
         }
 
-        unimplemented!()
+        let nl_packet_len = packet::NetlinkPacket::<&[u8]>::MIN_SIZE + packet::LinkPacket::<&[u8]>::MIN_SIZE + attrs.len();
+        if buffer.len() < nl_packet_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is too small."));
+        }
+
+        let mut nl_packet = packet::NetlinkPacket::new_unchecked(&mut buffer[..nl_packet_len]);
+        nl_packet.set_len(nl_packet_len as u32);
+        nl_packet.set_kind(packet::Kind::RTM_NEWLINK);
+        nl_packet.set_flags(packet::Flags::NLM_F_REQUEST | packet::Flags::NLM_F_ACK);
+        nl_packet.set_seq(0);
+        nl_packet.set_pid(0);
+
+        let mut link_packet = packet::LinkPacket::new_unchecked(nl_packet.payload_mut());
+        link_packet.set_family(packet::AddressFamily::AF_UNSPEC);
+        link_packet.set_kind(packet::LinkKind(0));
+        link_packet.set_ifindex(ifindex as i32);
+        link_packet.set_flags(flags);
+        link_packet.set_change(change);
+        link_packet.payload_mut().copy_from_slice(attrs);
+
+        {
+            let pkt = packet::NetlinkPacket::new_unchecked(&buffer[..nl_packet_len]);
+            trace!("try send netlink message:\n{}", pkt);
+            let link_pkt = packet::LinkPacket::new_unchecked(pkt.payload());
+            trace!("{}", link_pkt);
+        }
+
+        self.nl_socket.request(buffer, nl_packet_len)
     }
-    
-    pub fn remove_neighbour(&mut self) -> Result<(), io::Error> {
-        // RTM_DELNEIGH
+
+    pub fn add_addr(&mut self, req: &addr::AddrRequest, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip addr add 10.0.0.2/24 dev eth0
+        // sudo ip addr add fd00::2/64 dev eth0
+        let flags = packet::Flags::NLM_F_CREATE | packet::Flags::NLM_F_EXCL | packet::Flags::NLM_F_REQUEST
+            | packet::Flags::NLM_F_ACK;
+
+        self.send_addr_request(packet::Kind::RTM_NEWADDR, flags, req, buffer)
+    }
+
+    pub fn remove_addr(&mut self, ifindex: u32, addr: IpAddr, prefix_len: u8, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip addr del 10.0.0.2/24 dev eth0
+        let req = addr::AddrRequest::new(ifindex, addr, prefix_len);
+        let flags = packet::Flags::NLM_F_REQUEST | packet::Flags::NLM_F_ACK;
+
+        self.send_addr_request(packet::Kind::RTM_DELADDR, flags, &req, buffer)
+    }
+
+    fn send_addr_request(&mut self,
+                         kind: packet::Kind,
+                         flags: packet::Flags,
+                         req: &addr::AddrRequest,
+                         buffer: &mut [u8]) -> Result<(), io::Error> {
         if unsafe { libc::getuid() != 0 } {
             return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
         }
 
-        unimplemented!()
+        let header_len = packet::NetlinkPacket::<&[u8]>::MIN_SIZE;
+        let payload_len = req.emit(&mut buffer[header_len..])?;
+        let nl_packet_len = header_len + payload_len;
+
+        let mut nl_packet = packet::NetlinkPacket::new_unchecked(&mut buffer[..]);
+        nl_packet.set_len(nl_packet_len as u32);
+        nl_packet.set_kind(kind);
+        nl_packet.set_flags(flags);
+        nl_packet.set_seq(0);
+        nl_packet.set_pid(0);
+
+        {
+            let pkt = packet::NetlinkPacket::new_unchecked(&buffer[..nl_packet_len]);
+            trace!("try send netlink message:\n{}", pkt);
+            let addr_pkt = packet::AddrPacket::new_unchecked(pkt.payload());
+            trace!("{}", addr_pkt);
+        }
+
+        self.nl_socket.request(buffer, nl_packet_len)
     }
+    
+    /// Add an ARP (IPv4) or NDP (IPv6) entry, fails if the entry already exists.
+    pub fn add_neighbour(&mut self,
+                         ifindex: u32,
+                         dst_addr: IpAddr,
+                         hw_addr: packet::MacAddr,
+                         state: packet::NeighbourState,
+                         buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip neigh add 192.168.1.1 lladdr d4:ee:07:5a:67:40 dev eth0 nud permanent
+        let flags = packet::Flags::NLM_F_CREATE | packet::Flags::NLM_F_EXCL | packet::Flags::NLM_F_REQUEST
+            | packet::Flags::NLM_F_ACK;
 
-    pub fn get_route(&mut self, dst_addr: IpAddr, prefix_len: u8, buffer: &mut [u8]) -> Result<route::Route, io::Error> {
-        // ip route get 1.1.1.1
-        // ip route get 1.1.1.0/24
-        let address_family;
-        let scope;
-        let attr_dst_addr_len;
+        self.send_neighbour_request(packet::Kind::RTM_NEWNEIGH, flags, ifindex, dst_addr, Some(hw_addr), state, buffer)
+    }
 
-        if dst_addr.is_ipv4() {
-            assert!(prefix_len <= 32);
-            address_family = packet::AddressFamily::AF_INET;
-            attr_dst_addr_len = packet::align(4 + 4);
-        } else if dst_addr.is_ipv6() {
-            assert!(prefix_len <= 128);
-            address_family = packet::AddressFamily::AF_INET6;
-            attr_dst_addr_len = packet::align(4 + 16);
-        } else {
-            unreachable!();
+    /// Add an ARP (IPv4) or NDP (IPv6) entry, or override the existing one.
+    pub fn replace_neighbour(&mut self,
+                             ifindex: u32,
+                             dst_addr: IpAddr,
+                             hw_addr: packet::MacAddr,
+                             state: packet::NeighbourState,
+                             buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip neigh replace 192.168.1.1 lladdr d4:ee:07:5a:67:40 dev eth0 nud reachable
+        let flags = packet::Flags::NLM_F_CREATE | packet::Flags::NLM_F_REPLACE | packet::Flags::NLM_F_REQUEST
+            | packet::Flags::NLM_F_ACK;
+
+        self.send_neighbour_request(packet::Kind::RTM_NEWNEIGH, flags, ifindex, dst_addr, Some(hw_addr), state, buffer)
+    }
+
+    pub fn remove_neighbour(&mut self, ifindex: u32, dst_addr: IpAddr, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip neigh del 192.168.1.1 dev eth0
+        let flags = packet::Flags::NLM_F_REQUEST | packet::Flags::NLM_F_ACK;
+
+        self.send_neighbour_request(packet::Kind::RTM_DELNEIGH, flags, ifindex, dst_addr, None, packet::NeighbourState::NUD_NONE, buffer)
+    }
+
+    fn send_neighbour_request(&mut self,
+                              kind: packet::Kind,
+                              flags: packet::Flags,
+                              ifindex: u32,
+                              dst_addr: IpAddr,
+                              hw_addr: Option<packet::MacAddr>,
+                              state: packet::NeighbourState,
+                              buffer: &mut [u8]) -> Result<(), io::Error> {
+        if unsafe { libc::getuid() != 0 } {
+            return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
         }
 
-        if dst_addr.is_unspecified() {
-            // add default (0.0.0.0)
-            assert_eq!(prefix_len, 0);
-            scope = packet::RouteScope::RT_SCOPE_UNIVERSE;
-        } else {
-            scope = packet::RouteScope::RT_SCOPE_UNIVERSE;
+        let address_family = match dst_addr {
+            IpAddr::V4(_) => packet::AddressFamily::AF_INET,
+            IpAddr::V6(_) => packet::AddressFamily::AF_INET6,
+        };
+
+        let mut attrs = packet::NetlinkAttrBuilder::new();
+        attrs.put_addr(packet::NeighbourAttrType::NDA_DST.into(), dst_addr);
+        if let Some(hw_addr) = hw_addr {
+            attrs.put(packet::NeighbourAttrType::NDA_LLADDR.into(), &hw_addr.0);
         }
-        
-        let attrs_payload_len = attr_dst_addr_len;
-        let nl_packet_len = packet::NetlinkPacket::<&[u8]>::MIN_SIZE + packet::RoutePacket::<&[u8]>::MIN_SIZE + attrs_payload_len;
-        
-        let flags = packet::Flags::NLM_F_REQUEST;
 
-        let mut nl_packet = packet::NetlinkPacket::new_unchecked(buffer);
+        let header_len = packet::NetlinkPacket::<&[u8]>::MIN_SIZE;
+        let nl_packet_len = header_len + packet::NeighbourPacket::<&[u8]>::MIN_SIZE + attrs.len();
+        if buffer.len() < nl_packet_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is too small."));
+        }
+
+        let mut nl_packet = packet::NetlinkPacket::new_unchecked(&mut buffer[..nl_packet_len]);
         nl_packet.set_len(nl_packet_len as u32);
-        nl_packet.set_kind(packet::Kind::RTM_GETROUTE);
+        nl_packet.set_kind(kind);
         nl_packet.set_flags(flags);
         nl_packet.set_seq(0);
         nl_packet.set_pid(0);
 
-        let mut route_packet = packet::RoutePacket::new_unchecked(nl_packet.payload_mut());
-        route_packet.set_family(address_family);
-        route_packet.set_dst_len(prefix_len);
-        route_packet.set_src_len(0);
-        route_packet.set_tos(0);
-        route_packet.set_table(packet::RouteTable::RT_TABLE_UNSPEC);
-        route_packet.set_protocol(packet::RouteProtocol::RTPROT_UNSPEC);
-        route_packet.set_scope(scope);
-        route_packet.set_kind(packet::RouteType::RTN_UNSPEC);
-        route_packet.set_flags(packet::RouteFlags::RTM_F_LOOKUP_TABLE);
-        
-        // Set attrs
-        let mut attrs_payload = route_packet.payload_mut();
-        debug_assert_eq!(attrs_payload_len, attrs_payload.len());
+        let mut neigh_packet = packet::NeighbourPacket::new_unchecked(nl_packet.payload_mut());
+        neigh_packet.set_family(address_family);
+        neigh_packet.set_ifindex(ifindex as i32);
+        neigh_packet.set_state(state);
+        neigh_packet.set_flags(packet::NeighbourFlags::from_bits_truncate(0));
+        neigh_packet.set_kind(packet::RouteType::RTN_UNSPEC);
+        neigh_packet.payload_mut().copy_from_slice(attrs.as_bytes());
 
-        // dst_addr attr
-        let mut dst_addr_attr = packet::NetlinkAttrPacket::new_unchecked(&mut attrs_payload);
-        dst_addr_attr.set_len(attr_dst_addr_len as u16);
-        dst_addr_attr.set_kind(packet::RouteAttrType::RTA_DST.into());
-        let dst_addr_attr_payload = dst_addr_attr.payload_mut();
-        match dst_addr {
-            IpAddr::V4(v4_addr) => {
-                let dst_addr_data = v4_addr.octets();
-                &mut dst_addr_attr_payload[..dst_addr_data.len()].copy_from_slice(&dst_addr_data);
-                for x in &mut dst_addr_attr_payload[dst_addr_data.len()..] {
-                    *x = 0;
-                }
-            },
-            IpAddr::V6(v6_addr) => {
-                let dst_addr_data = v6_addr.octets();
-                &mut dst_addr_attr_payload[..dst_addr_data.len()].copy_from_slice(&dst_addr_data);
-                for x in &mut dst_addr_attr_payload[dst_addr_data.len()..] {
-                    *x = 0;
-                }
-            },
-        };
+        {
+            let pkt = packet::NetlinkPacket::new_unchecked(&buffer[..nl_packet_len]);
+            trace!("try send netlink message:\n{}", pkt);
+            let neigh_pkt = packet::NeighbourPacket::new_unchecked(pkt.payload());
+            trace!("{}", neigh_pkt);
+        }
 
-        let buffer = nl_packet.into_inner();
+        self.nl_socket.request(buffer, nl_packet_len)
+    }
 
-        self.nl_socket.send(&buffer[..nl_packet_len])?;
-        for x in &mut buffer[..] {
-            *x = 0;
-        }
+    pub fn rules<'a, 'b>(&'a mut self, buffer: &'b mut [u8]) -> Result<rule::Rules<'a, 'b>, io::Error> {
+        // ip rule list
+        let mut header = packet::nlmsghdr::default();
+        let rule_hdr = packet::fib_rule_hdr::default();
+        let payload = ();
+
+        header.nlmsg_type  = packet::Kind::RTM_GETRULE.into();
+        header.nlmsg_flags = (packet::Flags::NLM_F_REQUEST | packet::Flags::NLM_F_DUMP).into();
+        
+        let mut message = packet::nlmsg::new(header, rule_hdr, payload);
+        message.fill_size();
+
+        self.nl_socket.send(&message)?;
+
+        Ok(rule::Rules {
+            socket: &mut self.nl_socket,
+            buffer: buffer,
+            is_done: false,
+            buffer_len: 0,
+            offset: 0,
+        })
+    }
 
-        let amt = self.nl_socket.recv(buffer)?;
-        debug!("read {} bytes from netlink socket.", amt);
+    /// Add a policy routing rule, fails if the same rule already exists.
+    pub fn add_rule(&mut self, rule: &rule::Rule, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip rule add from 10.0.0.0/8 lookup 100 priority 1000
+        let flags = packet::Flags::NLM_F_CREATE | packet::Flags::NLM_F_EXCL | packet::Flags::NLM_F_REQUEST
+            | packet::Flags::NLM_F_ACK;
 
-        let nl_pkt = packet::NetlinkPacket::new_checked(&buffer[..amt])?;
+        self.send_rule_request(packet::Kind::RTM_NEWRULE, flags, rule, buffer)
+    }
 
-        let rt = route::Route::try_from(nl_pkt.payload())?;
+    /// Remove the first rule which matches the given one.
+    pub fn remove_rule(&mut self, rule: &rule::Rule, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip rule del from 10.0.0.0/8 lookup 100 priority 1000
+        let flags = packet::Flags::NLM_F_REQUEST | packet::Flags::NLM_F_ACK;
 
-        Ok(rt)
+        self.send_rule_request(packet::Kind::RTM_DELRULE, flags, rule, buffer)
     }
 
-    pub fn add_route(&mut self,
-                     dst_addr: IpAddr,
-                     prefix_len: u8,
-                     gateway: Option<IpAddr>,
-                     ifindex: Option<u32>,
-                     buffer: &mut [u8]) -> Result<(), io::Error> {
+    fn send_rule_request(&mut self,
+                         kind: packet::Kind,
+                         flags: packet::Flags,
+                         rule: &rule::Rule,
+                         buffer: &mut [u8]) -> Result<(), io::Error> {
         if unsafe { libc::getuid() != 0 } {
             return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
         }
 
-        let address_family;
-        let scope;
-        let attr_dst_addr_len;
-        let attr_ifindex_len;
-        let attr_gateway_len;
-
-        match (ifindex, gateway) {
-            (Some(_), Some(_)) => {
-                // sudo ip route add 1.1.1.1    via 192.168.1.100 dev eth0
-                // sudo ip route add 1.1.1.0/24 via 192.168.1.100 dev eth0
-            },
-            (Some(_), None) => {
-                // sudo ip route add 1.1.1.1    dev enp0s3
-                // sudo ip route add 1.1.1.0/24 dev enp0s3
-            },
-            (None, Some(_)) => {
-                // sudo ip route add 1.1.1.1    via 192.168.1.1
-                // sudo ip route add 1.1.1.0/24 via 192.168.1.1
-            },
-            (None, None) => {
-                return Err(io::Error::new(io::ErrorKind::Other, "gateway or ifindex provide at least one."));
-            },
+        let header_len = packet::NetlinkPacket::<&[u8]>::MIN_SIZE;
+        if buffer.len() < header_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is too small."));
         }
+        let payload_len = rule.emit(&mut buffer[header_len..])?;
+        let nl_packet_len = header_len + payload_len;
 
-        if ifindex.is_some() {
-            attr_ifindex_len = packet::align(4 + 4);
-        } else {
-            attr_ifindex_len = 0;
-        }
+        let mut nl_packet = packet::NetlinkPacket::new_unchecked(&mut buffer[..]);
+        nl_packet.set_len(nl_packet_len as u32);
+        nl_packet.set_kind(kind);
+        nl_packet.set_flags(flags);
+        nl_packet.set_seq(0);
+        nl_packet.set_pid(0);
 
-        match gateway {
-            Some(gateway_addr) => {
-                if gateway_addr.is_ipv4() {
-                    attr_gateway_len = packet::align(4 + 4);
-                } else if gateway_addr.is_ipv6() {
-                    attr_gateway_len = packet::align(4 + 16);
-                } else {
-                     unreachable!();
-                }
-            },
-            None => {
-                attr_gateway_len = 0;
-            }
+        {
+            let pkt = packet::NetlinkPacket::new_unchecked(&buffer[..nl_packet_len]);
+            trace!("try send netlink message:\n{}", pkt);
+            let rule_pkt = packet::RulePacket::new_unchecked(pkt.payload());
+            trace!("{}", rule_pkt);
         }
 
-        if dst_addr.is_ipv4() {
-            assert!(prefix_len <= 32);
-            address_family = packet::AddressFamily::AF_INET;
-            attr_dst_addr_len = packet::align(4 + 4);
-        } else if dst_addr.is_ipv6() {
-            assert!(prefix_len <= 128);
-            address_family = packet::AddressFamily::AF_INET6;
-            attr_dst_addr_len = packet::align(4 + 16);
-        } else {
-            unreachable!();
-        }
+        self.nl_socket.request(buffer, nl_packet_len)
+    }
 
-        if dst_addr.is_unspecified() {
-            // add default (0.0.0.0)
-            assert_eq!(prefix_len, 0);
-            scope = packet::RouteScope::RT_SCOPE_UNIVERSE;
+    pub fn get_route(&mut self, dst_addr: IpAddr, prefix_len: u8, buffer: &mut [u8]) -> Result<route::Route, io::Error> {
+        // ip route get 1.1.1.1
+        // ip route get 1.1.1.0/24
+        let address_family = if dst_addr.is_ipv4() {
+            assert!(prefix_len <= 32);
+            packet::AddressFamily::AF_INET
         } else {
-            scope = packet::RouteScope::RT_SCOPE_LINK;
-            // scope = packet::RouteScope::RT_SCOPE_NOWHERE;
-            // scope = packet::RouteScope::RT_SCOPE_UNIVERSE;
-        }
+            assert!(prefix_len <= 128);
+            packet::AddressFamily::AF_INET6
+        };
 
-        let attrs_payload_len = attr_dst_addr_len + attr_ifindex_len + attr_gateway_len;
-        let nl_packet_len = packet::NetlinkPacket::<&[u8]>::MIN_SIZE + packet::RoutePacket::<&[u8]>::MIN_SIZE + attrs_payload_len;
+        let mut attrs = packet::NetlinkAttrBuilder::new();
+        attrs.put_addr(packet::RouteAttrType::RTA_DST.into(), dst_addr);
 
-        let flags = packet::Flags::NLM_F_CREATE | packet::Flags::NLM_F_EXCL | packet::Flags::NLM_F_REQUEST
-            | packet::Flags::NLM_F_ACK;
+        let nl_packet_len = packet::NetlinkPacket::<&[u8]>::MIN_SIZE + packet::RoutePacket::<&[u8]>::MIN_SIZE + attrs.len();
+        if buffer.len() < nl_packet_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is too small."));
+        }
 
-        let mut nl_packet = packet::NetlinkPacket::new_unchecked(buffer);
+        let mut nl_packet = packet::NetlinkPacket::new_unchecked(&mut buffer[..nl_packet_len]);
         nl_packet.set_len(nl_packet_len as u32);
-        nl_packet.set_kind(packet::Kind::RTM_NEWROUTE);
-        nl_packet.set_flags(flags);
+        nl_packet.set_kind(packet::Kind::RTM_GETROUTE);
+        nl_packet.set_flags(packet::Flags::NLM_F_REQUEST);
         nl_packet.set_seq(0);
         nl_packet.set_pid(0);
 
@@ -419,112 +524,82 @@ impl RouteController {
         route_packet.set_dst_len(prefix_len);
         route_packet.set_src_len(0);
         route_packet.set_tos(0);
-        route_packet.set_table(packet::RouteTable::RT_TABLE_MAIN);
-        route_packet.set_protocol(packet::RouteProtocol::RTPROT_BOOT);
-        route_packet.set_scope(scope);
-        route_packet.set_kind(packet::RouteType::RTN_UNICAST);
-        route_packet.set_flags(packet::RouteFlags::from_bits_truncate(0));
-        
-        // Set attrs
-        let mut attrs_payload = route_packet.payload_mut();
-        debug_assert_eq!(attrs_payload_len, attrs_payload.len());
-
-        // dst_addr attr
-        let mut dst_addr_attr = packet::NetlinkAttrPacket::new_unchecked(&mut attrs_payload);
-        dst_addr_attr.set_len(attr_dst_addr_len as u16);
-        dst_addr_attr.set_kind(packet::RouteAttrType::RTA_DST.into());
-        let dst_addr_attr_payload = dst_addr_attr.payload_mut();
-        match dst_addr {
-            IpAddr::V4(v4_addr) => {
-                let dst_addr_data = v4_addr.octets();
-                &mut dst_addr_attr_payload[..dst_addr_data.len()].copy_from_slice(&dst_addr_data);
-                for x in &mut dst_addr_attr_payload[dst_addr_data.len()..] {
-                    *x = 0;
-                }
-            },
-            IpAddr::V6(v6_addr) => {
-                let dst_addr_data = v6_addr.octets();
-                &mut dst_addr_attr_payload[..dst_addr_data.len()].copy_from_slice(&dst_addr_data);
-                for x in &mut dst_addr_attr_payload[dst_addr_data.len()..] {
-                    *x = 0;
-                }
-            },
-        };
-        
-        // RTA_VIA
-        // gateway_addr attr
-        if gateway.is_some() {
-            let mut gateway_addr_attr = packet::NetlinkAttrPacket::new_unchecked(&mut attrs_payload[attr_dst_addr_len..]);
-            gateway_addr_attr.set_len(attr_gateway_len as u16);
-            gateway_addr_attr.set_kind(packet::RouteAttrType::RTA_GATEWAY.into());
-            let gateway_addr_attr_payload = gateway_addr_attr.payload_mut();
-            match gateway.unwrap() {
-                IpAddr::V4(v4_addr) => {
-                    let gateway_addr_data = v4_addr.octets();
-                    &mut gateway_addr_attr_payload[..gateway_addr_data.len()].copy_from_slice(&gateway_addr_data);
-                    for x in &mut gateway_addr_attr_payload[gateway_addr_data.len()..] {
-                        *x = 0;
-                    }
-                },
-                IpAddr::V6(v6_addr) => {
-                    let gateway_addr_data = v6_addr.octets();
-                    &mut gateway_addr_attr_payload[..gateway_addr_data.len()].copy_from_slice(&gateway_addr_data);
-                    for x in &mut gateway_addr_attr_payload[gateway_addr_data.len()..] {
-                        *x = 0;
-                    }
-                },
-            };
-        }
-
-        // out_ifindex attr
-        if ifindex.is_some() {
-            let offset = if gateway.is_some() { attr_dst_addr_len + attr_gateway_len } else { attr_dst_addr_len };
-            let mut out_ifindex_attr = packet::NetlinkAttrPacket::new_unchecked(&mut attrs_payload[offset..]);
-            
-            out_ifindex_attr.set_len(attr_ifindex_len as u16);
-            out_ifindex_attr.set_kind(packet::RouteAttrType::RTA_OIF.into());
-            let out_ifindex_attr_payload = out_ifindex_attr.payload_mut();
-            let ifindex = ifindex.unwrap();
-            &mut out_ifindex_attr_payload[..4].copy_from_slice(&ifindex.to_ne_bytes());
-            for x in &mut out_ifindex_attr_payload[4..] {
-                *x = 0;
-            }
-        }
-
-        let buffer = nl_packet.into_inner();
+        route_packet.set_table(packet::RouteTable::RT_TABLE_UNSPEC);
+        route_packet.set_protocol(packet::RouteProtocol::RTPROT_UNSPEC);
+        route_packet.set_scope(packet::RouteScope::RT_SCOPE_UNIVERSE);
+        route_packet.set_kind(packet::RouteType::RTN_UNSPEC);
+        route_packet.set_flags(packet::RouteFlags::RTM_F_LOOKUP_TABLE);
+        route_packet.payload_mut().copy_from_slice(attrs.as_bytes());
 
         {
-            let pkt = packet::NetlinkPacket::new_unchecked(&buffer);
+            let pkt = packet::NetlinkPacket::new_unchecked(&buffer[..nl_packet_len]);
             trace!("try send netlink message:\n{}", pkt);
             let rt_pkt = packet::RoutePacket::new_unchecked(pkt.payload());
             trace!("{}", rt_pkt);
         }
-        for x in &mut buffer[nl_packet_len..nl_packet_len] {
-            *x = 0;
+
+        let reply = self.nl_socket.request_reply(buffer, nl_packet_len)?;
+        let nl_pkt = packet::NetlinkPacket::new_checked(&buffer[reply])?;
+        if nl_pkt.kind() != packet::Kind::RTM_NEWROUTE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected reply: {}", nl_pkt.kind())));
         }
 
-        self.nl_socket.send(&buffer[..nl_packet_len])?;
-        for x in &mut buffer[..] {
-            *x = 0;
+        route::Route::try_from(nl_pkt.payload())
+    }
+
+    /// Add a route, fails if the route already exists.
+    pub fn add_route(&mut self, req: &route::RouteRequest, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip route add 1.1.1.0/24 via 192.168.1.100 dev eth0
+        // sudo ip route add 1.1.1.0/24 dev enp0s3
+        // sudo ip route add 1.1.1.0/24 via 192.168.1.1
+        let flags = packet::Flags::NLM_F_CREATE | packet::Flags::NLM_F_EXCL | packet::Flags::NLM_F_REQUEST
+            | packet::Flags::NLM_F_ACK;
+
+        self.send_route_request(flags, req, buffer)
+    }
+
+    /// Add a route, or override the existing one which has the same destination, table and priority.
+    pub fn replace_route(&mut self, req: &route::RouteRequest, buffer: &mut [u8]) -> Result<(), io::Error> {
+        // sudo ip route replace 1.1.1.0/24 via 192.168.1.1
+        let flags = packet::Flags::NLM_F_CREATE | packet::Flags::NLM_F_REPLACE | packet::Flags::NLM_F_REQUEST
+            | packet::Flags::NLM_F_ACK;
+
+        self.send_route_request(flags, req, buffer)
+    }
+
+    fn send_route_request(&mut self,
+                          flags: packet::Flags,
+                          req: &route::RouteRequest,
+                          buffer: &mut [u8]) -> Result<(), io::Error> {
+        if unsafe { libc::getuid() != 0 } {
+            return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
         }
 
-        let amt = self.nl_socket.recv(buffer)?;
-        debug!("read {} bytes from netlink socket.", amt);
-
-        let pkt = packet::NetlinkPacket::new_checked(&buffer[..amt])?;
-        trace!("{}", pkt);
-        let err_pkt = packet::NetlinkErrorPacket::new_unchecked(&pkt.payload()[..]);
-        if err_pkt.errorno() != 0 {
-            // TODO: 检查详细的错误类型？
-            //       比如要删除的地址并不存在等。
-            error!("{}", err_pkt);
-            return Err(err_pkt.err());
+        let header_len = packet::NetlinkPacket::<&[u8]>::MIN_SIZE;
+        if buffer.len() < header_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is too small."));
         }
+        let payload_len = req.emit(&mut buffer[header_len..])?;
+        let nl_packet_len = header_len + payload_len;
 
-        Ok(())
+        let mut nl_packet = packet::NetlinkPacket::new_unchecked(&mut buffer[..]);
+        nl_packet.set_len(nl_packet_len as u32);
+        nl_packet.set_kind(packet::Kind::RTM_NEWROUTE);
+        nl_packet.set_flags(flags);
+        nl_packet.set_seq(0);
+        nl_packet.set_pid(0);
+
+        {
+            let pkt = packet::NetlinkPacket::new_unchecked(&buffer[..nl_packet_len]);
+            trace!("try send netlink message:\n{}", pkt);
+            let rt_pkt = packet::RoutePacket::new_unchecked(pkt.payload());
+            trace!("{}", rt_pkt);
+        }
+
+        self.nl_socket.request(buffer, nl_packet_len)
     }
     
-    pub fn remove_route(&mut self, dst_addr: IpAddr, prefix_len: u8, buffer: &mut [u8]) -> Result<(), io::Error> {
+    pub fn remove_route(&mut self, dst_addr: IpAddr, prefix_len: u8, table: packet::RouteTable, buffer: &mut [u8]) -> Result<(), io::Error> {
         // sudo ip route del 1.1.1.1
         // sudo ip route del 1.1.1.1/24
         if unsafe { libc::getuid() != 0 } {
@@ -547,16 +622,17 @@ impl RouteController {
             unreachable!();
         }
 
-        if dst_addr.is_unspecified() {
-            // add default (0.0.0.0)
-            assert_eq!(prefix_len, 0);
+        if prefix_len == 0 {
+            // default route (0.0.0.0/0)
             scope = packet::RouteScope::RT_SCOPE_UNIVERSE;
         } else {
             // scope = packet::RouteScope::RT_SCOPE_LINK;
             scope = packet::RouteScope::RT_SCOPE_NOWHERE;
         }
 
-        let attrs_payload_len = attr_dst_addr_len;
+        let attr_table_len = packet::align(4 + 4);
+
+        let attrs_payload_len = attr_dst_addr_len + attr_table_len;
         let nl_packet_len = packet::NetlinkPacket::<&[u8]>::MIN_SIZE + packet::RoutePacket::<&[u8]>::MIN_SIZE + attrs_payload_len;
 
         let mut nl_packet = packet::NetlinkPacket::new_unchecked(buffer);
@@ -571,7 +647,7 @@ impl RouteController {
         route_packet.set_dst_len(prefix_len);
         route_packet.set_src_len(0);
         route_packet.set_tos(0);
-        route_packet.set_table(packet::RouteTable::RT_TABLE_MAIN);
+        route_packet.set_table(table);
         route_packet.set_protocol(packet::RouteProtocol::RTPROT_UNSPEC);
         route_packet.set_scope(scope);
         route_packet.set_kind(packet::RouteType::RTN_UNSPEC);
@@ -603,6 +679,12 @@ impl RouteController {
             },
         };
 
+        // table attr
+        let mut table_attr = packet::NetlinkAttrPacket::new_unchecked(&mut attrs_payload[attr_dst_addr_len..]);
+        table_attr.set_len(attr_table_len as u16);
+        table_attr.set_kind(packet::RouteAttrType::RTA_TABLE.into());
+        table_attr.payload_mut()[..4].copy_from_slice(&table.0.to_ne_bytes());
+
         let buffer = nl_packet.into_inner();
 
         {
@@ -611,28 +693,25 @@ impl RouteController {
             let rt_pkt = packet::RoutePacket::new_unchecked(pkt.payload());
             trace!("{}", rt_pkt);
         }
-        for x in &mut buffer[nl_packet_len..nl_packet_len] {
-            *x = 0;
-        }
-
-        self.nl_socket.send(&buffer[..nl_packet_len])?;
-        for x in &mut buffer[..] {
-            *x = 0;
-        }
-
-        let amt = self.nl_socket.recv(buffer)?;
-        trace!("read {} bytes from netlink socket.", amt);
-
-        let pkt = packet::NetlinkPacket::new_checked(&buffer[..amt])?;
-        trace!("{}", pkt);
-        let err_pkt = packet::NetlinkErrorPacket::new_unchecked(&pkt.payload()[..]);
-        if err_pkt.errorno() != 0 {
-            // TODO: 检查详细的错误类型？
-            //       比如要删除的地址并不存在等。
-            error!("{}", err_pkt);
-            return Err(err_pkt.err());
-        }
+        self.nl_socket.request(buffer, nl_packet_len)?;
         
         Ok(())
     }
 }
+
+
+#[test]
+fn test_get_route() {
+    let mut buffer = packet::alloc();
+    let mut route_controller = RouteController::new().unwrap();
+
+    // ip route get 127.0.0.1
+    let route = route_controller.get_route(IpAddr::from([127, 0, 0, 1]), 32, &mut buffer).unwrap();
+    assert_eq!(route.kind, packet::RouteType::RTN_LOCAL);
+    assert_eq!(route.table, packet::RouteTable::RT_TABLE_LOCAL);
+    assert_eq!(route.out_ifindex, Some(1));
+
+    // The socket is left without pending replies, the next request still works.
+    let route = route_controller.get_route(IpAddr::from([127, 0, 0, 2]), 32, &mut buffer).unwrap();
+    assert_eq!(route.dst_cidr.map(|cidr| cidr.address()), Some(IpAddr::from([127, 0, 0, 2]).into()));
+}
@@ -0,0 +1,244 @@
+use crate::socket::NetlinkSocket;
+use crate::packet;
+use crate::packet::Kind;
+use crate::packet::LinkFlags;
+use crate::packet::MulticastGroups;
+use crate::packet::NetlinkPacket;
+use crate::packet::NetlinkErrorPacket;
+
+use super::link::Link;
+use super::addr::Addr;
+use super::route::Route;
+use super::neigh::Neighbour;
+
+use std::io;
+use std::convert::TryFrom;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+
+/// Changes reported by the kernel on the subscribed multicast groups.
+#[derive(Debug, Clone)]
+pub enum Event {
+    NewLink(Link),
+    DelLink(Link),
+    NewAddr(Addr),
+    DelAddr(Addr),
+    NewRoute(Route),
+    DelRoute(Route),
+    NewNeighbour(Neighbour),
+    DelNeighbour(Neighbour),
+}
+
+impl Event {
+    /// Link added or changed and the interface is up (`ip link set dev <ifname> up`).
+    pub fn is_link_up(&self) -> bool {
+        match self {
+            Event::NewLink(link) => link.flags.contains(LinkFlags::IFF_UP),
+            _ => false,
+        }
+    }
+
+    /// Link removed or changed and the interface is down.
+    pub fn is_link_down(&self) -> bool {
+        match self {
+            Event::NewLink(link) => !link.flags.contains(LinkFlags::IFF_UP),
+            Event::DelLink(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Parse the payload of a `RTM_NEW*` or `RTM_DEL*` message, returns `None` for other messages.
+    pub fn parse(kind: Kind, payload: &[u8]) -> Result<Option<Self>, io::Error> {
+        let event = match kind {
+            Kind::RTM_NEWLINK  => Event::NewLink(Link::try_from(payload)?),
+            Kind::RTM_DELLINK  => Event::DelLink(Link::try_from(payload)?),
+            Kind::RTM_NEWADDR  => Event::NewAddr(Addr::try_from(payload)?),
+            Kind::RTM_DELADDR  => Event::DelAddr(Addr::try_from(payload)?),
+            Kind::RTM_NEWROUTE => Event::NewRoute(Route::try_from(payload)?),
+            Kind::RTM_DELROUTE => Event::DelRoute(Route::try_from(payload)?),
+            Kind::RTM_NEWNEIGH => Event::NewNeighbour(Neighbour::try_from(payload)?),
+            Kind::RTM_DELNEIGH => Event::DelNeighbour(Neighbour::try_from(payload)?),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(event))
+    }
+}
+
+
+/// Subscribe to link, address, route and neighbour changes (`ip monitor`).
+///
+/// ```no_run
+/// use netlink::route::monitor::Monitor;
+///
+/// let mut monitor = Monitor::new(Monitor::default_groups()).unwrap();
+/// for event in &mut monitor {
+///     println!("{:?}", event);
+/// }
+/// ```
+pub struct Monitor {
+    nl_socket: NetlinkSocket,
+    buffer: Vec<u8>,
+    buffer_len: usize,
+    offset: usize,
+}
+
+impl Monitor {
+    pub fn new(groups: MulticastGroups) -> Result<Self, io::Error> {
+        let mut nl_socket = NetlinkSocket::new(packet::Protocol::NETLINK_ROUTE.into())?;
+
+        let pid = 0;
+        nl_socket.bind(pid, groups.into())?;
+
+        Ok(Self {
+            nl_socket,
+            buffer: packet::alloc().to_vec(),
+            buffer_len: 0,
+            offset: 0,
+        })
+    }
+
+    /// Link, IPv4/IPv6 address, IPv4/IPv6 route and neighbour changes.
+    pub fn default_groups() -> MulticastGroups {
+        MulticastGroups::RTMGRP_LINK
+            | MulticastGroups::RTMGRP_NEIGH
+            | MulticastGroups::RTMGRP_IPV4_IFADDR
+            | MulticastGroups::RTMGRP_IPV6_IFADDR
+            | MulticastGroups::RTMGRP_IPV4_ROUTE
+            | MulticastGroups::RTMGRP_IPV6_ROUTE
+    }
+
+    /// Set underlying socket file descriptor to be non blocking,
+    /// `next_event` returns `WouldBlock` once there is no more event to read.
+    pub fn set_nonblock(&mut self) -> Result<(), io::Error> {
+        self.nl_socket.set_nonblock()
+    }
+
+    /// Wait for the next event, messages which are not events are skipped.
+    pub fn next_event(&mut self) -> Result<Event, io::Error> {
+        loop {
+            if self.offset >= self.buffer_len {
+                // NOTE: reset before reading, so a failed (e.g. `WouldBlock`) read does not replay old events.
+                self.buffer_len = 0;
+                self.offset = 0;
+
+                let amt = self.nl_socket.recv(&mut self.buffer)?;
+                trace!("read {} bytes from netlink socket.", amt);
+                self.buffer_len = amt;
+            }
+
+            let start = self.offset;
+            let pkt = match NetlinkPacket::new_checked(&self.buffer[start..self.buffer_len]) {
+                Ok(pkt) => pkt,
+                Err(e) => {
+                    self.offset = self.buffer_len;
+                    return Err(e);
+                },
+            };
+            self.offset += std::cmp::max(pkt.total_len(), NetlinkPacket::<&[u8]>::MIN_SIZE);
+
+            let payload = pkt.payload();
+            match pkt.kind() {
+                Kind::NLMSG_ERROR => {
+                    let err_pkt = NetlinkErrorPacket::new_checked(payload)?;
+                    if err_pkt.errorno() == 0 {
+                        continue;
+                    }
+                    return Err(err_pkt.err());
+                },
+                Kind::NLMSG_OVERRUN => return Err(io::Error::new(io::ErrorKind::InvalidData, "Overrun")),
+                kind => match Event::parse(kind, payload)? {
+                    Some(event) => return Ok(event),
+                    None => {
+                        trace!("Droped netlink message: {:?}", kind);
+                        continue;
+                    },
+                },
+            }
+        }
+    }
+}
+
+impl Iterator for Monitor {
+    type Item = Result<Event, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_event())
+    }
+}
+
+impl AsRawFd for Monitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.nl_socket.as_raw_fd()
+    }
+}
+
+#[cfg(feature = "mio")]
+mod mio {
+    use std::io;
+
+    use mio::{Ready, Poll, PollOpt, Token};
+    use mio::event::Evented;
+
+    impl Evented for super::Monitor {
+        fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+            self.nl_socket.register(poll, token, interest, opts)
+        }
+
+        fn reregister(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+            self.nl_socket.reregister(poll, token, interest, opts)
+        }
+
+        fn deregister(&self, poll: &Poll) -> io::Result<()> {
+            self.nl_socket.deregister(poll)
+        }
+    }
+}
+
+
+#[test]
+fn test_event_parse() {
+    use super::link::LinkRequest;
+    use super::addr::AddrRequest;
+    use super::route::RouteRequest;
+
+    let mut buffer = [0u8; 1024];
+
+    let len = LinkRequest::bridge("br0").mtu(1400).up().emit(&mut buffer).unwrap();
+    let event = Event::parse(Kind::RTM_NEWLINK, &buffer[..len]).unwrap().unwrap();
+    assert!(event.is_link_up());
+    match event {
+        Event::NewLink(link) => {
+            assert_eq!(link.ifname.unwrap().to_string(), "br0");
+            assert_eq!(link.mtu, Some(1400));
+        },
+        event => panic!("unexpected event: {:?}", event),
+    }
+    let event = Event::parse(Kind::RTM_DELLINK, &buffer[..len]).unwrap().unwrap();
+    assert!(event.is_link_down());
+
+    let len = LinkRequest::bridge("br0").emit(&mut buffer).unwrap();
+    assert!(Event::parse(Kind::RTM_NEWLINK, &buffer[..len]).unwrap().unwrap().is_link_down());
+
+    let len = AddrRequest::new(2, "10.0.0.2".parse().unwrap(), 24).emit(&mut buffer).unwrap();
+    match Event::parse(Kind::RTM_DELADDR, &buffer[..len]).unwrap().unwrap() {
+        Event::DelAddr(addr) => {
+            assert_eq!(addr.ifindex, 2);
+            assert_eq!(addr.prefix_len, 24);
+            assert_eq!(addr.local, Some("10.0.0.2".parse().unwrap()));
+        },
+        event => panic!("unexpected event: {:?}", event),
+    }
+
+    let len = RouteRequest::new("10.0.0.0".parse().unwrap(), 8).ifindex(2).emit(&mut buffer).unwrap();
+    match Event::parse(Kind::RTM_NEWROUTE, &buffer[..len]).unwrap().unwrap() {
+        Event::NewRoute(route) => {
+            assert_eq!(route.dst_cidr.map(|cidr| cidr.prefix_len()), Some(8));
+            assert_eq!(route.out_ifindex, Some(2));
+        },
+        event => panic!("unexpected event: {:?}", event),
+    }
+
+    assert!(Event::parse(Kind::NLMSG_DONE, &buffer[..len]).unwrap().is_none());
+    assert!(Event::parse(Kind::RTM_NEWROUTE, &buffer[..4]).is_err());
+}
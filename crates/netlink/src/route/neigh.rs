@@ -14,6 +14,7 @@ use crate::packet::NeighbourFlags;
 use byteorder::{ByteOrder, NetworkEndian};
 
 use std::io;
+use std::convert::TryFrom;
 
 // Routing/neighbour discovery messages.
 
@@ -83,10 +84,15 @@ impl<'a, 'b> Iterator for Neighbours<'a, 'b> {
             Err(e) => return Some(Err(e)),
         };
 
-        let packet = match NeighbourPacket::new_checked(pkt.payload()) {
-            Ok(pkt) => pkt,
-            Err(e) => return Some(Err(e)),
-        };
+        Some(Neighbour::try_from(pkt.payload()))
+    }
+}
+
+impl TryFrom<&[u8]> for Neighbour {
+    type Error = io::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let packet = NeighbourPacket::new_checked(value)?;
 
         let address_family = packet.family();
 
@@ -103,10 +109,7 @@ impl<'a, 'b> Iterator for Neighbours<'a, 'b> {
                 break;
             }
 
-            let attr = match NetlinkAttrPacket::new_checked(&payload) {
-                Ok(pkt) => pkt,
-                Err(e) => return Some(Err(e)),
-            };
+            let attr = NetlinkAttrPacket::new_checked(&payload)?;
 
             // let attr_payload_len = attr.payload_len();
             let attr_total_len = attr.total_len();
@@ -136,6 +139,6 @@ impl<'a, 'b> Iterator for Neighbours<'a, 'b> {
             payload = &payload[attr_total_len..];
         }
         
-        Some(Ok(Neighbour { ifindex, state, flags, dst_addr, hw_addr: link_addr }))
+        Ok(Neighbour { ifindex, state, flags, dst_addr, hw_addr: link_addr })
     }
 }
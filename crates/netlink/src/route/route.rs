@@ -5,8 +5,12 @@ use crate::packet::NetlinkPacket;
 use crate::packet::NetlinkErrorPacket;
 use crate::packet::RoutePacket;
 use crate::packet::NetlinkAttrPacket;
+use crate::packet::NextHopPacket;
 use crate::packet::RouteAttrType;
-use crate::packet::{RouteTable, RouteProtocol, RouteScope, RouteType, RouteFlags};
+use crate::packet::RouteMetricType;
+use crate::packet::{RouteTable, RouteProtocol, RouteScope, RouteType, RouteFlags, NextHopFlags};
+
+use crate::packet::NetlinkAttrBuilder;
 
 use byteorder::{ByteOrder, NativeEndian, NetworkEndian};
 use smoltcp::wire::IpCidr;
@@ -16,7 +20,98 @@ use std::convert::TryFrom;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 
-#[derive(Debug, Clone, Copy)]
+/// A next hop of a multipath (ECMP) route.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct NextHop {
+    pub gateway: Option<IpAddr>,
+    pub ifindex: u32,
+    /// 1 ..= 256, the traffic is balanced according to the weights of the next hops.
+    pub weight: u16,
+    pub flags: NextHopFlags,
+}
+
+impl NextHop {
+    pub fn new(ifindex: u32) -> Self {
+        Self { gateway: None, ifindex, weight: 1, flags: NextHopFlags::from_bits_truncate(0) }
+    }
+
+    pub fn gateway(mut self, gateway: IpAddr) -> Self {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    pub fn weight(mut self, weight: u16) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn flags(mut self, flags: NextHopFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+}
+
+/// Per-route metrics (`ip route add ... mtu 1400 advmss 1360 initcwnd 10`).
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct RouteMetrics {
+    pub mtu: Option<u32>,
+    pub window: Option<u32>,
+    pub advmss: Option<u32>,
+    pub hoplimit: Option<u32>,
+    pub initcwnd: Option<u32>,
+    pub initrwnd: Option<u32>,
+}
+
+impl RouteMetrics {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn emit(&self, attrs: &mut NetlinkAttrBuilder) {
+        let metrics = [
+            (RouteMetricType::RTAX_MTU, self.mtu),
+            (RouteMetricType::RTAX_WINDOW, self.window),
+            (RouteMetricType::RTAX_ADVMSS, self.advmss),
+            (RouteMetricType::RTAX_HOPLIMIT, self.hoplimit),
+            (RouteMetricType::RTAX_INITCWND, self.initcwnd),
+            (RouteMetricType::RTAX_INITRWND, self.initrwnd),
+        ];
+        for (kind, value) in metrics.iter() {
+            if let Some(value) = value {
+                attrs.put_u32((*kind).into(), *value);
+            }
+        }
+    }
+
+    fn parse(&mut self, mut payload: &[u8]) -> Result<(), io::Error> {
+        while payload.len() >= 4 {
+            let attr = NetlinkAttrPacket::new_checked(&payload)?;
+            let attr_total_len = attr.total_len();
+            let attr_kind = RouteMetricType(attr.kind());
+            let attr_data = attr.payload();
+
+            if attr_data.len() >= 4 {
+                let value = Some(NativeEndian::read_u32(&attr_data));
+                match attr_kind {
+                    RouteMetricType::RTAX_MTU => self.mtu = value,
+                    RouteMetricType::RTAX_WINDOW => self.window = value,
+                    RouteMetricType::RTAX_ADVMSS => self.advmss = value,
+                    RouteMetricType::RTAX_HOPLIMIT => self.hoplimit = value,
+                    RouteMetricType::RTAX_INITCWND => self.initcwnd = value,
+                    RouteMetricType::RTAX_INITRWND => self.initrwnd = value,
+                    _ => trace!("Droped Route Metric: type={:15} data={:?}", format!("{:?}", attr_kind), attr_data),
+                }
+            }
+
+            payload = &payload[std::cmp::min(attr_total_len, payload.len())..];
+        }
+
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Clone)]
 pub struct Route {
     pub table: RouteTable,
     pub protocol: RouteProtocol,
@@ -28,6 +123,47 @@ pub struct Route {
     pub pref_src: Option<IpAddr>,
     pub gateway: Option<IpAddr>,
     pub out_ifindex: Option<u32>,
+    // Metric (`ip route add ... metric <priority>`), lower values are preferred.
+    pub priority: Option<u32>,
+    pub metrics: RouteMetrics,
+    // Next hops of a multipath route, `gateway` and `out_ifindex` are not set in this case.
+    pub multipath: Vec<NextHop>,
+}
+
+fn parse_addr(address_family: AddressFamily, data: &[u8]) -> Option<IpAddr> {
+    if address_family == AddressFamily::AF_INET && data.len() >= 4 {
+        Some(Ipv4Addr::from(NetworkEndian::read_u32(&data)).into())
+    } else if address_family == AddressFamily::AF_INET6 && data.len() >= 16 {
+        Some(Ipv6Addr::from(NetworkEndian::read_u128(&data)).into())
+    } else {
+        None
+    }
+}
+
+fn parse_multipath(address_family: AddressFamily, mut payload: &[u8]) -> Result<Vec<NextHop>, io::Error> {
+    let mut next_hops = Vec::new();
+
+    while payload.len() >= NextHopPacket::<&[u8]>::MIN_SIZE {
+        let nh_packet = NextHopPacket::new_checked(payload)?;
+        let mut next_hop = NextHop::new(nh_packet.ifindex() as u32)
+            .weight(nh_packet.hops() as u16 + 1)
+            .flags(nh_packet.flags());
+
+        let mut attrs = nh_packet.payload();
+        while attrs.len() >= 4 {
+            let attr = NetlinkAttrPacket::new_checked(&attrs)?;
+            let attr_total_len = attr.total_len();
+            if RouteAttrType(attr.kind()) == RouteAttrType::RTA_GATEWAY {
+                next_hop.gateway = parse_addr(address_family, attr.payload());
+            }
+            attrs = &attrs[std::cmp::min(attr_total_len, attrs.len())..];
+        }
+
+        next_hops.push(next_hop);
+        payload = &payload[std::cmp::min(nh_packet.total_len(), payload.len())..];
+    }
+
+    Ok(next_hops)
 }
 
 impl TryFrom<&[u8]> for Route {
@@ -45,11 +181,12 @@ impl TryFrom<&[u8]> for Route {
         let src_len = packet.src_len();
         let dst_len = packet.dst_len();
 
+        // NOTE: The replies of `RTM_GETROUTE` have `src_len` set to the full length of the address.
         if address_family == AddressFamily::AF_INET {
-            debug_assert!(src_len == 0);
+            debug_assert!(src_len <= 32);
             debug_assert!(dst_len <= 32);
         } else if address_family == AddressFamily::AF_INET6 {
-            debug_assert!(src_len <= 0);
+            debug_assert!(src_len <= 128);
             debug_assert!(dst_len <= 128);
         }
 
@@ -57,6 +194,9 @@ impl TryFrom<&[u8]> for Route {
         let mut pref_src = None;
         let mut gateway = None;
         let mut out_ifindex = None;
+        let mut priority = None;
+        let mut metrics = RouteMetrics::default();
+        let mut multipath = Vec::new();
 
         let mut payload = packet.payload();
         
@@ -76,44 +216,30 @@ impl TryFrom<&[u8]> for Route {
             // println!("Route Attr: type={:15} data={:?}", format!("{:?}", attr_kind), attr_data);
             
             if attr_kind == RouteAttrType::RTA_TABLE {
-                table = RouteTable(attr_data[0]);
+                table = RouteTable(NativeEndian::read_u32(&attr_data));
             } else if attr_kind == RouteAttrType::RTA_DST {
-                if address_family == AddressFamily::AF_INET {
-                    let octets = NetworkEndian::read_u32(&attr_data);
-                    let dst_addr: IpAddr = Ipv4Addr::from(octets).into();
-                    dst_cidr = Some(IpCidr::new(dst_addr.into(), dst_len));
-                } else if address_family == AddressFamily::AF_INET6 {
-                    let octets = NetworkEndian::read_u128(&attr_data);
-                    let dst_addr: IpAddr = Ipv6Addr::from(octets).into();
-                    dst_cidr = Some(IpCidr::new(dst_addr.into(), dst_len));
-                } else {
-                    error!("Unknow Route Attr: type={:15} data={:?}", format!("{:?}", attr_kind), attr_data);
-                    continue;
+                match parse_addr(address_family, attr_data) {
+                    Some(dst_addr) => dst_cidr = Some(IpCidr::new(dst_addr.into(), dst_len)),
+                    None => error!("Unknow Route Attr: type={:15} data={:?}", format!("{:?}", attr_kind), attr_data),
                 }
             } else if attr_kind == RouteAttrType::RTA_PREFSRC {
-                if address_family == AddressFamily::AF_INET {
-                    let octets = NetworkEndian::read_u32(&attr_data);
-                    pref_src = Some(std::net::Ipv4Addr::from(octets).into());
-                } else if address_family == AddressFamily::AF_INET6 {
-                    let octets = NetworkEndian::read_u128(&attr_data);
-                    pref_src = Some(std::net::Ipv6Addr::from(octets).into())
-                } else {
-                    error!("Unknow Route Attr: type={:15} data={:?}", format!("{:?}", attr_kind), attr_data);
-                    continue;
+                match parse_addr(address_family, attr_data) {
+                    Some(addr) => pref_src = Some(addr),
+                    None => error!("Unknow Route Attr: type={:15} data={:?}", format!("{:?}", attr_kind), attr_data),
                 }
             } else if attr_kind == RouteAttrType::RTA_OIF {
                 out_ifindex = Some(NativeEndian::read_i32(&attr_data) as u32);
             } else if attr_kind == RouteAttrType::RTA_GATEWAY {
-                if address_family == AddressFamily::AF_INET {
-                    let octets = NetworkEndian::read_u32(&attr_data);
-                    gateway = Some(std::net::Ipv4Addr::from(octets).into());
-                } else if address_family == AddressFamily::AF_INET6 {
-                    let octets = NetworkEndian::read_u128(&attr_data);
-                    gateway = Some(std::net::Ipv6Addr::from(octets).into())
-                } else {
-                    error!("Unknow Route Attr: type={:15} data={:?}", format!("{:?}", attr_kind), attr_data);
-                    continue;
+                match parse_addr(address_family, attr_data) {
+                    Some(addr) => gateway = Some(addr),
+                    None => error!("Unknow Route Attr: type={:15} data={:?}", format!("{:?}", attr_kind), attr_data),
                 }
+            } else if attr_kind == RouteAttrType::RTA_PRIORITY {
+                priority = Some(NativeEndian::read_u32(&attr_data));
+            } else if attr_kind == RouteAttrType::RTA_METRICS {
+                metrics.parse(attr_data)?;
+            } else if attr_kind == RouteAttrType::RTA_MULTIPATH {
+                multipath = parse_multipath(address_family, attr_data)?;
             } else {
                 trace!("Droped Route Attr: type={:15} data={:?}", format!("{:?}", attr_kind), attr_data);
             }
@@ -121,7 +247,227 @@ impl TryFrom<&[u8]> for Route {
             payload = &payload[attr_total_len..];
         }
 
-        Ok(Route{ table, protocol, scope, kind, flags, address_family, dst_cidr, pref_src, gateway, out_ifindex, })
+        Ok(Route{ table, protocol, scope, kind, flags, address_family, dst_cidr, pref_src, gateway, out_ifindex,
+                  priority, metrics, multipath, })
+    }
+}
+
+
+/// Add or replace a route.
+///
+/// ```no_run
+/// use netlink::route::RouteController;
+/// use netlink::route::route::{ RouteRequest, NextHop, };
+///
+/// // sudo ip route add 10.0.0.0/8 metric 100 mtu 1400 \
+/// //      nexthop via 192.168.1.1 dev eth0 weight 1 \
+/// //      nexthop via 192.168.2.1 dev eth1 weight 2
+/// let req = RouteRequest::new("10.0.0.0".parse().unwrap(), 8)
+///     .priority(100)
+///     .mtu(1400)
+///     .nexthop(NextHop::new(2).gateway("192.168.1.1".parse().unwrap()))
+///     .nexthop(NextHop::new(3).gateway("192.168.2.1".parse().unwrap()).weight(2));
+/// let mut buffer = [0u8; 1024];
+/// RouteController::new().unwrap().add_route(&req, &mut buffer).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct RouteRequest {
+    pub dst_addr: IpAddr,
+    pub prefix_len: u8,
+    pub gateway: Option<IpAddr>,
+    pub ifindex: Option<u32>,
+    pub table: RouteTable,
+    pub protocol: RouteProtocol,
+    pub kind: RouteType,
+    pub priority: Option<u32>,
+    pub metrics: RouteMetrics,
+    pub multipath: Vec<NextHop>,
+}
+
+impl RouteRequest {
+    pub fn new(dst_addr: IpAddr, prefix_len: u8) -> Self {
+        Self {
+            dst_addr,
+            prefix_len,
+            gateway: None,
+            ifindex: None,
+            table: RouteTable::RT_TABLE_MAIN,
+            protocol: RouteProtocol::RTPROT_BOOT,
+            kind: RouteType::RTN_UNICAST,
+            priority: None,
+            metrics: RouteMetrics::default(),
+            multipath: Vec::new(),
+        }
+    }
+
+    pub fn gateway(mut self, gateway: IpAddr) -> Self {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    pub fn ifindex(mut self, ifindex: u32) -> Self {
+        self.ifindex = Some(ifindex);
+        self
+    }
+
+    pub fn table(mut self, table: RouteTable) -> Self {
+        self.table = table;
+        self
+    }
+
+    pub fn protocol(mut self, protocol: RouteProtocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn kind(mut self, kind: RouteType) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn priority(mut self, priority: u32) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn mtu(mut self, mtu: u32) -> Self {
+        self.metrics.mtu = Some(mtu);
+        self
+    }
+
+    pub fn advmss(mut self, advmss: u32) -> Self {
+        self.metrics.advmss = Some(advmss);
+        self
+    }
+
+    pub fn initcwnd(mut self, initcwnd: u32) -> Self {
+        self.metrics.initcwnd = Some(initcwnd);
+        self
+    }
+
+    pub fn metrics(mut self, metrics: RouteMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Add a next hop, the route becomes a multipath route.
+    pub fn nexthop(mut self, next_hop: NextHop) -> Self {
+        self.multipath.push(next_hop);
+        self
+    }
+
+    pub fn family(&self) -> AddressFamily {
+        match self.dst_addr {
+            IpAddr::V4(_) => AddressFamily::AF_INET,
+            IpAddr::V6(_) => AddressFamily::AF_INET6,
+        }
+    }
+
+    fn check(&self) -> Result<(), io::Error> {
+        let is_ipv4 = self.dst_addr.is_ipv4();
+        let max_prefix_len = if is_ipv4 { 32 } else { 128 };
+        if self.prefix_len > max_prefix_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid prefix length"));
+        }
+
+        if self.multipath.is_empty() {
+            if self.gateway.is_none() && self.ifindex.is_none() && self.kind == RouteType::RTN_UNICAST {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "gateway or ifindex provide at least one."));
+            }
+        } else if self.gateway.is_some() || self.ifindex.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "gateway and ifindex are set by the next hops of a multipath route"));
+        }
+
+        let gateways = self.gateway.iter().chain(self.multipath.iter().filter_map(|nh| nh.gateway.as_ref()));
+        for gateway in gateways {
+            if gateway.is_ipv4() != is_ipv4 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "gateway address family mismatch"));
+            }
+        }
+
+        for next_hop in self.multipath.iter() {
+            if next_hop.weight == 0 || next_hop.weight > 256 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid next hop weight"));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scope(&self) -> RouteScope {
+        let has_gateway = self.gateway.is_some() || self.multipath.iter().any(|nh| nh.gateway.is_some());
+        if self.kind != RouteType::RTN_UNICAST {
+            RouteScope::RT_SCOPE_UNIVERSE
+        } else if self.prefix_len == 0 || has_gateway {
+            // default route (0.0.0.0/0) or routes via a gateway
+            RouteScope::RT_SCOPE_UNIVERSE
+        } else {
+            RouteScope::RT_SCOPE_LINK
+        }
+    }
+
+    /// Write the `rtmsg` header and the attributes into the buffer, returns the written length.
+    pub fn emit(&self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+        self.check()?;
+
+        let mut attrs = NetlinkAttrBuilder::new();
+        attrs.put_addr(RouteAttrType::RTA_DST.into(), self.dst_addr);
+        if let Some(gateway) = self.gateway {
+            attrs.put_addr(RouteAttrType::RTA_GATEWAY.into(), gateway);
+        }
+        if let Some(ifindex) = self.ifindex {
+            attrs.put_u32(RouteAttrType::RTA_OIF.into(), ifindex);
+        }
+        // NOTE: `rtm_table` has only 8 bits, the table is always carried by `RTA_TABLE` too.
+        attrs.put_u32(RouteAttrType::RTA_TABLE.into(), self.table.0);
+        if let Some(priority) = self.priority {
+            attrs.put_u32(RouteAttrType::RTA_PRIORITY.into(), priority);
+        }
+        if !self.metrics.is_empty() {
+            let mut metrics = NetlinkAttrBuilder::new();
+            self.metrics.emit(&mut metrics);
+            attrs.put(RouteAttrType::RTA_METRICS.into(), metrics.as_bytes());
+        }
+        if !self.multipath.is_empty() {
+            let mut multipath = Vec::new();
+            for next_hop in self.multipath.iter() {
+                let mut nh_attrs = NetlinkAttrBuilder::new();
+                if let Some(gateway) = next_hop.gateway {
+                    nh_attrs.put_addr(RouteAttrType::RTA_GATEWAY.into(), gateway);
+                }
+
+                let start = multipath.len();
+                let nh_len = NextHopPacket::<&[u8]>::MIN_SIZE + nh_attrs.len();
+                multipath.resize(start + nh_len, 0);
+
+                let mut nh_packet = NextHopPacket::new_unchecked(&mut multipath[start..]);
+                nh_packet.set_len(nh_len as u16);
+                nh_packet.set_flags(next_hop.flags);
+                nh_packet.set_hops((next_hop.weight - 1) as u8);
+                nh_packet.set_ifindex(next_hop.ifindex as i32);
+                nh_packet.payload_mut().copy_from_slice(nh_attrs.as_bytes());
+            }
+            attrs.put(RouteAttrType::RTA_MULTIPATH.into(), &multipath);
+        }
+
+        let len = RoutePacket::<&[u8]>::MIN_SIZE + attrs.len();
+        if buffer.len() < len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is too small."));
+        }
+
+        let mut route_packet = RoutePacket::new_unchecked(&mut buffer[..len]);
+        route_packet.set_family(self.family());
+        route_packet.set_dst_len(self.prefix_len);
+        route_packet.set_src_len(0);
+        route_packet.set_tos(0);
+        route_packet.set_table(self.table);
+        route_packet.set_protocol(self.protocol);
+        route_packet.set_scope(self.scope());
+        route_packet.set_kind(self.kind);
+        route_packet.set_flags(RouteFlags::from_bits_truncate(0));
+        route_packet.payload_mut().copy_from_slice(attrs.as_bytes());
+
+        Ok(len)
     }
 }
 
@@ -184,4 +530,67 @@ impl<'a, 'b> Iterator for Routes<'a, 'b> {
         
         Some(Route::try_from(pkt.payload()))
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_route_metrics_emit() {
+    let metrics = RouteMetrics {
+        mtu: Some(1400),
+        window: None,
+        advmss: Some(1360),
+        hoplimit: Some(64),
+        initcwnd: Some(10),
+        initrwnd: None,
+    };
+    let mut attrs = NetlinkAttrBuilder::new();
+    metrics.emit(&mut attrs);
+    assert_eq!(attrs.len(), 4 * 8);
+
+    let mut parsed = RouteMetrics::default();
+    parsed.parse(attrs.as_bytes()).unwrap();
+    assert_eq!(parsed, metrics);
+    assert!(!parsed.is_empty());
+    assert!(RouteMetrics::default().is_empty());
+}
+
+#[test]
+fn test_route_request_emit() {
+    let mut buffer = [0u8; 1024];
+
+    let req = RouteRequest::new("10.0.0.0".parse().unwrap(), 8)
+        .table(RouteTable(1000))
+        .priority(100)
+        .mtu(1400)
+        .nexthop(NextHop::new(2).gateway("192.168.1.1".parse().unwrap()).weight(1))
+        .nexthop(NextHop::new(3).gateway("192.168.2.1".parse().unwrap()).weight(3).flags(NextHopFlags::RTNH_F_ONLINK));
+    let len = req.emit(&mut buffer).unwrap();
+    let route = Route::try_from(&buffer[..len]).unwrap();
+    assert_eq!(route.address_family, AddressFamily::AF_INET);
+    assert_eq!(route.dst_cidr, Some(IpCidr::new(smoltcp::wire::Ipv4Address::new(10, 0, 0, 0).into(), 8)));
+    assert_eq!(route.table, RouteTable(1000));
+    assert_eq!(route.scope, RouteScope::RT_SCOPE_UNIVERSE);
+    assert_eq!(route.priority, Some(100));
+    assert_eq!(route.metrics.mtu, Some(1400));
+    assert_eq!(route.gateway, None);
+    assert_eq!(route.out_ifindex, None);
+    assert_eq!(route.multipath, req.multipath);
+
+    let req = RouteRequest::new("fd00::".parse().unwrap(), 64).ifindex(2);
+    let len = req.emit(&mut buffer).unwrap();
+    let route = Route::try_from(&buffer[..len]).unwrap();
+    assert_eq!(route.address_family, AddressFamily::AF_INET6);
+    assert_eq!(route.dst_cidr.map(|cidr| cidr.prefix_len()), Some(64));
+    assert_eq!(route.table, RouteTable::RT_TABLE_MAIN);
+    assert_eq!(route.scope, RouteScope::RT_SCOPE_LINK);
+    assert_eq!(route.out_ifindex, Some(2));
+    assert!(route.metrics.is_empty());
+    assert!(route.multipath.is_empty());
+
+    let nh = NextHop::new(2).gateway("192.168.1.1".parse().unwrap());
+    // gateway address family mismatch
+    assert!(RouteRequest::new("fd00::".parse().unwrap(), 64).nexthop(nh).emit(&mut buffer).is_err());
+    // the next hops carry the gateway and ifindex
+    assert!(RouteRequest::new("10.0.0.0".parse().unwrap(), 8).ifindex(2).nexthop(nh).emit(&mut buffer).is_err());
+    assert!(RouteRequest::new("10.0.0.0".parse().unwrap(), 8).nexthop(nh.weight(0)).emit(&mut buffer).is_err());
+    assert!(RouteRequest::new("10.0.0.0".parse().unwrap(), 33).ifindex(2).emit(&mut buffer).is_err());
+}
@@ -0,0 +1,334 @@
+use crate::socket::NetlinkSocket;
+use crate::packet::Kind;
+use crate::packet::AddressFamily;
+use crate::packet::NetlinkPacket;
+use crate::packet::NetlinkErrorPacket;
+use crate::packet::NetlinkAttrPacket;
+use crate::packet::NetlinkAttrBuilder;
+use crate::packet::RulePacket;
+use crate::packet::RuleAttrType;
+use crate::packet::{RouteTable, RuleAction, RuleFlags};
+
+use byteorder::{ByteOrder, NativeEndian};
+use smoltcp::wire::{IpAddress, IpCidr, Ipv4Address, Ipv6Address};
+
+use std::io;
+use std::convert::TryFrom;
+
+
+/// A policy routing rule (`ip rule`).
+///
+/// ```no_run
+/// use netlink::route::RouteController;
+/// use netlink::route::rule::Rule;
+/// use netlink::packet::{ AddressFamily, RouteTable, };
+///
+/// // sudo ip rule add fwmark 0x1 lookup 100 priority 1000
+/// let rule = Rule::new(AddressFamily::AF_INET, RouteTable(100)).priority(1000).fwmark(0x1, 0xFFFF_FFFF);
+/// let mut buffer = [0u8; 1024];
+/// RouteController::new().unwrap().add_rule(&rule, &mut buffer).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Rule {
+    pub address_family: AddressFamily,
+    pub table: RouteTable,
+    pub action: RuleAction,
+    pub flags: RuleFlags,
+    // Attrs
+    pub priority: Option<u32>,
+    pub src_cidr: Option<IpCidr>,
+    pub dst_cidr: Option<IpCidr>,
+    pub fwmark: Option<u32>,
+    pub fwmask: Option<u32>,
+    pub suppress_prefixlen: Option<u32>,
+}
+
+impl Rule {
+    /// Lookup the given table (`ip rule add ... lookup <table>`).
+    pub fn new(address_family: AddressFamily, table: RouteTable) -> Self {
+        Self {
+            address_family,
+            table,
+            action: RuleAction::FR_ACT_TO_TBL,
+            flags: RuleFlags::from_bits_truncate(0),
+            priority: None,
+            src_cidr: None,
+            dst_cidr: None,
+            fwmark: None,
+            fwmask: None,
+            suppress_prefixlen: None,
+        }
+    }
+
+    pub fn action(mut self, action: RuleAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Match the packets which don't match the selectors (`ip rule add not ...`).
+    pub fn invert(mut self) -> Self {
+        self.flags |= RuleFlags::FIB_RULE_INVERT;
+        self
+    }
+
+    /// Lower values are looked up first, the kernel picks one when it is not set.
+    pub fn priority(mut self, priority: u32) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn from(mut self, src_cidr: IpCidr) -> Self {
+        self.src_cidr = Some(src_cidr);
+        self
+    }
+
+    pub fn to(mut self, dst_cidr: IpCidr) -> Self {
+        self.dst_cidr = Some(dst_cidr);
+        self
+    }
+
+    pub fn fwmark(mut self, fwmark: u32, fwmask: u32) -> Self {
+        self.fwmark = Some(fwmark);
+        self.fwmask = Some(fwmask);
+        self
+    }
+
+    /// Reject the routing decisions which have a prefix length of `prefix_len` or less
+    /// (`ip rule add ... suppress_prefixlength 0` ignores the default route of the table).
+    pub fn suppress_prefixlen(mut self, prefix_len: u32) -> Self {
+        self.suppress_prefixlen = Some(prefix_len);
+        self
+    }
+
+    fn check(&self) -> Result<(), io::Error> {
+        let is_ipv4 = if self.address_family == AddressFamily::AF_INET {
+            true
+        } else if self.address_family == AddressFamily::AF_INET6 {
+            false
+        } else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "address family is not supported"));
+        };
+
+        for cidr in [self.src_cidr, self.dst_cidr].iter() {
+            match cidr {
+                Some(IpCidr::Ipv4(_)) if is_ipv4 => { },
+                Some(IpCidr::Ipv6(_)) if !is_ipv4 => { },
+                Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "selector address family mismatch")),
+                None => { },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the `fib_rule_hdr` header and the attributes into the buffer, returns the written length.
+    pub fn emit(&self, buffer: &mut [u8]) -> Result<usize, io::Error> {
+        self.check()?;
+
+        let mut attrs = NetlinkAttrBuilder::new();
+        // NOTE: `fib_rule_hdr.table` has only 8 bits, `FRA_TABLE` is always set.
+        attrs.put_u32(RuleAttrType::FRA_TABLE.into(), self.table.0);
+        if let Some(priority) = self.priority {
+            attrs.put_u32(RuleAttrType::FRA_PRIORITY.into(), priority);
+        }
+        if let Some(src_cidr) = self.src_cidr {
+            attrs.put(RuleAttrType::FRA_SRC.into(), src_cidr.address().as_bytes());
+        }
+        if let Some(dst_cidr) = self.dst_cidr {
+            attrs.put(RuleAttrType::FRA_DST.into(), dst_cidr.address().as_bytes());
+        }
+        if let Some(fwmark) = self.fwmark {
+            attrs.put_u32(RuleAttrType::FRA_FWMARK.into(), fwmark);
+        }
+        if let Some(fwmask) = self.fwmask {
+            attrs.put_u32(RuleAttrType::FRA_FWMASK.into(), fwmask);
+        }
+        if let Some(suppress_prefixlen) = self.suppress_prefixlen {
+            attrs.put_u32(RuleAttrType::FRA_SUPPRESS_PREFIXLEN.into(), suppress_prefixlen);
+        }
+
+        let len = RulePacket::<&[u8]>::MIN_SIZE + attrs.len();
+        if buffer.len() < len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer is too small."));
+        }
+
+        let mut rule_packet = RulePacket::new_unchecked(&mut buffer[..len]);
+        rule_packet.set_family(self.address_family);
+        rule_packet.set_dst_len(self.dst_cidr.map(|cidr| cidr.prefix_len()).unwrap_or(0));
+        rule_packet.set_src_len(self.src_cidr.map(|cidr| cidr.prefix_len()).unwrap_or(0));
+        rule_packet.set_tos(0);
+        rule_packet.set_table(self.table);
+        rule_packet.set_action(self.action);
+        rule_packet.set_flags(self.flags);
+        rule_packet.payload_mut()[..attrs.len()].copy_from_slice(attrs.as_bytes());
+
+        Ok(len)
+    }
+}
+
+impl TryFrom<&[u8]> for Rule {
+    type Error = io::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let packet = RulePacket::new_checked(value)?;
+
+        let address_family = packet.family();
+        let src_len = packet.src_len();
+        let dst_len = packet.dst_len();
+
+        let mut rule = Rule::new(address_family, packet.table());
+        rule.action = packet.action();
+        rule.flags = packet.flags();
+
+        let parse_cidr = |data: &[u8], prefix_len: u8| -> Option<IpCidr> {
+            if address_family == AddressFamily::AF_INET && data.len() >= 4 {
+                Some(IpCidr::new(IpAddress::Ipv4(Ipv4Address::from_bytes(&data[..4])), prefix_len))
+            } else if address_family == AddressFamily::AF_INET6 && data.len() >= 16 {
+                Some(IpCidr::new(IpAddress::Ipv6(Ipv6Address::from_bytes(&data[..16])), prefix_len))
+            } else {
+                None
+            }
+        };
+
+        let mut payload = packet.payload();
+
+        loop {
+            if payload.len() < 4 {
+                break;
+            }
+
+            let attr = NetlinkAttrPacket::new_checked(&payload)?;
+            let attr_total_len = attr.total_len();
+
+            let attr_kind = RuleAttrType(attr.kind());
+            let attr_data = attr.payload();
+
+            if attr_kind == RuleAttrType::FRA_TABLE {
+                rule.table = RouteTable(NativeEndian::read_u32(&attr_data));
+            } else if attr_kind == RuleAttrType::FRA_PRIORITY {
+                rule.priority = Some(NativeEndian::read_u32(&attr_data));
+            } else if attr_kind == RuleAttrType::FRA_SRC {
+                rule.src_cidr = parse_cidr(attr_data, src_len);
+            } else if attr_kind == RuleAttrType::FRA_DST {
+                rule.dst_cidr = parse_cidr(attr_data, dst_len);
+            } else if attr_kind == RuleAttrType::FRA_FWMARK {
+                rule.fwmark = Some(NativeEndian::read_u32(&attr_data));
+            } else if attr_kind == RuleAttrType::FRA_FWMASK {
+                rule.fwmask = Some(NativeEndian::read_u32(&attr_data));
+            } else if attr_kind == RuleAttrType::FRA_SUPPRESS_PREFIXLEN {
+                let suppress_prefixlen = NativeEndian::read_u32(&attr_data);
+                // NOTE: The kernel reports `-1` when it is not set.
+                if suppress_prefixlen != 0xFFFF_FFFF {
+                    rule.suppress_prefixlen = Some(suppress_prefixlen);
+                }
+            } else {
+                trace!("Droped Rule Attr: type={:15} data={:?}", format!("{:?}", attr_kind), attr_data);
+            }
+
+            payload = &payload[attr_total_len..];
+        }
+
+        Ok(rule)
+    }
+}
+
+
+pub struct Rules<'a, 'b> {
+    pub(crate) socket: &'a mut NetlinkSocket,
+    pub(crate) buffer: &'b mut [u8],
+    pub(crate) is_done: bool,
+    pub(crate) buffer_len: usize,
+    pub(crate) offset: usize,
+}
+
+impl<'a, 'b> Rules<'a, 'b> {
+    fn next_packet(&mut self) -> Result<Option<NetlinkPacket<&[u8]>>, io::Error> {
+        if self.offset >= self.buffer_len {
+            let amt = self.socket.recv(&mut self.buffer)?;
+            trace!("read {} bytes from netlink socket.", amt);
+            self.buffer_len = amt;
+            self.offset = 0;
+        }
+
+        if self.buffer_len < NetlinkPacket::<&[u8]>::MIN_SIZE {
+            return Ok(None);
+        }
+
+        let start = self.offset;
+        let pkt = NetlinkPacket::new_checked(&self.buffer[self.offset..])?;
+        let pkt_len = pkt.total_len();
+        self.offset += pkt_len;
+        let end = self.offset;
+
+        let pkt = NetlinkPacket::new_unchecked(&self.buffer[start..end]);
+        match pkt.kind() {
+            Kind::NLMSG_NOOP     => Ok(None),
+            Kind::NLMSG_ERROR    => Err(NetlinkErrorPacket::new_checked(pkt.payload())?.err()),
+            Kind::NLMSG_DONE     => {
+                self.is_done = true;
+                Ok(None)
+            },
+            Kind::NLMSG_OVERRUN  => Err(io::Error::new(io::ErrorKind::InvalidData, "Overrun")),
+            Kind::RTM_NEWRULE => Ok(Some(pkt)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Netlink Message Type is not `{:?}`", Kind::RTM_NEWRULE))),
+        }
+    }
+}
+
+impl<'a, 'b> Iterator for Rules<'a, 'b> {
+    type Item = Result<Rule, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_done {
+            return None;
+        }
+
+        let pkt = match self.next_packet() {
+            Ok(Some(pkt)) => pkt,
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e)),
+        };
+
+        Some(Rule::try_from(pkt.payload()))
+    }
+}
+
+
+#[test]
+fn test_rule_emit() {
+    let mut buffer = [0u8; 1024];
+
+    let rule = Rule::new(AddressFamily::AF_INET, RouteTable(1000))
+        .priority(100)
+        .from(IpCidr::new(IpAddress::v4(10, 0, 0, 0), 8))
+        .to(IpCidr::new(IpAddress::v4(192, 168, 1, 0), 24))
+        .fwmark(0x1, 0xFF)
+        .suppress_prefixlen(0)
+        .invert();
+    let len = rule.emit(&mut buffer).unwrap();
+    let parsed = Rule::try_from(&buffer[..len]).unwrap();
+    assert_eq!(parsed.address_family, AddressFamily::AF_INET);
+    assert_eq!(parsed.table, RouteTable(1000));
+    assert_eq!(parsed.action, RuleAction::FR_ACT_TO_TBL);
+    assert_eq!(parsed.flags, RuleFlags::FIB_RULE_INVERT);
+    assert_eq!(parsed.priority, Some(100));
+    assert_eq!(parsed.src_cidr, rule.src_cidr);
+    assert_eq!(parsed.dst_cidr, rule.dst_cidr);
+    assert_eq!(parsed.fwmark, Some(0x1));
+    assert_eq!(parsed.fwmask, Some(0xFF));
+    assert_eq!(parsed.suppress_prefixlen, Some(0));
+
+    let rule = Rule::new(AddressFamily::AF_INET6, RouteTable::RT_TABLE_MAIN)
+        .to(IpCidr::new(IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 0, 0), 64));
+    let len = rule.emit(&mut buffer).unwrap();
+    let parsed = Rule::try_from(&buffer[..len]).unwrap();
+    assert_eq!(parsed.address_family, AddressFamily::AF_INET6);
+    assert_eq!(parsed.table, RouteTable::RT_TABLE_MAIN);
+    assert_eq!(parsed.src_cidr, None);
+    assert_eq!(parsed.dst_cidr, rule.dst_cidr);
+    assert_eq!(parsed.priority, None);
+
+    // selector address family mismatch
+    let rule = Rule::new(AddressFamily::AF_INET6, RouteTable::RT_TABLE_MAIN).from(IpCidr::new(IpAddress::v4(10, 0, 0, 0), 8));
+    assert!(rule.emit(&mut buffer).is_err());
+}
@@ -1,9 +1,12 @@
 // https://tools.ietf.org/html/rfc3549
 // /usr/include/linux/netlink.h
 
+use crate::packet::{ self, Flags, Kind, NetlinkPacket, NetlinkErrorPacket, };
+
 use libc;
 
 use std::io::{self, Read, Write};
+use std::ops::Range;
 use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
 
 
@@ -19,6 +22,7 @@ pub const NETLINK_TX_RING: libc::c_int          = 7;
 pub const NETLINK_LISTEN_ALL_NSID: libc::c_int  = 8;
 pub const NETLINK_LIST_MEMBERSHIPS: libc::c_int = 9;
 pub const NETLINK_CAP_ACK: libc::c_int          = 10;
+pub const NETLINK_EXT_ACK: libc::c_int          = 11;
 
 
 #[repr(C)]
@@ -34,6 +38,8 @@ pub struct sockaddr_nl {
 #[derive(Debug)]
 pub struct NetlinkSocket {
     fd: libc::c_int,
+    // sequence number of the last request
+    seq: u32,
 }
 
 impl NetlinkSocket {
@@ -49,7 +55,23 @@ impl NetlinkSocket {
             return Err(io::Error::last_os_error());
         }
 
-        Ok(Self { fd })
+        let mut nl_socket = Self { fd, seq: 0 };
+        // NOTE: Extended ACK was added in Linux 4.12, older kernels still report the errno.
+        if let Err(e) = nl_socket.set_opt(NETLINK_EXT_ACK, 1) {
+            trace!("unable to enable extended ACK on netlink socket at FD#{}: {}", fd, e);
+        }
+
+        Ok(nl_socket)
+    }
+
+    fn set_opt(&mut self, opt: libc::c_int, value: libc::c_int) -> Result<(), io::Error> {
+        let value_ptr = &value as *const libc::c_int as *const libc::c_void;
+        let value_len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        if unsafe { libc::setsockopt(self.fd, SOL_NETLINK, opt, value_ptr, value_len) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
     }
 
     pub fn bind(&mut self, pid: u32, groups: u32) -> Result<(), io::Error> {
@@ -155,6 +177,139 @@ impl NetlinkSocket {
         Ok(amt as usize)
     }
 
+    /// Send a request (the message in `buffer[..len]`) and wait for the kernel to acknowledge it,
+    /// the reply is received into the same buffer.
+    ///
+    /// `NLM_F_REQUEST` and `NLM_F_ACK` are set on the message, and the sequence number is assigned here.
+    pub fn request(&mut self, buffer: &mut [u8], len: usize) -> Result<(), io::Error> {
+        {
+            let mut nl_packet = NetlinkPacket::new_checked(&mut buffer[..len])?;
+            let flags = nl_packet.flags() | Flags::NLM_F_REQUEST | Flags::NLM_F_ACK;
+            nl_packet.set_flags(flags);
+        }
+
+        let seqs = self.send_messages(&mut buffer[..len])?;
+        self.wait_acks(seqs, buffer)
+    }
+
+    /// Send a request (the message in `buffer[..len]`) which has a single reply, e.g. `RTM_GETROUTE`
+    /// without `NLM_F_DUMP`, and wait for it. The reply is received into the same buffer.
+    ///
+    /// Returns the range of the reply message in `buffer`, an error reply (`NLMSG_ERROR`) is returned
+    /// as the error, with the extended ACK message if there is one.
+    pub fn request_reply(&mut self, buffer: &mut [u8], len: usize) -> Result<Range<usize>, io::Error> {
+        {
+            let mut nl_packet = NetlinkPacket::new_checked(&mut buffer[..len])?;
+            let flags = (nl_packet.flags() | Flags::NLM_F_REQUEST) - Flags::NLM_F_ACK;
+            nl_packet.set_flags(flags);
+        }
+
+        self.send_messages(&mut buffer[..len])?;
+        let seq = NetlinkPacket::new_unchecked(&buffer[..len]).seq();
+
+        loop {
+            let amt = self.recv(buffer)?;
+            trace!("read {} bytes from netlink socket.", amt);
+
+            let mut offset = 0;
+            while offset + NetlinkPacket::<&[u8]>::MIN_SIZE <= amt {
+                let pkt = NetlinkPacket::new_checked(&buffer[offset..amt])?;
+                let pkt_len = pkt.total_len();
+                if pkt_len < NetlinkPacket::<&[u8]>::MIN_SIZE {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "packet is too small."));
+                }
+                let start = offset;
+                offset += pkt_len;
+
+                if pkt.seq() != seq {
+                    trace!("Droped netlink message: kind={} seq={}", pkt.kind(), pkt.seq());
+                    continue;
+                }
+
+                if pkt.kind() == Kind::NLMSG_ERROR {
+                    let err_pkt = NetlinkErrorPacket::new_checked(pkt.payload())?;
+                    if err_pkt.errorno() != 0 {
+                        let err = err_pkt.err_with_ext_ack(pkt.flags());
+                        debug!("netlink request (seq {}) failed: {}", seq, err);
+                        return Err(err);
+                    }
+
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "netlink request has no reply."));
+                }
+
+                return Ok(start..offset);
+            }
+        }
+    }
+
+    /// Assign sequence numbers to every message in the buffer and send them at once,
+    /// returns the sequence numbers of the messages which request an ACK.
+    pub fn send_messages(&mut self, messages: &mut [u8]) -> Result<Vec<u32>, io::Error> {
+        let mut seqs = Vec::new();
+        let mut offset = 0;
+        while offset < messages.len() {
+            let mut nl_packet = NetlinkPacket::new_checked(&mut messages[offset..])?;
+            if nl_packet.total_len() < NetlinkPacket::<&[u8]>::MIN_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "packet is too small."));
+            }
+
+            self.seq = self.seq.wrapping_add(1);
+            nl_packet.set_seq(self.seq);
+            if nl_packet.flags().contains(Flags::NLM_F_ACK) {
+                seqs.push(self.seq);
+            }
+
+            offset += nl_packet.total_len();
+        }
+
+        trace!("try send {} bytes netlink messages (seq {:?}).", messages.len(), seqs);
+        self.send(messages)?;
+
+        Ok(seqs)
+    }
+
+    /// Wait for the ACK (`NLMSG_ERROR`) of every sequence number, replies to other requests are ignored.
+    ///
+    /// Returns the first error reported by the kernel, with the extended ACK message if there is one.
+    pub fn wait_acks(&mut self, mut seqs: Vec<u32>, buffer: &mut [u8]) -> Result<(), io::Error> {
+        while !seqs.is_empty() {
+            let amt = self.recv(buffer)?;
+            trace!("read {} bytes from netlink socket.", amt);
+
+            let mut offset = 0;
+            while offset + NetlinkPacket::<&[u8]>::MIN_SIZE <= amt {
+                let pkt = NetlinkPacket::new_checked(&buffer[offset..amt])?;
+                let pkt_len = pkt.total_len();
+                if pkt_len < NetlinkPacket::<&[u8]>::MIN_SIZE {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "packet is too small."));
+                }
+                offset += pkt_len;
+
+                if pkt.kind() != Kind::NLMSG_ERROR {
+                    continue;
+                }
+
+                let pos = match seqs.iter().position(|seq| *seq == pkt.seq()) {
+                    Some(pos) => pos,
+                    None => {
+                        trace!("Droped netlink ACK: seq={}", pkt.seq());
+                        continue;
+                    },
+                };
+                seqs.remove(pos);
+
+                let err_pkt = NetlinkErrorPacket::new_checked(pkt.payload())?;
+                if err_pkt.errorno() != 0 {
+                    let err = err_pkt.err_with_ext_ack(pkt.flags());
+                    debug!("netlink request (seq {}) failed: {}", pkt.seq(), err);
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn sendmsg<T: AsRef<[u8]> + ?Sized>(&mut self, buf: &T) -> Result<usize, io::Error> {
         let buffer = buf.as_ref();
         let ptr = buffer.as_ptr() as *const libc::c_void;
@@ -196,6 +351,162 @@ impl NetlinkSocket {
 }
 
 
+/// The reply of a request sent through `Pipeline`.
+#[derive(Debug)]
+pub struct Ack {
+    pub seq: u32,
+    pub result: Result<(), io::Error>,
+}
+
+/// Send requests without waiting for the kernel, the ACKs are matched to the requests by sequence number.
+///
+/// The socket is non blocking, so the pipeline can be registered on an event loop
+/// (with the `mio` feature) and drained with `next_ack` once it is readable.
+///
+/// ```no_run
+/// use netlink::socket::Pipeline;
+/// use netlink::route::route::RouteRequest;
+/// use netlink::packet::{ Protocol, Kind, Flags, };
+///
+/// let mut pipeline = Pipeline::new(Protocol::NETLINK_ROUTE.into()).unwrap();
+/// let req = RouteRequest::new("10.0.0.0".parse().unwrap(), 8).ifindex(1);
+/// let seq = pipeline.submit(Kind::RTM_NEWROUTE, Flags::NLM_F_CREATE | Flags::NLM_F_REPLACE, |buffer| req.emit(buffer)).unwrap();
+/// loop {
+///     match pipeline.next_ack() {
+///         Ok(ack) => println!("seq {}: {:?}", ack.seq, ack.result),
+///         Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+///         Err(e) => panic!("{}", e),
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Pipeline {
+    nl_socket: NetlinkSocket,
+    // sequence numbers of the requests which are not acknowledged yet
+    pending: Vec<u32>,
+    buffer: Vec<u8>,
+    buffer_len: usize,
+    offset: usize,
+}
+
+impl Pipeline {
+    pub fn new(proto: i32) -> Result<Self, io::Error> {
+        let mut nl_socket = NetlinkSocket::new(proto)?;
+
+        let pid    = 0;
+        let groups = 0;
+        nl_socket.bind(pid, groups)?;
+        nl_socket.set_nonblock()?;
+
+        Ok(Self {
+            nl_socket,
+            pending: Vec::new(),
+            buffer: packet::alloc().to_vec(),
+            buffer_len: 0,
+            offset: 0,
+        })
+    }
+
+    /// Sequence numbers of the requests which are not acknowledged yet.
+    pub fn pending(&self) -> &[u32] {
+        &self.pending
+    }
+
+    /// Send a request and return its sequence number right away.
+    ///
+    /// `emit` writes the payload (e.g. `RouteRequest::emit`) and returns the written length,
+    /// `NLM_F_REQUEST` and `NLM_F_ACK` are always set on the message.
+    pub fn submit<F>(&mut self, kind: Kind, flags: Flags, emit: F) -> Result<u32, io::Error>
+        where F: FnOnce(&mut [u8]) -> Result<usize, io::Error> {
+        let header_len = NetlinkPacket::<&[u8]>::MIN_SIZE;
+        let mut buffer = packet::alloc();
+        let payload_len = emit(&mut buffer[header_len..])?;
+        let len = header_len + payload_len;
+
+        {
+            let mut nl_packet = NetlinkPacket::new_unchecked(&mut buffer[..len]);
+            nl_packet.set_len(len as u32);
+            nl_packet.set_kind(kind);
+            nl_packet.set_flags(flags | Flags::NLM_F_REQUEST | Flags::NLM_F_ACK);
+            nl_packet.set_seq(0);
+            nl_packet.set_pid(0);
+        }
+
+        let seqs = self.nl_socket.send_messages(&mut buffer[..len])?;
+        self.pending.extend_from_slice(&seqs);
+
+        Ok(seqs[0])
+    }
+
+    /// Read the next ACK, returns `WouldBlock` once there is nothing more to read.
+    ///
+    /// Other messages and the ACKs of unknown sequence numbers are skipped.
+    pub fn next_ack(&mut self) -> Result<Ack, io::Error> {
+        loop {
+            if self.offset >= self.buffer_len {
+                // NOTE: reset before reading, so a failed (e.g. `WouldBlock`) read does not replay old ACKs.
+                self.buffer_len = 0;
+                self.offset = 0;
+
+                let amt = self.nl_socket.recv(&mut self.buffer)?;
+                trace!("read {} bytes from netlink socket.", amt);
+                self.buffer_len = amt;
+            }
+
+            if self.buffer_len - self.offset < NetlinkPacket::<&[u8]>::MIN_SIZE {
+                self.offset = self.buffer_len;
+                continue;
+            }
+
+            let pkt = match NetlinkPacket::new_checked(&self.buffer[self.offset..self.buffer_len]) {
+                Ok(pkt) => pkt,
+                Err(e) => {
+                    self.offset = self.buffer_len;
+                    return Err(e);
+                },
+            };
+            let pkt_len = pkt.total_len();
+            if pkt_len < NetlinkPacket::<&[u8]>::MIN_SIZE {
+                self.offset = self.buffer_len;
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "packet is too small."));
+            }
+            self.offset += pkt_len;
+
+            if pkt.kind() != Kind::NLMSG_ERROR {
+                trace!("Droped netlink message: kind={:?} seq={}", pkt.kind(), pkt.seq());
+                continue;
+            }
+
+            let seq = pkt.seq();
+            match self.pending.iter().position(|pending_seq| *pending_seq == seq) {
+                Some(pos) => { self.pending.remove(pos); },
+                None => {
+                    trace!("Droped netlink ACK: seq={}", seq);
+                    continue;
+                },
+            }
+
+            let err_pkt = NetlinkErrorPacket::new_checked(pkt.payload())?;
+            let result = if err_pkt.errorno() != 0 {
+                let err = err_pkt.err_with_ext_ack(pkt.flags());
+                debug!("netlink request (seq {}) failed: {}", seq, err);
+                Err(err)
+            } else {
+                Ok(())
+            };
+
+            return Ok(Ack { seq, result });
+        }
+    }
+}
+
+impl AsRawFd for Pipeline {
+    fn as_raw_fd(&self) -> RawFd {
+        self.nl_socket.as_raw_fd()
+    }
+}
+
+
 impl AsRawFd for NetlinkSocket {
     fn as_raw_fd(&self) -> RawFd {
         self.fd
@@ -231,3 +542,86 @@ impl Drop for NetlinkSocket {
     }
 }
 
+
+#[cfg(feature = "mio")]
+mod mio {
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    use mio::{Ready, Poll, PollOpt, Token};
+    use mio::event::Evented;
+    use mio::unix::EventedFd;
+
+    impl Evented for super::NetlinkSocket {
+        fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+            EventedFd(&self.as_raw_fd()).register(poll, token, interest, opts)
+        }
+
+        fn reregister(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+            EventedFd(&self.as_raw_fd()).reregister(poll, token, interest, opts)
+        }
+
+        fn deregister(&self, poll: &Poll) -> io::Result<()> {
+            EventedFd(&self.as_raw_fd()).deregister(poll)
+        }
+    }
+
+    impl Evented for super::Pipeline {
+        fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+            self.nl_socket.register(poll, token, interest, opts)
+        }
+
+        fn reregister(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+            self.nl_socket.reregister(poll, token, interest, opts)
+        }
+
+        fn deregister(&self, poll: &Poll) -> io::Result<()> {
+            self.nl_socket.deregister(poll)
+        }
+    }
+}
+
+
+#[cfg(test)]
+fn test_next_ack(pipeline: &mut Pipeline) -> Ack {
+    for _ in 0..100 {
+        match pipeline.next_ack() {
+            Ok(ack) => return ack,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::sleep(std::time::Duration::from_millis(10)),
+            Err(e) => panic!("{}", e),
+        }
+    }
+    panic!("timed out waiting for netlink ACK");
+}
+
+#[test]
+fn test_pipeline() {
+    use crate::packet::{ AddressFamily, LinkPacket, Protocol, };
+
+    let emit_link = |ifindex: i32| move |buffer: &mut [u8]| -> Result<usize, io::Error> {
+        let len = LinkPacket::<&[u8]>::MIN_SIZE;
+        let mut link_packet = LinkPacket::new_unchecked(&mut buffer[..len]);
+        link_packet.set_family(AddressFamily::AF_UNSPEC);
+        link_packet.set_ifindex(ifindex);
+        Ok(len)
+    };
+
+    let mut pipeline = Pipeline::new(Protocol::NETLINK_ROUTE.into()).unwrap();
+    // The loopback link always exists, its `RTM_NEWLINK` reply comes before the ACK and is skipped.
+    let get_seq = pipeline.submit(Kind::RTM_GETLINK, Flags::empty(), emit_link(1)).unwrap();
+    // NOTE: There is no such link, nothing is changed even when running as root.
+    let del_seq = pipeline.submit(Kind::RTM_DELLINK, Flags::empty(), emit_link(0x7FFF_FFF0)).unwrap();
+    assert_ne!(get_seq, del_seq);
+    assert_eq!(pipeline.pending(), &[get_seq, del_seq]);
+
+    let ack = test_next_ack(&mut pipeline);
+    assert_eq!(ack.seq, get_seq);
+    assert!(ack.result.is_ok());
+
+    let ack = test_next_ack(&mut pipeline);
+    assert_eq!(ack.seq, del_seq);
+    assert!(ack.result.is_err());
+
+    assert!(pipeline.pending().is_empty());
+    assert_eq!(pipeline.next_ack().unwrap_err().kind(), io::ErrorKind::WouldBlock);
+}
@@ -255,9 +255,7 @@ pub struct nlattr {
 // O := Payload stored in network byte order
 // 
 // Note: The N and O flag are mutually exclusive.
-pub const NLA_F_NESTED: i32        = 1 << 15;
-pub const NLA_F_NET_BYTEORDER: i32 = 1 << 14;
-pub const NLA_TYPE_MASK: i32       = !(NLA_F_NESTED | NLA_F_NET_BYTEORDER);
+// NLA_F_NESTED, NLA_F_NET_BYTEORDER and NLA_TYPE_MASK are defined in `packet::netlink`.
 pub const NLA_ALIGNTO: usize       = 4;
 
 #[inline]
@@ -321,7 +321,7 @@ pub fn load_resolver_config() -> Result<ResolverConfig, io::Error> {
 }
 
 pub fn save_resolver_config(config: &ResolverConfig) -> Result<(), io::Error> {
-    let mut file = OpenOptions::new().create(true).write(true).open(RESOLVER_CONFIG_FILE_PATH)?;
+    let mut file = OpenOptions::new().create(true).write(true).truncate(true).open(RESOLVER_CONFIG_FILE_PATH)?;
     for nameserver in config.nameservers.iter() {
         file.write_all(format!("nameserver {}{}", nameserver, LINE_BREAK).as_bytes())?;
     }
@@ -338,7 +338,7 @@ pub fn save_resolver_config(config: &ResolverConfig) -> Result<(), io::Error> {
         .map(|cidr| {
             let addr = cidr.address();
             let netmask = netmask_from_ipcidr(*cidr);
-            format!("{}/{}", addr, cidr)
+            format!("{}/{}", addr, netmask)
         })
         .collect::<Vec<String>>()
         .join(" ");
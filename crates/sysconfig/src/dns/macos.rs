@@ -225,6 +225,7 @@ pub struct Dns {
     pub default_addrs: Option<Vec<IpAddr>>,
     pub manually_specifying_domain_name: Option<String>,
     pub manually_specifying_addrs: Option<Vec<IpAddr>>,
+    pub manually_specifying_search_domains: Option<Vec<String>>,
 }
 
 impl SCNetworkService {
@@ -249,6 +250,7 @@ impl SCNetworkService {
         let mut default_addrs: Option<Vec<IpAddr>> = None;
         let mut manually_specifying_domain_name: Option<String> = None;
         let mut manually_specifying_addrs: Option<Vec<IpAddr>> = None;
+        let mut manually_specifying_search_domains: Option<Vec<String>> = None;
 
         if let Some(value) = store.get(CFString::new(&format!("State:/Network/Service/{}/DNS", self.id()))) {
             if let Some(dict) = value.downcast_into::<CFDictionary>() {
@@ -303,6 +305,19 @@ impl SCNetworkService {
                         }
                     }
                 }
+
+                if let Some(domains) = dict.find(CFString::from_static_string("SearchDomains").as_concrete_TypeRef().as_void_ptr()) {
+                    let domains = unsafe { CFType::wrap_under_get_rule(*domains) };
+                    if let Some(domains) = domains.downcast::<CFArray<CFTypeRef>>() {
+                        let temp = domains.iter()
+                            .map(|domain| unsafe { CFString::wrap_under_get_rule(*domain as *const _).to_string() })
+                            .collect::<Vec<String>>();
+
+                        if temp.len() > 0 {
+                            manually_specifying_search_domains = Some(temp);
+                        }
+                    }
+                }
             }
         }
 
@@ -311,14 +326,23 @@ impl SCNetworkService {
             default_addrs: default_addrs,
             manually_specifying_domain_name: manually_specifying_domain_name,
             manually_specifying_addrs: manually_specifying_addrs,
+            manually_specifying_search_domains: manually_specifying_search_domains,
         }
     }
 
     /// 需要 ROOT 权限执行
     pub fn set_dns(&self, addrs: &[ IpAddr ]) -> Result<bool, std::io::Error> {
+        self.set_dns_config(addrs, &[])
+    }
+
+    /// 设置 DNS 服务器以及搜索域，为空的项会被移除 (恢复为 DHCP 获得的配置)
+    /// 
+    /// 需要 ROOT 权限执行
+    pub fn set_dns_config(&self, addrs: &[ IpAddr ], search_domains: &[ String ]) -> Result<bool, std::io::Error> {
         // https://00f.net/2011/08/14/programmatically-changing-network-configuration-on-osx/
         // sudo networksetup -getdnsservers "Wi-Fi"
         // sudo networksetup -setdnsservers "Wi-Fi" "Empty"
+        // sudo networksetup -setsearchdomains "Wi-Fi" "Empty"
         if unsafe { libc::getuid() } != 0 {
             return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
         }
@@ -326,16 +350,30 @@ impl SCNetworkService {
         let store = SCDynamicStoreBuilder::new(SESSION_NAME).build();
 
         let mut dns_dictionary = CFMutableDictionary::new();
-        let d_keys = CFString::from_static_string("ServerAddresses");
-        let d_values = CFArray::from_CFTypes(
-                            &addrs
-                            .iter()
-                            .map(|s| CFString::new(&format!("{}", s)) )
-                            .collect::<Vec<CFString>>());
-        
-        dns_dictionary.add(
-            &d_keys.as_concrete_TypeRef().as_void_ptr(),
-            &d_values.as_concrete_TypeRef().as_void_ptr());
+        if addrs.len() > 0 {
+            let d_keys = CFString::from_static_string("ServerAddresses");
+            let d_values = CFArray::from_CFTypes(
+                                &addrs
+                                .iter()
+                                .map(|s| CFString::new(&format!("{}", s)) )
+                                .collect::<Vec<CFString>>());
+            
+            dns_dictionary.add(
+                &d_keys.as_concrete_TypeRef().as_void_ptr(),
+                &d_values.as_concrete_TypeRef().as_void_ptr());
+        }
+        if search_domains.len() > 0 {
+            let d_keys = CFString::from_static_string("SearchDomains");
+            let d_values = CFArray::from_CFTypes(
+                                &search_domains
+                                .iter()
+                                .map(|s| CFString::new(s) )
+                                .collect::<Vec<CFString>>());
+            
+            dns_dictionary.add(
+                &d_keys.as_concrete_TypeRef().as_void_ptr(),
+                &d_values.as_concrete_TypeRef().as_void_ptr());
+        }
         let dns_dictionary = dns_dictionary.as_CFType().downcast::<CFDictionary>().unwrap();
         
         let key = format!("Setup:/Network/Service/{}/DNS", self.id());
@@ -0,0 +1,256 @@
+// Firewall rules through nftables (netlink), every rule lives in the `inet exodus` table,
+// which is rebuilt in a single batch on every change, so that rules are added and removed
+// atomically and nothing outside of the table is touched.
+//
+// NOTE: NAT chains in the `inet` family require Linux 5.2 or later.
+//       A packet has to be accepted by every base chain it traverses, so packets dropped
+//       by other tables (e.g. iptables FORWARD policy) are not accepted by the rules here.
+use crate::IpCidr;
+
+use netlink::netfilter::{ self, Batch, Chain, ChainType, Hook, NetfilterController, ProtoFamily, Table, Verdict, };
+
+use std::io;
+use std::net::{ IpAddr, SocketAddr, };
+
+
+const TABLE_NAME: &str = "exodus";
+
+const CHAIN_INPUT: &str       = "input";
+const CHAIN_FORWARD: &str     = "forward";
+const CHAIN_OUTPUT: &str      = "output";
+const CHAIN_PREROUTING: &str  = "prerouting";
+const CHAIN_POSTROUTING: &str = "postrouting";
+const CHAIN_KILL_SWITCH: &str = "kill_switch";
+
+const CHAINS: [&str; 6] = [ CHAIN_INPUT, CHAIN_FORWARD, CHAIN_OUTPUT, CHAIN_PREROUTING, CHAIN_POSTROUTING, CHAIN_KILL_SWITCH ];
+
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum FilterChain {
+    Input,
+    Forward,
+    Output,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Action {
+    Accept,
+    Drop,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    fn number(&self) -> u8 {
+        match *self {
+            Protocol::Tcp => netfilter::IPPROTO_TCP,
+            Protocol::Udp => netfilter::IPPROTO_UDP,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Rule {
+    /// Accept or drop packets by interface and address, unset fields match everything.
+    Filter {
+        chain: FilterChain,
+        in_ifname: Option<String>,
+        out_ifname: Option<String>,
+        source: Option<IpCidr>,
+        destination: Option<IpCidr>,
+        action: Action,
+    },
+    /// nft add rule inet exodus postrouting ip saddr <source> oifname <out_ifname> masquerade
+    Masquerade { source: IpCidr, out_ifname: String },
+    /// nft add rule inet exodus postrouting ip saddr <source> oifname <out_ifname> snat ip to <to>
+    Snat { source: IpCidr, out_ifname: String, to: IpAddr },
+    /// nft add rule inet exodus prerouting iifname <in_ifname> <protocol> dport <port> dnat ip to <to>
+    Dnat { in_ifname: String, protocol: Protocol, port: u16, to: SocketAddr },
+    /// Drop every outgoing packet except the ones going through the tunnel interface,
+    /// to the loopback interface, or to the VPN server endpoint.
+    KillSwitch { tun_ifname: String, server_addr: SocketAddr },
+}
+
+impl Rule {
+    /// Accept forwarded packets coming from or going to the interface.
+    pub fn forward_accept(ifname: &str) -> Vec<Self> {
+        vec![
+            Rule::Filter {
+                chain: FilterChain::Forward,
+                in_ifname: Some(ifname.to_string()),
+                out_ifname: None,
+                source: None,
+                destination: None,
+                action: Action::Accept,
+            },
+            Rule::Filter {
+                chain: FilterChain::Forward,
+                in_ifname: None,
+                out_ifname: Some(ifname.to_string()),
+                source: None,
+                destination: None,
+                action: Action::Accept,
+            },
+        ]
+    }
+
+    fn chain(&self) -> &'static str {
+        match *self {
+            Rule::Filter { chain: FilterChain::Input, .. } => CHAIN_INPUT,
+            Rule::Filter { chain: FilterChain::Forward, .. } => CHAIN_FORWARD,
+            Rule::Filter { chain: FilterChain::Output, .. } => CHAIN_OUTPUT,
+            Rule::Masquerade { .. } | Rule::Snat { .. } => CHAIN_POSTROUTING,
+            Rule::Dnat { .. } => CHAIN_PREROUTING,
+            Rule::KillSwitch { .. } => CHAIN_KILL_SWITCH,
+        }
+    }
+
+    fn to_nftables(&self, table: &Table) -> Vec<netfilter::Rule> {
+        let rule = netfilter::Rule::new(table, self.chain());
+
+        match self {
+            Rule::Filter { in_ifname, out_ifname, source, destination, action, .. } => {
+                let mut rule = rule;
+                if let Some(ifname) = in_ifname {
+                    rule = rule.iifname(ifname);
+                }
+                if let Some(ifname) = out_ifname {
+                    rule = rule.oifname(ifname);
+                }
+                if let Some(cidr) = source {
+                    rule = rule.saddr(cidr);
+                }
+                if let Some(cidr) = destination {
+                    rule = rule.daddr(cidr);
+                }
+                let rule = match action {
+                    Action::Accept => rule.accept(),
+                    Action::Drop => rule.drop(),
+                };
+
+                vec![rule]
+            },
+            Rule::Masquerade { source, out_ifname } => {
+                vec![rule.saddr(source).oifname(out_ifname).masquerade()]
+            },
+            Rule::Snat { source, out_ifname, to } => {
+                vec![rule.saddr(source).oifname(out_ifname).snat(*to)]
+            },
+            Rule::Dnat { in_ifname, protocol, port, to } => {
+                vec![rule.iifname(in_ifname).dport(protocol.number(), *port).dnat(to.ip(), Some(to.port()))]
+            },
+            Rule::KillSwitch { tun_ifname, server_addr } => {
+                let server_cidr = IpCidr::new(server_addr.ip().into(), if server_addr.is_ipv4() { 32 } else { 128 });
+                vec![
+                    rule.clone().oifname("lo").accept(),
+                    rule.clone().oifname(tun_ifname).accept(),
+                    rule.daddr(&server_cidr).dport(netfilter::IPPROTO_UDP, server_addr.port()).accept(),
+                ]
+            },
+        }
+    }
+}
+
+
+fn table() -> Table {
+    Table::new(ProtoFamily::NFPROTO_INET, TABLE_NAME)
+}
+
+fn chain(table: &Table, name: &str) -> Chain {
+    match name {
+        CHAIN_INPUT => Chain::base(table, name, ChainType::Filter, Hook::NF_INET_LOCAL_IN, 0, Verdict::Accept),
+        CHAIN_FORWARD => Chain::base(table, name, ChainType::Filter, Hook::NF_INET_FORWARD, 0, Verdict::Accept),
+        CHAIN_OUTPUT => Chain::base(table, name, ChainType::Filter, Hook::NF_INET_LOCAL_OUT, 0, Verdict::Accept),
+        CHAIN_PREROUTING => Chain::base(table, name, ChainType::Nat, Hook::NF_INET_PRE_ROUTING, -100, Verdict::Accept),
+        CHAIN_POSTROUTING => Chain::base(table, name, ChainType::Nat, Hook::NF_INET_POST_ROUTING, 100, Verdict::Accept),
+        // Drops whatever the kill switch rules do not accept.
+        CHAIN_KILL_SWITCH => Chain::base(table, name, ChainType::Filter, Hook::NF_INET_LOCAL_OUT, 10, Verdict::Drop),
+        _ => unreachable!(),
+    }
+}
+
+
+/// The firewall rules owned by exodus.
+#[derive(Debug, Default)]
+pub struct Firewall {
+    rules: Vec<Rule>,
+}
+
+impl Firewall {
+    pub fn new() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn add(&mut self, rule: Rule) -> Result<(), io::Error> {
+        self.rules.push(rule);
+        if let Err(e) = self.commit() {
+            self.rules.pop();
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, rule: &Rule) -> Result<(), io::Error> {
+        let pos = match self.rules.iter().position(|item| item == rule) {
+            Some(pos) => pos,
+            None => return Ok(()),
+        };
+
+        let rule = self.rules.remove(pos);
+        if let Err(e) = self.commit() {
+            self.rules.insert(pos, rule);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Remove every rule together with the table.
+    pub fn clear(&mut self) -> Result<(), io::Error> {
+        self.rules.clear();
+        self.commit()
+    }
+
+    fn commit(&self) -> Result<(), io::Error> {
+        let table = table();
+        let mut batch = Batch::new();
+
+        // NOTE: Creating the table first makes deleting it succeed even if it does not exist yet.
+        batch.add_table(&table);
+        batch.delete_table(&table);
+
+        if !self.rules.is_empty() {
+            batch.add_table(&table);
+
+            for name in CHAINS.iter() {
+                let rules = self.rules.iter()
+                    .filter(|rule| rule.chain() == *name)
+                    .flat_map(|rule| rule.to_nftables(&table))
+                    .collect::<Vec<netfilter::Rule>>();
+                if rules.is_empty() {
+                    continue;
+                }
+
+                batch.add_chain(&chain(&table, name));
+                for rule in rules.iter() {
+                    batch.add_rule(rule);
+                }
+            }
+        }
+
+        let mut buffer = netlink::packet::alloc();
+        NetfilterController::new()?.execute(batch, &mut buffer)?;
+        debug!("nft table inet {}: {} rules", TABLE_NAME, self.rules.len());
+
+        Ok(())
+    }
+}
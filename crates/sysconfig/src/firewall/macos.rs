@@ -1,2 +1,67 @@
 use libc;
-// use pfctl;
\ No newline at end of file
+// use pfctl;
+
+use std::io::{ self, Write, };
+use std::net::SocketAddr;
+use std::process::{ Command, Stdio, };
+
+
+// NOTE: The default /etc/pf.conf evaluates every anchor under `com.apple/*`,
+//       so the rules take effect without touching the main ruleset.
+const KILL_SWITCH_ANCHOR: &str = "com.apple/exodus";
+
+
+fn pfctl(args: &[&str], stdin: Option<&str>) -> Result<String, io::Error> {
+    debug!("pfctl {}", args.join(" "));
+
+    let mut child = Command::new("pfctl")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(input) = stdin {
+        child.stdin.take().unwrap().write_all(input.as_bytes())?;
+    }
+
+    let output = child.wait_with_output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, stderr.trim().to_string()));
+    }
+
+    Ok(stderr.to_string())
+}
+
+/// Block every outgoing packet except the ones going through the tunnel interface,
+/// to the loopback interface, or to the VPN server endpoint.
+///
+/// pf is enabled if it is not yet, and stays enabled after the kill switch is removed
+/// (the anchor is empty by then).
+pub fn add_kill_switch(tun_ifname: &str, server_addr: &SocketAddr) -> Result<(), io::Error> {
+    let family = if server_addr.is_ipv4() { "inet" } else { "inet6" };
+    let rules = format!("block drop out all
+pass out quick on lo0 all
+pass out quick on {tun_ifname} all
+pass out quick {family} proto udp from any to {ip} port {port}
+",
+        tun_ifname = tun_ifname,
+        family = family,
+        ip = server_addr.ip(),
+        port = server_addr.port());
+
+    pfctl(&["-a", KILL_SWITCH_ANCHOR, "-f", "-"], Some(&rules))?;
+
+    match pfctl(&["-e"], None) {
+        Ok(_) => Ok(()),
+        Err(ref e) if e.to_string().contains("already enabled") => Ok(()),
+        Err(e) => {
+            let _ = remove_kill_switch();
+            Err(e)
+        },
+    }
+}
+
+pub fn remove_kill_switch() -> Result<(), io::Error> {
+    pfctl(&["-a", KILL_SWITCH_ANCHOR, "-F", "rules"], None).map(|_| ())
+}
@@ -60,11 +60,11 @@ pub fn ipv6_forwarding() -> Result<bool, io::Error> {
 #[inline]
 pub fn enable_ipv6_forwarding() -> Result<bool, io::Error> {
     let _ = set_value(IPV6_KEY, ONE)?;
-    ipv4_forwarding()
+    ipv6_forwarding()
 }
 
 #[inline]
 pub fn disable_ipv6_forwarding() -> Result<bool, io::Error> {
     let _ = set_value(IPV6_KEY, ZERO)?;
-    ipv4_forwarding()
+    ipv6_forwarding()
 }
@@ -4,6 +4,8 @@ extern crate log;
 extern crate libc;
 #[cfg(unix)]
 extern crate sysctl;
+#[cfg(target_os = "linux")]
+extern crate netlink;
 
 #[cfg(target_os = "macos")]
 extern crate core_foundation;
@@ -19,6 +21,8 @@ pub mod route;
 pub mod neigh;
 pub mod firewall;
 pub mod ip_forwarding;
+#[cfg(target_os = "linux")]
+pub mod netns;
 
 
 pub use smoltcp::wire::IpCidr;
@@ -0,0 +1,115 @@
+use netlink::route::RouteController;
+use netlink::packet::{ MacAddr, NeighbourState, };
+
+use smoltcp::wire::EthernetAddress;
+
+use std::io;
+use std::net::IpAddr;
+
+
+// $ ip neigh list
+pub fn list<'a>(buffer: &'a mut Vec<u8>) -> Result<NeighTable<'a>, io::Error> {
+    buffer.resize(netlink::packet::MAX_NL_LENGTH, 0);
+
+    let mut route_controller = RouteController::new()?;
+    let mut neighs = Vec::new();
+    for neigh in route_controller.neighbours(&mut buffer[..])? {
+        let neigh = neigh?;
+        // NOTE: Entries which are still resolving (or failed) have no link layer address.
+        let (ip_addr, hw_addr) = match (neigh.dst_addr, neigh.hw_addr) {
+            (Some(ip_addr), Some(hw_addr)) => (ip_addr, hw_addr),
+            _ => continue,
+        };
+
+        neighs.push(Neigh {
+            ip_addr,
+            link_addr: EthernetAddress(hw_addr.0),
+            link_index: neigh.ifindex,
+            state: neigh.state.into(),
+        });
+    }
+
+    Ok(NeighTable { neighs: neighs.into_iter(), _buffer: buffer })
+}
+
+#[derive(Debug, Clone)]
+pub struct Neigh {
+    pub ip_addr: IpAddr,
+    pub link_addr: EthernetAddress,
+    pub link_index: u32,
+    pub state: State,
+}
+
+
+pub struct NeighTable<'a> {
+    neighs: std::vec::IntoIter<Neigh>,
+    _buffer: &'a mut Vec<u8>,
+}
+
+impl<'a> Iterator for NeighTable<'a> {
+    type Item = Neigh;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.neighs.next()
+    }
+}
+
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum State {
+    /// A static entry, never expires (`nud permanent`).
+    Permanent,
+    /// A confirmed entry, expires like the ones learned by ARP/NDP (`nud reachable`).
+    Reachable,
+    /// An expired entry, verified by the kernel before it's used (`nud stale`).
+    Stale,
+}
+
+impl Into<NeighbourState> for State {
+    fn into(self) -> NeighbourState {
+        match self {
+            State::Permanent => NeighbourState::NUD_PERMANENT,
+            State::Reachable => NeighbourState::NUD_REACHABLE,
+            State::Stale => NeighbourState::NUD_STALE,
+        }
+    }
+}
+
+impl From<NeighbourState> for State {
+    /// The dynamic states other than `reachable` (`delay`, `probe`, `noarp`, ...) are treated as `stale`.
+    fn from(value: NeighbourState) -> Self {
+        if value.contains(NeighbourState::NUD_PERMANENT) {
+            State::Permanent
+        } else if value.contains(NeighbourState::NUD_REACHABLE) {
+            State::Reachable
+        } else {
+            State::Stale
+        }
+    }
+}
+
+// $ ip neigh get <ip_addr> dev <link_index>
+pub fn get(ip_addr: IpAddr, link_index: u32) -> Result<Option<Neigh>, io::Error> {
+    let mut buffer = Vec::new();
+    let neigh = list(&mut buffer)?.find(|neigh| neigh.ip_addr == ip_addr && neigh.link_index == link_index);
+
+    Ok(neigh)
+}
+
+// $ ip neigh add <ip_addr> lladdr <link_addr> dev <link_index> nud <state>
+pub fn add(ip_addr: IpAddr, link_addr: EthernetAddress, link_index: u32, state: State) -> Result<(), io::Error> {
+    let mut buffer = [0u8; 1024];
+    RouteController::new()?.add_neighbour(link_index, ip_addr, MacAddr(link_addr.0), state.into(), &mut buffer)
+}
+
+// $ ip neigh replace <ip_addr> lladdr <link_addr> dev <link_index> nud <state>
+pub fn replace(ip_addr: IpAddr, link_addr: EthernetAddress, link_index: u32, state: State) -> Result<(), io::Error> {
+    let mut buffer = [0u8; 1024];
+    RouteController::new()?.replace_neighbour(link_index, ip_addr, MacAddr(link_addr.0), state.into(), &mut buffer)
+}
+
+// $ ip neigh del <ip_addr> dev <link_index>
+pub fn delete(ip_addr: IpAddr, link_index: u32) -> Result<(), io::Error> {
+    let mut buffer = [0u8; 1024];
+    RouteController::new()?.remove_neighbour(link_index, ip_addr, &mut buffer)
+}
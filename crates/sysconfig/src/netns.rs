@@ -0,0 +1,26 @@
+// Network namespaces, Linux only.
+use netlink::route::RouteController;
+
+pub use netlink::netns::{ NetNs, list, create, delete, };
+
+use std::io;
+use std::ffi::CString;
+
+
+// $ ip link set dev <ifname> netns <name>
+pub fn move_link(ifname: &str, name: &str) -> Result<(), io::Error> {
+    let ifname = CString::new(ifname).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let ifindex = unsafe { libc::if_nametoindex(ifname.as_ptr()) };
+    if ifindex == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let netns = NetNs::open(name)?;
+    let mut buffer = [0u8; 1024];
+    RouteController::new()?.set_link_netns(ifindex, netns.target(), &mut buffer)
+}
+
+// $ ip netns exec <name> ...
+pub fn exec<F, T>(name: &str, f: F) -> Result<T, io::Error> where F: FnOnce() -> T {
+    NetNs::open(name)?.run(f)
+}
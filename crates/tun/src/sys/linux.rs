@@ -4,8 +4,9 @@ use libc::{sockaddr, c_void, c_char, c_uchar, c_short, c_ushort, c_int, c_uint,
 
 use std::ptr;
 use std::mem;
+use std::thread;
 use std::ffi::{CStr, CString};
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::io::{self, Error, ErrorKind};
 use std::os::unix::io::{RawFd, AsRawFd, IntoRawFd};
 
@@ -80,6 +81,14 @@ pub struct ifreq {
     pub ifru: ifru,
 }
 
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct in6_ifreq {
+    pub ifr6_addr:      libc::in6_addr,
+    pub ifr6_prefixlen: u32,
+    pub ifr6_ifindex:   c_int,
+}
+
 ioctl!(bad read siocgifflags with 0x8913; ifreq);
 ioctl!(bad write siocsifflags with 0x8914; ifreq);
 ioctl!(bad read siocgifaddr with 0x8915; ifreq);
@@ -93,6 +102,8 @@ ioctl!(bad write siocsifnetmask with 0x891c; ifreq);
 ioctl!(bad read siocgifmtu with 0x8921; ifreq);
 ioctl!(bad write siocsifmtu with 0x8922; ifreq);
 ioctl!(bad write siocsifname with 0x8923; ifreq);
+ioctl!(bad read siocgifindex with 0x8933; ifreq);
+ioctl!(bad write siocsifaddr6 with 0x8916; in6_ifreq);
 
 ioctl!(write tunsetiff with b'T', 202; c_int);
 ioctl!(write tunsetpersist with b'T', 203; c_int);
@@ -105,6 +116,7 @@ pub struct Device {
     name: String,
     tun: RawFd,
     ctl: RawFd,
+    ctl6: RawFd,
 }
 
 impl Device {
@@ -114,7 +126,7 @@ impl Device {
             return Err(Error::new(ErrorKind::InvalidInput, "name too long"));
         }
 
-        let (tun, ctl, name) = unsafe {
+        let (tun, ctl, ctl6, name) = unsafe {
             let tun = libc::open(b"/dev/net/tun\0".as_ptr() as *const _, libc::O_RDWR);
             if tun < 0 {
                 return Err(io::Error::last_os_error());
@@ -134,16 +146,38 @@ impl Device {
                 return Err(io::Error::last_os_error());
             }
 
-            (tun, ctl, CStr::from_ptr(req.ifrn.name.as_ptr()).to_string_lossy().into())
+            // IPv6 addresses have to be configured through an AF_INET6 socket,
+            // it's created here so that it lives in the same namespace as the device.
+            // NOTE: It fails when IPv6 is disabled in the kernel.
+            let ctl6 = libc::socket(libc::AF_INET6, libc::SOCK_DGRAM, 0);
+
+            (tun, ctl, ctl6, CStr::from_ptr(req.ifrn.name.as_ptr()).to_string_lossy().into())
         };
 
         Ok(Device {
             name: name,
             tun:  tun,
             ctl:  ctl,
+            ctl6: ctl6,
         })
     }
 
+    /// Create the device inside another network namespace, `netns` refers to the namespace
+    /// (e.g. an opened `/run/netns/<name>`).
+    ///
+    /// The device is created on a dedicated thread which joins the namespace and exits afterwards,
+    /// so the namespace of the calling thread never changes. The device stays in `netns`.
+    pub fn new_in_netns(name: &str, netns: RawFd) -> Result<Self, Error> {
+        let name = name.to_string();
+        thread::spawn(move || -> Result<Self, Error> {
+            if unsafe { libc::setns(netns, libc::CLONE_NEWNET) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Self::new(&name)
+        }).join().unwrap_or_else(|_| Err(io::Error::new(ErrorKind::Other, "thread panicked")))
+    }
+
     /// Set the owner of the device.
     pub fn user(&mut self, value: i32) -> Result<(), Error> {
         unsafe {
@@ -226,6 +260,36 @@ impl Device {
         }
     }
 
+    /// Add an IPv6 address to the device.
+    pub fn add_address_v6<T: Into<Ipv6Addr>>(&mut self, value: T, prefix_len: u8) -> Result<(), Error> {
+        if prefix_len > 128 {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid prefix length"));
+        }
+
+        if self.ctl6 < 0 {
+            return Err(Error::new(ErrorKind::Other, "IPv6 is not supported"));
+        }
+
+        unsafe {
+            // NOTE: `if_nametoindex` looks up the namespace of the calling thread.
+            let mut ifreq = self.request();
+            if siocgifindex(self.ctl, &mut ifreq) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut req: in6_ifreq = mem::zeroed();
+            req.ifr6_addr.s6_addr = value.into().octets();
+            req.ifr6_prefixlen = prefix_len as u32;
+            req.ifr6_ifindex = ifreq.ifru.ivalue;
+
+            if siocsifaddr6(self.ctl6, &req) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        }
+    }
+
     pub fn destination(&self) -> Result<Ipv4Addr, Error> {
         unsafe {
             let mut req = self.request();
@@ -378,9 +442,41 @@ impl Drop for Device {
             if self.ctl >= 0 {
                 libc::close(self.ctl);
             }
+            if self.ctl6 >= 0 {
+                libc::close(self.ctl6);
+            }
             if self.tun >= 0 {
                 libc::close(self.tun);
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+#[ignore] // NOTE: Requires root, run with `cargo test -- --ignored`.
+fn test_new_in_netns() {
+    use std::fs;
+    use std::process::Command;
+
+    let netns_name = "exodus-tun-test";
+    let ifname = "exotun-test0";
+    let ip = |args: &[&str]| Command::new("ip").args(args).output().unwrap().status.success();
+
+    let origin = fs::read_link("/proc/thread-self/ns/net").unwrap();
+    assert!(Device::new_in_netns(ifname, -1).is_err());
+    assert_eq!(fs::read_link("/proc/thread-self/ns/net").unwrap(), origin);
+
+    assert!(ip(&["netns", "add", netns_name]));
+    let netns = fs::File::open(format!("/run/netns/{}", netns_name)).unwrap();
+    let ret = Device::new_in_netns(ifname, netns.as_raw_fd());
+    let in_netns = ip(&["-n", netns_name, "link", "show", ifname]);
+    let in_origin = ip(&["link", "show", ifname]);
+    let current = fs::read_link("/proc/thread-self/ns/net").unwrap();
+    drop(ret);
+    drop(netns);
+    assert!(ip(&["netns", "del", netns_name]));
+
+    assert!(in_netns);
+    assert!(!in_origin);
+    assert_eq!(current, origin);
+}
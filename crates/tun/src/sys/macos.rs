@@ -5,7 +5,7 @@ use libc::{self, sockaddr, c_void, c_char, c_short, c_ushort, c_int, c_uint};
 use std::ptr;
 use std::mem;
 use std::ffi::CStr;
-use std::net::Ipv4Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::io::{self, Error, ErrorKind};
 use std::os::unix::io::{RawFd, AsRawFd, IntoRawFd};
 
@@ -23,6 +23,7 @@ pub const UTUN_OPT_IFNAME: c_int      = 2;
 pub const UTUN_FLAGS_NO_OUTPUT: c_int = 0x0001;
 pub const UTUN_FLAGS_NO_INPUT: c_int  = 0x0002;
 pub const UTUN_CONTROL_NAME: &str     = "com.apple.net.utun_control";
+pub const ND6_INFINITE_LIFETIME: u32  = 0xffffffff;
 
 
 
@@ -110,6 +111,26 @@ pub struct ifaliasreq {
     pub mask: sockaddr,
 }
 
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct in6_addrlifetime {
+    pub ia6t_expire: libc::time_t,
+    pub ia6t_preferred: libc::time_t,
+    pub ia6t_vltime: u32,
+    pub ia6t_pltime: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct in6_aliasreq {
+    pub ifra_name: [c_char; IFNAMSIZ],
+    pub ifra_addr: libc::sockaddr_in6,
+    pub ifra_dstaddr: libc::sockaddr_in6,
+    pub ifra_prefixmask: libc::sockaddr_in6,
+    pub ifra_flags: c_int,
+    pub ifra_lifetime: in6_addrlifetime,
+}
+
 ioctl!(readwrite ctliocginfo with 'N', 3; ctl_info);
 
 ioctl!(write siocsifflags with 'i', 16; ifreq);
@@ -133,6 +154,17 @@ ioctl!(readwrite siocgifmtu with 'i', 51; ifreq);
 ioctl!(write siocaifaddr with 'i', 26; ifaliasreq);
 ioctl!(write siocdifaddr with 'i', 25; ifreq);
 
+ioctl!(write siocaifaddr_in6 with 'i', 26; in6_aliasreq);
+
+
+fn sockaddr_in6(addr: Ipv6Addr) -> libc::sockaddr_in6 {
+    let mut sa: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+    sa.sin6_len = mem::size_of::<libc::sockaddr_in6>() as u8;
+    sa.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+    sa.sin6_addr.s6_addr = addr.octets();
+    sa
+}
+
 
 #[derive(Debug)]
 pub struct Device {
@@ -265,6 +297,42 @@ impl Device {
         }
     }
 
+    /// Add an IPv6 address to the device.
+    pub fn add_address_v6<T: Into<Ipv6Addr>>(&mut self, value: T, prefix_len: u8) -> Result<(), Error> {
+        if prefix_len > 128 {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid prefix length"));
+        }
+
+        let name = self.name()?;
+        let mask = if prefix_len == 0 { 0u128 } else { !0u128 << (128 - prefix_len as u32) };
+
+        unsafe {
+            let mut req: in6_aliasreq = mem::zeroed();
+            ptr::copy_nonoverlapping(name.as_ptr() as *const c_char, req.ifra_name.as_mut_ptr(), name.len());
+
+            req.ifra_addr = sockaddr_in6(value.into());
+            req.ifra_prefixmask = sockaddr_in6(Ipv6Addr::from(mask));
+            req.ifra_lifetime.ia6t_vltime = ND6_INFINITE_LIFETIME;
+            req.ifra_lifetime.ia6t_pltime = ND6_INFINITE_LIFETIME;
+
+            // IPv6 addresses have to be configured through an AF_INET6 socket.
+            let ctl6 = libc::socket(libc::AF_INET6, libc::SOCK_DGRAM, 0);
+            if ctl6 < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let ret = siocaifaddr_in6(ctl6, &req);
+            let err = io::Error::last_os_error();
+            libc::close(ctl6);
+
+            if ret < 0 {
+                return Err(err);
+            }
+
+            Ok(())
+        }
+    }
+
     pub fn destination(&self) -> Result<Ipv4Addr, Error> {
         let name = self.name()?;
 
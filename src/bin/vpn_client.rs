@@ -5,31 +5,89 @@ extern crate log;
 extern crate env_logger;
 extern crate exodus;
 
-use exodus::{ Ipv4Cidr, Ipv4Address, EthernetAddress, };
-use exodus::vpn::{ VpnClientConfig, VpnClient, InterfaceKind, };
+use exodus::{ Ipv4Cidr, Ipv4Address, EthernetAddress, Identity, PublicKey, };
+use exodus::vpn::{
+    VpnClientConfig, VpnClient, InterfaceKind,
+    DEFAULT_REKEY_AFTER_TIME, DEFAULT_REKEY_AFTER_BYTES,
+    DEFAULT_KEEPALIVE_INTERVAL, DEFAULT_PEER_TIMEOUT,
+};
 
 use std::env;
+use std::fs;
 use std::io::{self, Read, Write};
+use std::net::SocketAddr;
 
 
+// 未设置 EXODUS_IDENTITY 时，身份密钥保存在这个文件中 (权限 0600)
+const IDENTITY_FILE: &str = "exodus_client.key";
+
+/// 读取密钥文件，文件不存在时随机生成一个身份密钥并保存，私钥不会写入日志
+fn load_or_generate_identity(path: &str) -> Result<Identity, io::Error> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    match fs::read_to_string(path) {
+        Ok(s) => return s.trim().parse::<Identity>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => { },
+        Err(e) => return Err(e),
+    }
+
+    let identity = Identity::generate();
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+    writeln!(file, "{}", identity.to_secret_base64())?;
+    warn!("随机生成身份密钥，已保存到 {}，公钥: {}", path, identity.public_key());
+
+    Ok(identity)
+}
+
 fn main() {
     env::set_var("RUST_LOG", "exodus=DEBUG,vpn_client=DEBUG");
     env_logger::init();
     exodus::signal::init();
+
+    // 客户端的身份密钥，公钥需要添加到服务端的白名单里面
+    let identity = match env::var("EXODUS_IDENTITY") {
+        Ok(s) => s.parse::<Identity>().expect("EXODUS_IDENTITY 不是合法的密钥！"),
+        Err(_) => {
+            warn!("未设置 EXODUS_IDENTITY，使用密钥文件: {}", IDENTITY_FILE);
+            load_or_generate_identity(IDENTITY_FILE).expect("无法读取或者保存身份密钥！")
+        }
+    };
+    info!("client public key: {}", identity.public_key());
+
+    let server_public_key = env::var("EXODUS_SERVER_PUBLIC_KEY")
+        .expect("请通过 EXODUS_SERVER_PUBLIC_KEY 设置服务端公钥！")
+        .parse::<PublicKey>()
+        .expect("EXODUS_SERVER_PUBLIC_KEY 不是合法的公钥！");
     
     let vpn_client_config = VpnClientConfig {
         tun_ifname: "utun9".to_string(),
         egress_iface_addr: Ipv4Address([192, 168, 199, 200]),
-        egress_iface_gateway_addr: Ipv4Address([192, 168, 199, 1]),
-        vpn_server_addr: Ipv4Address([119, 28, 213, 41]),
-        vpn_server_port: 9050,
+        vpn_server_addr: "119.28.213.41:9050".parse::<SocketAddr>().unwrap(),
+        identity: identity.clone(),
+        server_public_key,
+        rekey_after_time: DEFAULT_REKEY_AFTER_TIME,
+        rekey_after_bytes: DEFAULT_REKEY_AFTER_BYTES,
+        include_routes: Vec::new(),
+        exclude_routes: Vec::new(),
+        configure_dns: true,
+        kill_switch: false,
+        keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+        server_timeout: DEFAULT_PEER_TIMEOUT,
     };
     let vpn_client_config = VpnClientConfig {
         tun_ifname: "utun9".to_string(),
         egress_iface_addr: Ipv4Address([192, 168, 199, 200]),
-        egress_iface_gateway_addr: Ipv4Address([192, 168, 199, 1]),
-        vpn_server_addr: "192.168.199.232".parse::<Ipv4Address>().unwrap(),
-        vpn_server_port: 9050,
+        vpn_server_addr: "192.168.199.232:9050".parse::<SocketAddr>().unwrap(),
+        identity: identity.clone(),
+        server_public_key,
+        rekey_after_time: DEFAULT_REKEY_AFTER_TIME,
+        rekey_after_bytes: DEFAULT_REKEY_AFTER_BYTES,
+        include_routes: Vec::new(),
+        exclude_routes: Vec::new(),
+        configure_dns: true,
+        kill_switch: false,
+        keepalive_interval: DEFAULT_KEEPALIVE_INTERVAL,
+        server_timeout: DEFAULT_PEER_TIMEOUT,
     };
 
     let mut vpn_client = VpnClient::new(vpn_client_config).unwrap();
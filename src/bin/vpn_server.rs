@@ -5,22 +5,66 @@ extern crate log;
 extern crate env_logger;
 extern crate exodus;
 
-use exodus::{ Ipv4Cidr, Ipv4Address, EthernetAddress, };
-use exodus::vpn::{ VpnServerConfig, VpnServer, InterfaceKind, };
+use exodus::{ Ipv4Cidr, Ipv4Address, Ipv6Cidr, Ipv6Address, EthernetAddress, Identity, PublicKey, };
+use exodus::vpn::{
+    VpnServerConfig, VpnServer, InterfaceKind,
+    DEFAULT_REKEY_AFTER_TIME, DEFAULT_REKEY_AFTER_BYTES, DEFAULT_PEER_TIMEOUT,
+};
 
 use std::env;
+use std::fs;
 use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::path::PathBuf;
 
 
+// 未设置 EXODUS_IDENTITY 时，身份密钥保存在这个文件中 (权限 0600)
+const IDENTITY_FILE: &str = "exodus_server.key";
+
+/// 读取密钥文件，文件不存在时随机生成一个身份密钥并保存，私钥不会写入日志
+fn load_or_generate_identity(path: &str) -> Result<Identity, io::Error> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    match fs::read_to_string(path) {
+        Ok(s) => return s.trim().parse::<Identity>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => { },
+        Err(e) => return Err(e),
+    }
+
+    let identity = Identity::generate();
+    let mut file = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+    writeln!(file, "{}", identity.to_secret_base64())?;
+    warn!("随机生成身份密钥，已保存到 {}，公钥: {}", path, identity.public_key());
+
+    Ok(identity)
+}
+
 fn main() {
     env::set_var("RUST_LOG", "exodus=DEBUG,vpn_server=DEBUG");
     env_logger::init();
     exodus::signal::init();
+
+    // 服务端的身份密钥，客户端需要配置对应的公钥
+    let identity = match env::var("EXODUS_IDENTITY") {
+        Ok(s) => s.parse::<Identity>().expect("EXODUS_IDENTITY 不是合法的密钥！"),
+        Err(_) => {
+            warn!("未设置 EXODUS_IDENTITY，使用密钥文件: {}", IDENTITY_FILE);
+            load_or_generate_identity(IDENTITY_FILE).expect("无法读取或者保存身份密钥！")
+        }
+    };
+
+    // 允许接入的客户端公钥，使用逗号分隔
+    let authorized_keys = env::var("EXODUS_AUTHORIZED_KEYS")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| s.trim().parse::<PublicKey>().expect("EXODUS_AUTHORIZED_KEYS 包含不合法的公钥！"))
+        .collect::<Vec<PublicKey>>();
     
-    // Ubuntu Server
     let vpn_server_config = VpnServerConfig {
         tun_ifname: "utun9".to_string(),
         tun_cidr: Ipv4Cidr::new(Ipv4Address([172, 16, 0, 1]), 12),  // 172.16.0.0/12
+        tun_cidr6: Some(Ipv6Cidr::new(Ipv6Address::new(0xfd00, 0xac10, 0, 0, 0, 0, 0, 0), 64)),  // fd00:ac10::/64
         egress_iface_kind: InterfaceKind::Ethernet,
         egress_iface_name: "eth0".to_string(),
         egress_iface_addr: "172.19.0.7".parse::<Ipv4Address>().unwrap(),
@@ -28,11 +72,24 @@ fn main() {
         egress_iface_gateway_addr: Some("172.19.0.1".parse::<Ipv4Address>().unwrap()),
         egress_iface_gateway_hwaddr: Some("fe:ee:54:cb:79:fb".parse::<EthernetAddress>().unwrap()),
         tunnel_service_udp_port: 9050,
+        tunnel_service_dual_stack: false,
+        identity: identity.clone(),
+        authorized_keys: authorized_keys.clone(),
+        rekey_after_time: DEFAULT_REKEY_AFTER_TIME,
+        rekey_after_bytes: DEFAULT_REKEY_AFTER_BYTES,
+        peer_timeout: DEFAULT_PEER_TIMEOUT,
+        pushed_routes: Vec::new(),
+        dns_servers: vec![ "8.8.8.8".parse::<IpAddr>().unwrap(), "1.1.1.1".parse::<IpAddr>().unwrap() ],
+        search_domains: Vec::new(),
+        static_leases: Vec::new(),
+        reserved_addrs: Vec::new(),
+        lease_file: Some(PathBuf::from("exodus-leases.txt")),
     };
     // Debian Server
     let vpn_server_config = VpnServerConfig {
         tun_ifname: "utun9".to_string(),
         tun_cidr: Ipv4Cidr::new(Ipv4Address([10, 192, 168, 0]), 24),  // 10.192.168.0/24
+        tun_cidr6: Some(Ipv6Cidr::new(Ipv6Address::new(0xfd00, 0x0ac0, 0xa800, 0, 0, 0, 0, 0), 64)),  // fd00:ac0:a800::/64
         egress_iface_kind: InterfaceKind::Ethernet,
         egress_iface_name: "enp0s3".to_string(),
         egress_iface_addr: "192.168.199.232".parse::<Ipv4Address>().unwrap(),
@@ -40,6 +97,18 @@ fn main() {
         egress_iface_gateway_addr: Some("192.168.199.1".parse::<Ipv4Address>().unwrap()),
         egress_iface_gateway_hwaddr: Some("d4:ee:07:5a:67:40".parse::<EthernetAddress>().unwrap()),
         tunnel_service_udp_port: 9050,
+        tunnel_service_dual_stack: false,
+        identity: identity.clone(),
+        authorized_keys: authorized_keys.clone(),
+        rekey_after_time: DEFAULT_REKEY_AFTER_TIME,
+        rekey_after_bytes: DEFAULT_REKEY_AFTER_BYTES,
+        peer_timeout: DEFAULT_PEER_TIMEOUT,
+        pushed_routes: Vec::new(),
+        dns_servers: vec![ "8.8.8.8".parse::<IpAddr>().unwrap(), "1.1.1.1".parse::<IpAddr>().unwrap() ],
+        search_domains: Vec::new(),
+        static_leases: Vec::new(),
+        reserved_addrs: Vec::new(),
+        lease_file: Some(PathBuf::from("exodus-leases.txt")),
     };
 
     let mut vpn_server = VpnServer::new(vpn_server_config).unwrap();
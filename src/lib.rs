@@ -7,10 +7,15 @@ extern crate log;
 extern crate env_logger;
 extern crate ctrlc;
 extern crate mio;
+extern crate rand;
+extern crate libc;
 extern crate tun;
 extern crate crypto;
 extern crate compression;
 extern crate smoltcp;
+extern crate sysconfig;
+#[cfg(target_os = "linux")]
+extern crate netlink;
 
 pub mod signal;
 pub mod vpn;
@@ -18,6 +23,8 @@ pub mod vpn;
 
 pub use smoltcp::wire::{
     EthernetAddress, EthernetProtocol,
-    IpProtocol, IpVersion,
+    IpProtocol, IpVersion, IpAddress, IpCidr,
     Ipv4Cidr, Ipv4Address,
+    Ipv6Cidr, Ipv6Address,
 };
+pub use crypto::{ Identity, PublicKey, };
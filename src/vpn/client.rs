@@ -2,39 +2,96 @@ use mio;
 use smoltcp::wire::{
     PrettyPrinter,
     EthernetAddress, EthernetFrame, EthernetProtocol,
-    IpProtocol, IpVersion,
+    IpProtocol, IpVersion, IpAddress, IpCidr,
     Ipv4Cidr, Ipv4Address, Ipv4Packet,
+    Ipv6Cidr, Ipv6Address,
     TcpPacket, UdpPacket,
 };
 
+use crypto::{ AesSiv256, Identity, PublicKey, EphemeralKey, };
+
 // use crate::nat;
 use crate::signal;
 use crate::vpn::{
-    TAP_TOKEN, TUN_TOKEN, UDP_TOKEN,
-    DHCP_REQ_PACKET_SIGNATURE, DHCP_RES_PACKET_SIGNATURE,
-    TUNNEL_PACKET_SIGNATURE, BYE_PACKET_SIGNATURE,
+    TAP_TOKEN, TUN_TOKEN, UDP_TOKEN, ROUTE_TOKEN,
+    HANDSHAKE_INIT_PACKET_SIGNATURE, HANDSHAKE_RESP_PACKET_SIGNATURE,
+    TUNNEL_PACKET_SIGNATURE, BYE_PACKET_SIGNATURE, REKEY_PACKET_SIGNATURE, KEEPALIVE_PACKET_SIGNATURE,
+    REKEY_GRACE_TIME, REKEY_RETRY_TIME, EGRESS_CHECK_INTERVAL,
+    parse_ip_pkt, read_tun_pkt, write_tun_pkt,
 };
+use crate::vpn::handshake::{ HandshakeInit, HandshakeResp, session_keys, };
+use crate::vpn::session::{ self, Session, };
+use crate::vpn::dhcp::DhcpState;
+use crate::vpn::system::{ self, SystemConfig, };
+
+#[cfg(target_os = "linux")]
+use netlink::route::monitor::{ Monitor, Event, };
+#[cfg(target_os = "linux")]
+use netlink::packet::{ MulticastGroups, RouteTable, RouteType, };
 
 use std::collections::HashMap;
 use std::io::{self, Read, Write};
 use std::os::unix::io::AsRawFd;
-use std::net::{ IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, UdpSocket, };
+use std::net::{ IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpListener, UdpSocket, };
+use std::time::{ Duration, Instant, };
 
 
 #[derive(Debug, Clone)]
 pub struct VpnClientConfig {
     pub tun_ifname: String,
     pub egress_iface_addr: Ipv4Address,
-    pub egress_iface_gateway_addr: Ipv4Address,
-    pub vpn_server_addr: Ipv4Address,
-    pub vpn_server_port: u16,
+    // 服务端的隧道地址，可以是 IPv4 或者 IPv6 地址
+    pub vpn_server_addr: SocketAddr,
+    // 客户端的身份密钥，公钥需要添加到服务端的白名单里面
+    pub identity: Identity,
+    pub server_public_key: PublicKey,
+    // 会话存在的时间或者传输的数据量超出限制之后，重新握手
+    pub rekey_after_time: Duration,
+    pub rekey_after_bytes: u64,
+    // 经过隧道的路由，为空时使用服务端推送的路由，服务端也没有推送时，所有流量都经过隧道
+    pub include_routes: Vec<IpCidr>,
+    // 不经过隧道的路由，这些地址的数据包直接通过系统默认网关发送
+    pub exclude_routes: Vec<IpCidr>,
+    // 使用服务端推送的 DNS 配置，断开时恢复系统原来的配置
+    pub configure_dns: bool,
+    // 只允许经过隧道以及发往服务端的数据包离开本机，重新接入期间也不会撤销，直到客户端正常断开 (出错退出时保留)
+    pub kill_switch: bool,
+    // 发送心跳包的间隔
+    pub keepalive_interval: Duration,
+    // 超过这个时间没有收到服务端的数据包，则重新接入
+    pub server_timeout: Duration,
 }
 
-#[derive(Debug, Clone)]
-pub struct DhcpState {
-    tun_addr        : Ipv4Address,
-    tun_gateway_addr: Ipv4Address,
-    tun_netmask     : Ipv4Address,
+struct PendingHandshake {
+    ephemeral_key: EphemeralKey,
+    init: HandshakeInit,
+    sent_at: Instant,
+}
+
+impl PendingHandshake {
+    fn new(config: &VpnClientConfig) -> Self {
+        let ephemeral_key = EphemeralKey::generate();
+        let init = HandshakeInit::new(&config.identity, &config.server_public_key, ephemeral_key.public_key());
+
+        Self { ephemeral_key, init, sent_at: Instant::now() }
+    }
+
+    /// 完成密钥协商，调用之前需要先验证握手响应的签名
+    fn complete(self, resp: &HandshakeResp) -> Result<(DhcpState, Session), io::Error> {
+        let shared_secret = self.ephemeral_key.diffie_hellman(&resp.ephemeral_key);
+        let (c2s_key, s2c_key) = session_keys(&shared_secret, &self.init.ephemeral_key, &resp.ephemeral_key);
+
+        let payload = AesSiv256::new(s2c_key).open(&resp.payload)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "握手失败: 无法解密地址信息！"))?;
+        let dhcp_state = DhcpState::parse(&payload)?;
+        if dhcp_state.tun_addr == Ipv4Address::UNSPECIFIED {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "DHCP 失败: 无可用地址！"))
+        }
+
+        let session = Session::new(resp.session_id, c2s_key, s2c_key);
+
+        Ok((dhcp_state, session))
+    }
 }
 
 pub struct VpnClient {
@@ -43,124 +100,135 @@ pub struct VpnClient {
     buffer     : [u8; 2048],
     tun_device : tun::Device,
     udp_socket : mio::net::UdpSocket,
+    system_config: SystemConfig,
+    // 添加到服务端的主机路由时使用的系统默认网关
+    server_gateway: IpAddr,
+    // 监听系统路由表的变化，默认网关变更时重新添加到服务端的主机路由
+    #[cfg(target_os = "linux")]
+    route_monitor: Monitor,
+    session    : Session,
+    // rekey 完成之后，在宽限期内仍然可以使用的旧会话
+    previous_session: Option<(Session, Instant)>,
+    pending_handshake: Option<PendingHandshake>,
+    auth_failures: u64,
+    // 最后一次收到服务端认证通过的数据包的时间
+    last_received: Instant,
+    last_keepalive: Instant,
+    last_egress_check: Instant,
+    // 发送失败时重新绑定了 UDP Socket，需要在下一次轮询之前重新注册
+    udp_socket_rebound: bool,
+    // 服务端没有响应，正在重新接入
+    rejoining: bool,
 }
 
 impl VpnClient {
-    fn dhcp_request(config: &VpnClientConfig, udp_socket: &mut mio::net::UdpSocket) -> Result<DhcpState, io::Error> {
-        let server_addr = &SocketAddrV4::new(config.vpn_server_addr.into(), config.vpn_server_port).into();
+    fn handshake(config: &VpnClientConfig, udp_socket: &mut mio::net::UdpSocket) -> Result<(DhcpState, Session), io::Error> {
+        let server_addr = &config.vpn_server_addr;
         let mut buffer = [0u8; 2048];
 
         loop {
-            udp_socket.send_to(&DHCP_REQ_PACKET_SIGNATURE, &server_addr)?;
-
-            debug!("try recv dhcp response ...");
+            let pending = PendingHandshake::new(config);
+            udp_socket.send_to(&pending.init.to_bytes(), &server_addr)?;
 
-            if !signal::is_running() {
-                std::process::exit(0);
-            }
+            debug!("try recv handshake response ...");
 
-            match udp_socket.recv_from(&mut buffer) {
-                Ok((amt, peer_addr)) => {
-                    if server_addr != &peer_addr {
-                        continue;
-                    }
-                    
-                    let packet_signature = [
-                        buffer[0], buffer[1], 
-                        buffer[2], buffer[3], 
-                    ];
-                    
-                    if amt < 16 || packet_signature != DHCP_RES_PACKET_SIGNATURE {
-                        return Err(io::Error::new(io::ErrorKind::InvalidData, "DHCP 失败: 未知协议！"))
-                    }
+            // 超时之后重新发起握手
+            let deadline = Instant::now() + Duration::from_secs(1);
 
-                    let tun_addr = Ipv4Address::from_bytes(&buffer[4..8]);
-                    if tun_addr == Ipv4Address::UNSPECIFIED {
-                        return Err(io::Error::new(io::ErrorKind::InvalidData, "DHCP 失败: 无可用地址！"))
-                    }
+            loop {
+                if !signal::is_running() {
+                    std::process::exit(0);
+                }
 
-                    let tun_gateway_addr = Ipv4Address::from_bytes(&buffer[8..12]);
-                    let tun_netmask = Ipv4Address::from_bytes(&buffer[12..16]);
+                match udp_socket.recv_from(&mut buffer) {
+                    Ok((amt, peer_addr)) => {
+                        if server_addr != &peer_addr {
+                            continue;
+                        }
 
-                    return Ok(DhcpState{ tun_addr, tun_gateway_addr, tun_netmask, });
-                },
-                Err(e) => {
-                    match e.kind() {
-                        io::ErrorKind::WouldBlock => {
-                            std::thread::sleep(std::time::Duration::from_millis(200));
+                        // NOTE: 忽略过期的或者伪造的握手响应
+                        let resp = match HandshakeResp::parse(&buffer[..amt]) {
+                            Some(resp) => resp,
+                            None => continue,
+                        };
+                        if !resp.verify(&config.server_public_key, &pending.init) {
                             continue;
-                        },
-                        _ => return Err(e),
-                    }
-                },
-            }
+                        }
 
+                        return pending.complete(&resp);
+                    },
+                    Err(e) => {
+                        match e.kind() {
+                            io::ErrorKind::WouldBlock => {
+                                if Instant::now() >= deadline {
+                                    break;
+                                }
+                                std::thread::sleep(Duration::from_millis(200));
+                                continue;
+                            },
+                            _ => return Err(e),
+                        }
+                    },
+                }
+            }
         }
     }
 
     pub fn new(config: VpnClientConfig) -> Result<Self, io::Error> {
         // 172.16.0.0/16
-        let local_addr: SocketAddr  = SocketAddrV4::new(config.egress_iface_addr.into(), 0).into();
-        let server_addr: SocketAddr = SocketAddrV4::new(config.vpn_server_addr.into(), config.vpn_server_port).into();
+        let server_addr: SocketAddr = config.vpn_server_addr;
+        // NOTE: 本地地址需要和服务端地址属于同一个协议族
+        let local_addr: SocketAddr  = match server_addr {
+            SocketAddr::V4(_) => SocketAddrV4::new(config.egress_iface_addr.into(), 0).into(),
+            SocketAddr::V6(_) => SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0).into(),
+        };
         
         let mut udp_socket = mio::net::UdpSocket::bind(&local_addr)?;
         let local_addr1 = udp_socket.local_addr()?;
         info!("bind to {}", local_addr1);
 
-        let dhcp_state = Self::dhcp_request(&config, &mut udp_socket)?;
+        let (dhcp_state, mut session) = Self::handshake(&config, &mut udp_socket)?;
 
         debug!("try connect to {} ...", server_addr);
         udp_socket.connect(server_addr)?;
         info!("connect to {} ...", server_addr);
 
         let local_addr2 = udp_socket.local_addr()?;
-        // 确保前后地址一致
-        assert_eq!(local_addr1, local_addr2);
+        if local_addr1 != local_addr2 {
+            debug!("local address changed: {} --> {}", local_addr1, local_addr2);
+        }
         debug!("connected!");
 
+        // 发送一个空数据包，让服务端确认新的会话
+        udp_socket.send(&session.seal(TUNNEL_PACKET_SIGNATURE, &[]))?;
+
         let mut tun_device = tun::Device::new(&config.tun_ifname)?;
         tun_device.set_address(dhcp_state.tun_addr)?;
         tun_device.set_netmask(dhcp_state.tun_netmask)?;
         tun_device.set_destination(dhcp_state.tun_gateway_addr)?;
         tun_device.set_mtu(1500-30-4)?;
         tun_device.enabled(true)?;
+        if let Some(tun_addr6) = dhcp_state.tun_addr6 {
+            tun_device.add_address_v6(tun_addr6.address(), tun_addr6.prefix_len())?;
+        }
 
-        // NOTE:
-        // 这里需要为系统配置 静态路由
-        // Linux
-        //      sudo route add -net 172.16.0.0/16  dev utun9
-        // macOS
-        //      sudo route add -net 172.16.0.0/16 -interface utun8
-        // 
-        // 在未来，这个会通过 C 库自动实现
-        // 目前临时使用 命令行程序 去配置这些数据
-        let tun_cidr = Ipv4Cidr::from_netmask(dhcp_state.tun_addr, dhcp_state.tun_netmask).unwrap();
-        warn!("为系统路由表添加静态路由:
-        Linux:
-            sudo route add -net {tun_cidr} dev {tun_ifname}
-            sudo route add -n {server_addr} gw 192.168.199.1
-
-            sudo route -n delete default
-            sudo route -n add default gw 172.16.10.13
-            # TODO: 配置 DNS ？
-        macOS:
-            route -n get default | grep interface | awk '{{print $2}}'
-            
-            sudo route add {server_addr} 192.168.199.1
-            sudo route add -net {tun_cidr} -interface {tun_ifname}
-
-            sudo route delete default
-            sudo route add default {tun_addr}
-            
-            networksetup -setdnsservers \"Wi-Fi\" \"8.8.8.8\"
-        ",
-        tun_cidr=tun_cidr,
-        tun_ifname=&config.tun_ifname,
-        tun_addr=dhcp_state.tun_addr,
-        server_addr=config.vpn_server_addr,
-        );
-        
-        // std::thread::sleep(std::time::Duration::new(1, 0));
+        // 配置路由，退出时撤销
+        let mut system_config = SystemConfig::new();
+        let server_gateway = add_server_route(&config, &mut system_config)?;
+        add_tunnel_routes(&config, &dhcp_state, &mut system_config)?;
+        configure_dns(&config, &dhcp_state, &mut system_config)?;
+
+        #[cfg(target_os = "linux")]
+        let route_monitor = {
+            let mut monitor = Monitor::new(MulticastGroups::RTMGRP_IPV4_ROUTE | MulticastGroups::RTMGRP_IPV6_ROUTE)?;
+            monitor.set_nonblock()?;
+            monitor
+        };
+
+        // NOTE: 出错退出时 Kill Switch 不会被撤销，所以放在最后，启动失败时不会留下它
+        if config.kill_switch {
+            system_config.add_kill_switch(&config.tun_ifname, &config.vpn_server_addr)?;
+        }
 
         Ok(VpnClient {
             config,
@@ -168,34 +236,393 @@ impl VpnClient {
             buffer: [0u8; 2048],
             tun_device,
             udp_socket,
+            system_config,
+            server_gateway,
+            #[cfg(target_os = "linux")]
+            route_monitor,
+            session,
+            previous_session: None,
+            pending_handshake: None,
+            auth_failures: 0,
+            last_received: Instant::now(),
+            last_keepalive: Instant::now(),
+            last_egress_check: Instant::now(),
+            udp_socket_rebound: false,
+            rejoining: false,
         })
     }
 
+    /// 认证失败而被丢弃的隧道数据包数量
+    pub fn auth_failures(&self) -> u64 {
+        self.auth_failures
+    }
+
+    fn start_rekey(&mut self) -> Result<(), io::Error> {
+        let pending = PendingHandshake::new(&self.config);
+        // NOTE: 发送失败时等待超时之后重试
+        self.send_to_server(&pending.init.to_bytes());
+        self.pending_handshake = Some(pending);
+
+        Ok(())
+    }
+
+    fn handle_handshake_resp(&mut self, pkt_amt: usize) -> Result<(), io::Error> {
+        let resp = match HandshakeResp::parse(&self.buffer[..pkt_amt]) {
+            Some(resp) => resp,
+            None => return Ok(()),
+        };
+
+        let server_public_key = &self.config.server_public_key;
+        let is_valid = self.pending_handshake.as_ref()
+            .map(|pending| resp.verify(server_public_key, &pending.init))
+            .unwrap_or(false);
+        if !is_valid {
+            trace!("忽略过期的或者伪造的握手响应");
+            return Ok(());
+        }
+
+        let tun_addr = self.dhcp_state.tun_addr;
+        let pending = self.pending_handshake.take().unwrap();
+        let (dhcp_state, session) = match pending.complete(&resp) {
+            Ok(ret) => ret,
+            Err(e) => {
+                warn!("[{}] rekey 失败: {}", tun_addr, e);
+                return Ok(());
+            },
+        };
+
+        if dhcp_state.tun_addr != tun_addr && !self.rejoining {
+            warn!("[{}] rekey 失败: 服务端分配了不同的地址 {}", tun_addr, dhcp_state.tun_addr);
+            return Ok(());
+        }
+
+        if self.rejoining && dhcp_state != self.dhcp_state {
+            self.set_dhcp_state(dhcp_state)?;
+        }
+
+        let previous_session = std::mem::replace(&mut self.session, session);
+        self.previous_session = Some((previous_session, Instant::now() + REKEY_GRACE_TIME));
+        self.last_received = Instant::now();
+
+        if self.rejoining {
+            self.rejoining = false;
+            info!("[{}] 重新接入完成 (session {})", self.dhcp_state.tun_addr, self.session.id());
+        } else {
+            info!("[{}] rekey 完成 (session {})", tun_addr, self.session.id());
+        }
+
+        // 发送一个空数据包，让服务端确认新的会话
+        let message = self.session.seal(TUNNEL_PACKET_SIGNATURE, &[]);
+        self.send_to_server(&message);
+
+        Ok(())
+    }
+
+    /// 发送数据包到服务端，发送失败不会结束事件循环
+    ///
+    /// NOTE: 出口地址失效时 (例如 Wi-Fi 断开、DHCP 更换了地址)，立即检查出口地址并重新绑定 UDP Socket，
+    ///       新的 Socket 在下一次轮询之前注册到 `mio::Poll`
+    fn send_to_server(&mut self, message: &[u8]) {
+        let e = match self.udp_socket.send(message) {
+            Ok(_) => return,
+            Err(e) => e,
+        };
+        debug!("[UDP] 发送数据包失败: {}", e);
+
+        let is_egress_lost = e.kind() == io::ErrorKind::AddrNotAvailable
+            || e.raw_os_error() == Some(libc::ENETUNREACH);
+        if !is_egress_lost {
+            return;
+        }
+
+        let now = Instant::now();
+        self.last_egress_check = now.checked_sub(EGRESS_CHECK_INTERVAL).unwrap_or(now);
+        match self.rebind_if_needed() {
+            Ok(true) => self.udp_socket_rebound = true,
+            Ok(false) => { },
+            Err(e) => debug!("无法重新绑定 UDP Socket: {}", e),
+        }
+    }
+
+    /// 出口地址发生变化时 (例如从 Wi-Fi 切换到 LTE)，重新绑定 UDP Socket，
+    /// 返回 `true` 表示 Socket 已经更换，需要重新注册到 `mio::Poll`
+    fn rebind_if_needed(&mut self) -> Result<bool, io::Error> {
+        let now = Instant::now();
+        if now.duration_since(self.last_egress_check) < EGRESS_CHECK_INTERVAL {
+            return Ok(false);
+        }
+        self.last_egress_check = now;
+
+        let server_addr = self.config.vpn_server_addr;
+        let mut egress_addr = match lookup_egress_addr(&server_addr) {
+            Ok(addr) => addr,
+            Err(e) => {
+                debug!("无法获取出口地址: {}", e);
+                return Ok(false);
+            },
+        };
+
+        if self.is_tun_addr(egress_addr) {
+            // NOTE: 到服务端的主机路由随着网卡一起被系统删除了，通过新的默认网关重新添加
+            if let Err(e) = self.repin_server_route() {
+                debug!("无法添加到服务端的路由: {}", e);
+                return Ok(false);
+            }
+
+            egress_addr = match lookup_egress_addr(&server_addr) {
+                Ok(addr) if !self.is_tun_addr(addr) => addr,
+                _ => return Ok(false),
+            };
+        }
+
+        let local_addr = self.udp_socket.local_addr()?;
+        if local_addr.ip() == egress_addr {
+            return Ok(false);
+        }
+
+        info!("出口地址变更: {} --> {}，重新绑定 UDP Socket", local_addr.ip(), egress_addr);
+        let udp_socket = mio::net::UdpSocket::bind(&SocketAddr::new(egress_addr, 0))?;
+        udp_socket.connect(server_addr)?;
+        self.udp_socket = udp_socket;
+
+        // 发送一个心跳包，让服务端更新客户端的地址
+        let message = self.session.seal(KEEPALIVE_PACKET_SIGNATURE, &[]);
+        let _ = self.udp_socket.send(&message);
+        self.last_keepalive = now;
+
+        Ok(true)
+    }
+
+    /// 删除到服务端的主机路由，然后通过当前的系统默认网关重新添加
+    fn repin_server_route(&mut self) -> Result<(), io::Error> {
+        let server_ip = self.config.vpn_server_addr.ip();
+        let prefix_len = if server_ip.is_ipv4() { 32 } else { 128 };
+        let _ = self.system_config.remove_route(server_ip, prefix_len);
+        self.server_gateway = add_server_route(&self.config, &mut self.system_config)?;
+
+        Ok(())
+    }
+
+    /// 读取路由表的变化，默认网关变更时重新添加到服务端的主机路由，并尽快检查出口地址
+    #[cfg(target_os = "linux")]
+    fn handle_route_events(&mut self) -> Result<(), io::Error> {
+        let mut default_route_changed = false;
+
+        loop {
+            match self.route_monitor.next_event() {
+                Ok(Event::NewRoute(route)) | Ok(Event::DelRoute(route)) => {
+                    let is_default = route.dst_cidr.map(|cidr| cidr.prefix_len() == 0).unwrap_or(true);
+                    if is_default && route.kind == RouteType::RTN_UNICAST && route.table == RouteTable::RT_TABLE_MAIN {
+                        default_route_changed = true;
+                    }
+                },
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    // NOTE: 接收缓冲区溢出，部分事件已丢失，按默认路由已经变化处理，重新读取路由表
+                    warn!("路由表变化的事件溢出，重新读取路由表");
+                    default_route_changed = true;
+                    break;
+                },
+                Err(e) => {
+                    warn!("读取路由表变化失败: {}", e);
+                    break;
+                },
+            }
+        }
+
+        if !default_route_changed {
+            return Ok(());
+        }
+
+        let server_ip = self.config.vpn_server_addr.ip();
+        match system::default_gateway(server_ip.is_ipv4()) {
+            Ok(Some(gateway)) if gateway != self.server_gateway => {
+                info!("系统默认网关变更: {} --> {}，重新添加到服务端的路由", self.server_gateway, gateway);
+                if let Err(e) = self.repin_server_route() {
+                    debug!("无法添加到服务端的路由: {}", e);
+                }
+
+                let now = Instant::now();
+                self.last_egress_check = now.checked_sub(EGRESS_CHECK_INTERVAL).unwrap_or(now);
+            },
+            Ok(_) => { },
+            Err(e) => debug!("无法获取系统默认网关: {}", e),
+        }
+
+        Ok(())
+    }
+
+    fn is_tun_addr(&self, addr: IpAddr) -> bool {
+        match addr {
+            IpAddr::V4(addr) => Ipv4Address::from(addr) == self.dhcp_state.tun_addr,
+            IpAddr::V6(addr) => self.dhcp_state.tun_addr6.map(|cidr| cidr.address() == addr.into()).unwrap_or(false),
+        }
+    }
+
+    /// 重新接入之后，使用服务端推送的新配置
+    fn set_dhcp_state(&mut self, dhcp_state: DhcpState) -> Result<(), io::Error> {
+        if dhcp_state.tun_addr != self.dhcp_state.tun_addr || dhcp_state.tun_addr6 != self.dhcp_state.tun_addr6 {
+            // NOTE: 服务端已经回收了原来的地址，重新配置 TUN 设备
+            info!("[{}] 服务端重新分配了地址: {}", self.dhcp_state.tun_addr, dhcp_state.tun_addr);
+            self.set_tun_addrs(&dhcp_state)?;
+        }
+
+        let old_routes = tunnel_routes(&self.config, &self.dhcp_state);
+        let new_routes = tunnel_routes(&self.config, &dhcp_state);
+        let tun_ifname = Some(self.config.tun_ifname.as_str());
+        for (dst_addr, prefix_len) in old_routes.iter().filter(|route| !new_routes.contains(route)) {
+            self.system_config.remove_route(*dst_addr, *prefix_len)?;
+        }
+        for (dst_addr, prefix_len) in new_routes.iter().filter(|route| !old_routes.contains(route)) {
+            self.system_config.add_route(*dst_addr, *prefix_len, None, tun_ifname)?;
+        }
+
+        if dhcp_state.dns_servers != self.dhcp_state.dns_servers || dhcp_state.search_domains != self.dhcp_state.search_domains {
+            configure_dns(&self.config, &dhcp_state, &mut self.system_config)?;
+        }
+        self.dhcp_state = dhcp_state;
+
+        Ok(())
+    }
+
+    /// 删除 TUN 设备上原来的地址，配置新分配的地址
+    fn set_tun_addrs(&mut self, dhcp_state: &DhcpState) -> Result<(), io::Error> {
+        let tun_ifname = &self.config.tun_ifname;
+        let tun_cidr = Ipv4Cidr::from_netmask(self.dhcp_state.tun_addr, self.dhcp_state.tun_netmask).unwrap();
+        if dhcp_state.tun_addr != self.dhcp_state.tun_addr {
+            // NOTE: 设置新地址时原来的地址可能已经被替换，删除失败不影响后面的配置
+            if let Err(e) = system::remove_address(tun_ifname, IpAddr::V4(tun_cidr.address().into()), tun_cidr.prefix_len()) {
+                debug!("无法删除 {} 原来的地址 {}: {}", tun_ifname, tun_cidr, e);
+            }
+            self.tun_device.set_address(dhcp_state.tun_addr)?;
+            self.tun_device.set_netmask(dhcp_state.tun_netmask)?;
+            self.tun_device.set_destination(dhcp_state.tun_gateway_addr)?;
+        }
+
+        if dhcp_state.tun_addr6 != self.dhcp_state.tun_addr6 {
+            if let Some(tun_addr6) = self.dhcp_state.tun_addr6 {
+                if let Err(e) = system::remove_address(tun_ifname, IpAddr::V6(tun_addr6.address().into()), tun_addr6.prefix_len()) {
+                    warn!("无法删除 {} 原来的地址 {}: {}", tun_ifname, tun_addr6, e);
+                }
+            }
+            if let Some(tun_addr6) = dhcp_state.tun_addr6 {
+                self.tun_device.add_address_v6(tun_addr6.address(), tun_addr6.prefix_len())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 使用当前会话，或者在宽限期内的旧会话解密数据包
+    fn open(&mut self, pkt_amt: usize) -> Result<Vec<u8>, io::Error> {
+        let plaintext = self.open_with_session(pkt_amt)?;
+        self.last_received = Instant::now();
+
+        Ok(plaintext)
+    }
+
+    fn open_with_session(&mut self, pkt_amt: usize) -> Result<Vec<u8>, io::Error> {
+        let message = &self.buffer[..pkt_amt];
+        let id = session::session_id(&message);
+
+        if Some(self.session.id()) == id {
+            return self.session.open(&message);
+        }
+
+        if let Some((ref mut previous_session, expire_at)) = self.previous_session {
+            if Some(previous_session.id()) == id && Instant::now() < expire_at {
+                return previous_session.open(&message);
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::InvalidData, "unknown session"))
+    }
+
+    fn maintain_sessions(&mut self) -> Result<(), io::Error> {
+        let now = Instant::now();
+        let tun_addr = self.dhcp_state.tun_addr;
+
+        let is_expired = self.previous_session.as_ref()
+            .map(|(_, expire_at)| now >= *expire_at)
+            .unwrap_or(false);
+        if is_expired {
+            self.previous_session = None;
+        }
+
+        if !self.rejoining && now.duration_since(self.last_received) >= self.config.server_timeout {
+            warn!("[{}] 服务端没有响应，重新接入 ...", tun_addr);
+            self.rejoining = true;
+            self.start_rekey()?;
+            return Ok(());
+        }
+
+        if !self.rejoining && now.duration_since(self.last_keepalive) >= self.config.keepalive_interval {
+            self.last_keepalive = now;
+            let message = self.session.seal(KEEPALIVE_PACKET_SIGNATURE, &[]);
+            // NOTE: 网络暂时不可用时忽略错误，由超时检测负责重新接入
+            self.send_to_server(&message);
+        }
+
+        match self.pending_handshake {
+            Some(ref pending) => {
+                if now.duration_since(pending.sent_at) >= REKEY_RETRY_TIME {
+                    debug!("[{}] 握手超时，重新发起 rekey", tun_addr);
+                    self.start_rekey()?;
+                }
+            },
+            None => {
+                if self.session.needs_rekey(self.config.rekey_after_time, self.config.rekey_after_bytes) {
+                    info!("[{}] 开始 rekey (已传输 {} 字节)", tun_addr, self.session.bytes());
+                    self.start_rekey()?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
     pub fn run_forever(&mut self) -> Result<(), io::Error> {
         let mut events = mio::Events::with_capacity(1024);
         let poll = mio::Poll::new().unwrap();
 
         poll.register(&self.tun_device, TUN_TOKEN, mio::Ready::readable(), mio::PollOpt::edge())?;
         poll.register(&self.udp_socket, UDP_TOKEN, mio::Ready::readable(), mio::PollOpt::edge())?;
+        #[cfg(target_os = "linux")]
+        poll.register(&self.route_monitor, ROUTE_TOKEN, mio::Ready::readable(), mio::PollOpt::edge())?;
 
         let timeout = std::time::Duration::new(2, 0);
 
         loop {
             if !signal::is_running() {
                 // 通知断开链接，不再需要处理错误
-                let _ = self.udp_socket.send(&BYE_PACKET_SIGNATURE);
-                // TODO: 清理系统配置
+                let message = self.session.seal(BYE_PACKET_SIGNATURE, &[]);
+                let _ = self.udp_socket.send(&message);
+                self.system_config.restore();
                 break;
             }
 
+            let is_rebound = std::mem::replace(&mut self.udp_socket_rebound, false);
+            if self.rebind_if_needed()? || is_rebound {
+                poll.register(&self.udp_socket, UDP_TOKEN, mio::Ready::readable(), mio::PollOpt::edge())?;
+            }
+            self.maintain_sessions()?;
+
             if let Err(_) = poll.poll(&mut events, Some(timeout)) {
                 continue;
             }
             
             for event in events.iter() {
                 match event.token() {
-                    UDP_TOKEN => {
-                        let amt = self.udp_socket.recv(&mut self.buffer)?;
+                    UDP_TOKEN => loop {
+                        let amt = match recv_server_pkt(&self.udp_socket, &mut self.buffer) {
+                            Ok(Some(amt)) => amt,
+                            Ok(None) => break,
+                            Err(e) => {
+                                warn!("[UDP] 读取数据包失败: {}", e);
+                                break;
+                            },
+                        };
 
                         if amt <= 4 {
                             trace!("畸形的数据包");
@@ -207,33 +634,64 @@ impl VpnClient {
                             self.buffer[2], self.buffer[3], 
                         ];
 
-                        let packet = &self.buffer[4..amt];
-
                         match packet_signature {
-                            DHCP_REQ_PACKET_SIGNATURE => {
-                                debug!("DHCP Request packet signature.");
+                            HANDSHAKE_INIT_PACKET_SIGNATURE => {
+                                debug!("Handshake init packet signature.");
+                                continue;
+                            },
+                            HANDSHAKE_RESP_PACKET_SIGNATURE => {
+                                self.handle_handshake_resp(amt)?;
                                 continue;
                             },
-                            DHCP_RES_PACKET_SIGNATURE => {
-                                debug!("DHCP Response packet signature.");
+                            REKEY_PACKET_SIGNATURE => {
+                                if let Err(_) = self.open(amt) {
+                                    self.auth_failures += 1;
+                                    debug!("[UDP] 丢弃数据包: 认证失败 (累计 {} 个)", self.auth_failures);
+                                    continue;
+                                }
+
+                                if self.pending_handshake.is_none() {
+                                    info!("[{}] 服务端请求 rekey", self.dhcp_state.tun_addr);
+                                    self.start_rekey()?;
+                                }
+                                continue;
+                            },
+                            KEEPALIVE_PACKET_SIGNATURE => {
+                                if let Err(_) = self.open(amt) {
+                                    self.auth_failures += 1;
+                                    debug!("[UDP] 丢弃数据包: 认证失败 (累计 {} 个)", self.auth_failures);
+                                }
                                 continue;
                             },
                             TUNNEL_PACKET_SIGNATURE => {
-                                // debug!("\x1b[31m [UDP] \x1b[0m", PrettyPrinter::<Ipv4Packet<&[u8]>>::new("", &packet));
-                                let ipv4_packet = Ipv4Packet::new_unchecked(&packet);
-                                let ipv4_protocol = ipv4_packet.protocol();
-                                let src_ip = ipv4_packet.src_addr();
-                                let dst_ip = ipv4_packet.dst_addr();
+                                let packet = match self.open(amt) {
+                                    Ok(packet) => packet,
+                                    Err(_) => {
+                                        self.auth_failures += 1;
+                                        debug!("[UDP] 丢弃数据包: 认证失败 (累计 {} 个)", self.auth_failures);
+                                        continue;
+                                    }
+                                };
+
+                                if packet.is_empty() {
+                                    continue;
+                                }
 
-                                trace!("[UDP] Forwarding IPv4 {} {} --> {} ...",
-                                    ipv4_protocol,
+                                // debug!("\x1b[31m [UDP] \x1b[0m", PrettyPrinter::<Ipv4Packet<&[u8]>>::new("", &packet));
+                                let (ip_protocol, src_ip, dst_ip) = match parse_ip_pkt(&packet) {
+                                    Some(ip_info) => ip_info,
+                                    None => {
+                                        trace!("[UDP] 畸形的 IP 数据包");
+                                        continue;
+                                    },
+                                };
+
+                                trace!("[UDP] Forwarding {} {} --> {} ...",
+                                    ip_protocol,
                                     src_ip,
                                     dst_ip);
 
-                                #[cfg(target_os = "macos")]
-                                let packet = &self.buffer[..amt];
-                                
-                                self.tun_device.write(&packet)?;
+                                write_tun_pkt(&mut self.tun_device, &mut self.buffer, &packet)?;
                             },
                             BYE_PACKET_SIGNATURE => {
                                 continue;
@@ -245,49 +703,37 @@ impl VpnClient {
                         }
                     },
                     TUN_TOKEN => {
-                        #[cfg(target_os = "macos")]
-                        let amt = self.tun_device.read(&mut self.buffer)?;
-
-                        #[cfg(target_os = "linux")]
-                        &mut self.buffer[..4].copy_from_slice(&TUNNEL_PACKET_SIGNATURE);
-                        #[cfg(target_os = "linux")]
-                        let amt = self.tun_device.read(&mut self.buffer[4..])?;
-                        
-                        if amt <= 4 {
+                        let amt = read_tun_pkt(&mut self.tun_device, &mut self.buffer)?;
+                        if amt == 0 {
                             trace!("畸形的数据包");
                             continue;
                         }
 
-                        let packet_signature = [
-                            self.buffer[0], self.buffer[1], 
-                            self.buffer[2], self.buffer[3], 
-                        ];
-
-                        #[cfg(target_os = "macos")]
-                        assert_eq!(packet_signature, TUNNEL_PACKET_SIGNATURE);
-
-                        #[cfg(target_os = "linux")]
-                        let mut packet = &self.buffer[4..amt + 4];
-                        #[cfg(target_os = "macos")]
-                        let mut packet = &self.buffer[4..amt];
+                        let packet = &self.buffer[4..amt + 4];
+                        let (ip_protocol, src_ip, dst_ip) = match parse_ip_pkt(&packet) {
+                            Some(ip_info) => ip_info,
+                            None => {
+                                trace!("畸形的 IP 数据包");
+                                continue;
+                            },
+                        };
 
-                        if IpVersion::of_packet(&packet) != Ok(IpVersion::Ipv4) {
-                            trace!("暂时只支持处理 IPv4 协议！");
+                        if self.config.exclude_routes.iter().any(|cidr| cidr.contains_addr(&dst_ip)) {
+                            trace!("[TUN] 丢弃不经过隧道的数据包: {} --> {}", src_ip, dst_ip);
                             continue;
                         }
 
-                        let ipv4_packet = Ipv4Packet::new_unchecked(&packet);
-                        let ipv4_protocol = ipv4_packet.protocol();
-                        let src_ip = ipv4_packet.src_addr();
-                        let dst_ip = ipv4_packet.dst_addr();
-
-                        trace!("[TUN] Forwarding IPv4 {} {} --> {} to {}:{} over UDP ...",
-                            ipv4_protocol,
+                        trace!("[TUN] Forwarding {} {} --> {} to {} over UDP ...",
+                            ip_protocol,
                             src_ip,
                             dst_ip,
-                            self.config.vpn_server_addr,
-                            self.config.vpn_server_port);
-                        self.udp_socket.send(&self.buffer[..packet.len()+4])?;
+                            self.config.vpn_server_addr);
+                        let message = self.session.seal(TUNNEL_PACKET_SIGNATURE, &packet);
+                        self.send_to_server(&message);
+                    },
+                    #[cfg(target_os = "linux")]
+                    ROUTE_TOKEN => {
+                        self.handle_route_events()?;
                     },
                     _ => unreachable!(),
                 }
@@ -298,3 +744,171 @@ impl VpnClient {
     }
 }
 
+
+/// 通过系统默认网关添加一条到服务端的主机路由，避免隧道数据包被路由回隧道，返回使用的网关
+fn add_server_route(config: &VpnClientConfig, system_config: &mut SystemConfig) -> Result<IpAddr, io::Error> {
+    let server_ip = config.vpn_server_addr.ip();
+    let prefix_len = if server_ip.is_ipv4() { 32 } else { 128 };
+
+    match system::default_gateway(server_ip.is_ipv4())? {
+        Some(gateway) => {
+            system_config.add_route(server_ip, prefix_len, Some(gateway), None)?;
+            Ok(gateway)
+        },
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "找不到系统默认网关！")),
+    }
+}
+
+/// 网段的网络地址
+fn cidr_network(cidr: &IpCidr) -> IpAddr {
+    match cidr {
+        IpCidr::Ipv4(cidr) => IpAddr::V4(cidr.network().address().into()),
+        IpCidr::Ipv6(cidr) => {
+            let prefix_len = cidr.prefix_len() as u32;
+            let mask = if prefix_len == 0 { 0 } else { u128::max_value() << (128 - prefix_len) };
+            IpAddr::V6(Ipv6Addr::from(u128::from_be_bytes(cidr.address().0) & mask))
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// 添加隧道子网路由，以及经过隧道和不经过隧道的路由
+///
+/// 经过隧道的路由按以下顺序选择:
+///     1. 客户端配置的 `include_routes`
+///     2. 服务端推送的路由
+///     3. 覆盖默认路由的两条 /1 路由 (原来的默认路由保持不变)
+/// 经过隧道 (TUN 设备) 的路由: 隧道网段，以及配置的或者服务端推送的路由
+fn tunnel_routes(config: &VpnClientConfig, dhcp_state: &DhcpState) -> Vec<(IpAddr, u8)> {
+    let tun_cidr = Ipv4Cidr::from_netmask(dhcp_state.tun_addr, dhcp_state.tun_netmask).unwrap();
+    let mut tun_cidrs = vec![IpCidr::Ipv4(tun_cidr)];
+    if let Some(tun_addr6) = dhcp_state.tun_addr6 {
+        tun_cidrs.push(IpCidr::Ipv6(tun_addr6));
+    }
+
+    let routes = if !config.include_routes.is_empty() {
+        config.include_routes.clone()
+    } else if !dhcp_state.routes.is_empty() {
+        dhcp_state.routes.clone()
+    } else {
+        let mut routes = vec![
+            IpCidr::new(IpAddress::v4(0, 0, 0, 0), 1),
+            IpCidr::new(IpAddress::v4(128, 0, 0, 0), 1),
+        ];
+        if dhcp_state.tun_addr6.is_some() {
+            routes.push(IpCidr::new(IpAddress::Ipv6(Ipv6Address::UNSPECIFIED), 1));
+            routes.push(IpCidr::new(IpAddress::Ipv6(Ipv6Address::new(0x8000, 0, 0, 0, 0, 0, 0, 0)), 1));
+        }
+        routes
+    };
+
+    for cidr in routes.iter() {
+        if let IpCidr::Ipv6(_) = cidr {
+            if dhcp_state.tun_addr6.is_none() {
+                warn!("隧道没有 IPv6 地址，忽略路由: {}", cidr);
+                continue;
+            }
+        }
+        tun_cidrs.push(*cidr);
+    }
+
+    let mut tun_routes = Vec::new();
+    for cidr in tun_cidrs.iter() {
+        let route = (cidr_network(cidr), cidr.prefix_len());
+        if !tun_routes.contains(&route) {
+            tun_routes.push(route);
+        }
+    }
+
+    tun_routes
+}
+
+fn add_tunnel_routes(config: &VpnClientConfig, dhcp_state: &DhcpState, system_config: &mut SystemConfig) -> Result<(), io::Error> {
+    let tun_ifname = Some(config.tun_ifname.as_str());
+
+    if config.include_routes.is_empty() && !dhcp_state.routes.is_empty() {
+        info!("使用服务端推送的路由: {:?}", dhcp_state.routes);
+    }
+    for (dst_addr, prefix_len) in tunnel_routes(config, dhcp_state) {
+        system_config.add_route(dst_addr, prefix_len, None, tun_ifname)?;
+    }
+
+    for cidr in config.exclude_routes.iter() {
+        let is_ipv4 = match cidr { IpCidr::Ipv4(_) => true, _ => false, };
+        match system::default_gateway(is_ipv4)? {
+            Some(gateway) => system_config.add_route(cidr_network(cidr), cidr.prefix_len(), Some(gateway), None)?,
+            None => warn!("找不到系统默认网关，无法添加路由: {}", cidr),
+        }
+    }
+
+    Ok(())
+}
+
+/// 使用服务端推送的 DNS 配置
+fn configure_dns(config: &VpnClientConfig, dhcp_state: &DhcpState, system_config: &mut SystemConfig) -> Result<(), io::Error> {
+    if !config.configure_dns || dhcp_state.dns_servers.is_empty() {
+        return Ok(());
+    }
+
+    system_config.set_dns(&dhcp_state.dns_servers, &dhcp_state.search_domains)
+}
+
+/// 系统路由表为发往服务端的数据包选择的源地址
+fn lookup_egress_addr(server_addr: &SocketAddr) -> Result<IpAddr, io::Error> {
+    let local_addr: SocketAddr = match server_addr {
+        SocketAddr::V4(_) => SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0).into(),
+    };
+
+    // NOTE: UDP 的 connect 不会发送任何数据包，只会查询路由表
+    let socket = UdpSocket::bind(local_addr)?;
+    socket.connect(server_addr)?;
+
+    Ok(socket.local_addr()?.ip())
+}
+
+/// 网络切换或者服务端重启时出现的错误，不应该结束事件循环，由超时检测负责重新接入
+fn is_transient_error(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::AddrNotAvailable
+        | io::ErrorKind::Interrupted => true,
+        _ => match e.raw_os_error() {
+            Some(libc::ENETUNREACH) | Some(libc::EHOSTUNREACH) | Some(libc::ENETDOWN) => true,
+            _ => false,
+        },
+    }
+}
+
+/// 读取服务端发来的数据包，没有更多数据可读时返回 `None`
+///
+/// NOTE: 服务端退出或者重启时，已连接的 UDP Socket 会收到 ICMP 端口不可达 (`ConnectionRefused`)，
+///       这类错误只记录下来，继续读取
+fn recv_server_pkt(udp_socket: &mio::net::UdpSocket, buffer: &mut [u8]) -> Result<Option<usize>, io::Error> {
+    loop {
+        match udp_socket.recv(buffer) {
+            Ok(amt) => return Ok(Some(amt)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(ref e) if is_transient_error(e) => debug!("[UDP] 读取数据包失败: {}", e),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+
+#[test]
+fn test_recv_server_pkt_connection_refused() {
+    // 找一个没有监听的端口
+    let server_addr = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    let udp_socket = mio::net::UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    udp_socket.connect(server_addr).unwrap();
+    udp_socket.send(b"ping").unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+
+    let mut buffer = [0u8; 64];
+    assert_eq!(recv_server_pkt(&udp_socket, &mut buffer).unwrap(), None);
+    assert!(is_transient_error(&io::Error::from(io::ErrorKind::ConnectionRefused)));
+    assert!(!is_transient_error(&io::Error::from(io::ErrorKind::PermissionDenied)));
+}
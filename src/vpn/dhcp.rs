@@ -0,0 +1,214 @@
+use smoltcp::wire::{ IpAddress, IpCidr, Ipv4Address, Ipv6Address, Ipv6Cidr, };
+
+use std::io;
+use std::net::{ IpAddr, Ipv4Addr, Ipv6Addr, };
+
+
+// 握手响应中加密传输的网络配置:
+//      tun_addr (4) | gateway (4) | netmask (4) | tun_addr6 (16) | gateway6 (16) | prefix_len6 (1)
+//      | route_count (1) | route ... | dns_count (1) | dns_server ... | domain_count (1) | search_domain ...
+//
+// 没有配置 IPv6 地址池时，IPv6 部分全部为 0。每条路由的格式为:
+//      ip_version (1) | prefix_len (1) | address (4 或者 16)
+// DNS 服务器的格式为:
+//      ip_version (1) | address (4 或者 16)
+// 搜索域的格式为:
+//      len (1) | domain (len)
+const FIXED_LEN: usize = 45;
+
+
+/// 服务端在握手时分配给客户端的地址以及网络配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct DhcpState {
+    pub tun_addr        : Ipv4Address,
+    pub tun_gateway_addr: Ipv4Address,
+    pub tun_netmask     : Ipv4Address,
+    // 服务端没有配置 IPv6 地址池时为空
+    pub tun_addr6        : Option<Ipv6Cidr>,
+    pub tun_gateway_addr6: Option<Ipv6Address>,
+    // 服务端建议客户端经过隧道的路由
+    pub routes: Vec<IpCidr>,
+    // 服务端推送的 DNS 配置
+    pub dns_servers: Vec<IpAddr>,
+    pub search_domains: Vec<String>,
+}
+
+impl DhcpState {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = vec![0u8; FIXED_LEN];
+        (&mut payload[0..4]).copy_from_slice(&self.tun_addr.0);
+        (&mut payload[4..8]).copy_from_slice(&self.tun_gateway_addr.0);
+        (&mut payload[8..12]).copy_from_slice(&self.tun_netmask.0);
+        if let (Some(tun_addr6), Some(tun_gateway_addr6)) = (self.tun_addr6, self.tun_gateway_addr6) {
+            (&mut payload[12..28]).copy_from_slice(&tun_addr6.address().0);
+            (&mut payload[28..44]).copy_from_slice(&tun_gateway_addr6.0);
+            payload[44] = tun_addr6.prefix_len();
+        }
+
+        let routes = &self.routes[..std::cmp::min(self.routes.len(), u8::max_value() as usize)];
+        payload.push(routes.len() as u8);
+        for route in routes {
+            match route {
+                IpCidr::Ipv4(cidr) => {
+                    payload.extend_from_slice(&[4, cidr.prefix_len()]);
+                    payload.extend_from_slice(&cidr.address().0);
+                },
+                IpCidr::Ipv6(cidr) => {
+                    payload.extend_from_slice(&[6, cidr.prefix_len()]);
+                    payload.extend_from_slice(&cidr.address().0);
+                },
+                _ => unreachable!(),
+            }
+        }
+
+        let dns_servers = &self.dns_servers[..std::cmp::min(self.dns_servers.len(), u8::max_value() as usize)];
+        payload.push(dns_servers.len() as u8);
+        for dns_server in dns_servers {
+            match dns_server {
+                IpAddr::V4(addr) => {
+                    payload.push(4);
+                    payload.extend_from_slice(&addr.octets());
+                },
+                IpAddr::V6(addr) => {
+                    payload.push(6);
+                    payload.extend_from_slice(&addr.octets());
+                },
+            }
+        }
+
+        // NOTE: 域名最长 253 字节，超出长度的搜索域会被忽略
+        let search_domains = self.search_domains.iter()
+            .filter(|domain| domain.len() <= u8::max_value() as usize)
+            .take(u8::max_value() as usize)
+            .collect::<Vec<&String>>();
+        payload.push(search_domains.len() as u8);
+        for domain in search_domains {
+            payload.push(domain.len() as u8);
+            payload.extend_from_slice(domain.as_bytes());
+        }
+
+        payload
+    }
+
+    pub fn parse(payload: &[u8]) -> Result<Self, io::Error> {
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "握手失败: 未知协议！");
+
+        if payload.len() < FIXED_LEN + 1 {
+            return Err(malformed());
+        }
+
+        let tun_addr = Ipv4Address::from_bytes(&payload[0..4]);
+        let tun_gateway_addr = Ipv4Address::from_bytes(&payload[4..8]);
+        let tun_netmask = Ipv4Address::from_bytes(&payload[8..12]);
+
+        let (tun_addr6, tun_gateway_addr6) = if payload[44] > 0 {
+            let tun_addr6 = Ipv6Address::from_bytes(&payload[12..28]);
+            let tun_gateway_addr6 = Ipv6Address::from_bytes(&payload[28..44]);
+            (Some(Ipv6Cidr::new(tun_addr6, payload[44])), Some(tun_gateway_addr6))
+        } else {
+            (None, None)
+        };
+
+        let route_count = payload[FIXED_LEN] as usize;
+        let mut routes = Vec::with_capacity(route_count);
+        let mut data = &payload[FIXED_LEN + 1..];
+        for _ in 0..route_count {
+            if data.len() < 2 {
+                return Err(malformed());
+            }
+
+            let (ip_version, prefix_len) = (data[0], data[1]);
+            let (addr_len, max_prefix_len) = match ip_version {
+                4 => (4, 32),
+                6 => (16, 128),
+                _ => return Err(malformed()),
+            };
+            if data.len() < 2 + addr_len || prefix_len > max_prefix_len {
+                return Err(malformed());
+            }
+
+            let addr = &data[2..2 + addr_len];
+            let addr = if ip_version == 4 {
+                IpAddress::Ipv4(Ipv4Address::from_bytes(addr))
+            } else {
+                IpAddress::Ipv6(Ipv6Address::from_bytes(addr))
+            };
+            routes.push(IpCidr::new(addr, prefix_len));
+            data = &data[2 + addr_len..];
+        }
+
+        let mut dns_servers = Vec::new();
+        let mut search_domains = Vec::new();
+        // NOTE: 旧版本的服务端不会推送 DNS 配置
+        if !data.is_empty() {
+            let dns_count = data[0] as usize;
+            data = &data[1..];
+            for _ in 0..dns_count {
+                if data.is_empty() {
+                    return Err(malformed());
+                }
+
+                let addr_len = match data[0] { 4 => 4, 6 => 16, _ => return Err(malformed()), };
+                if data.len() < 1 + addr_len {
+                    return Err(malformed());
+                }
+
+                let addr = &data[1..1 + addr_len];
+                let addr = if addr_len == 4 {
+                    let mut octets = [0u8; 4];
+                    octets.copy_from_slice(addr);
+                    IpAddr::V4(Ipv4Addr::from(octets))
+                } else {
+                    let mut octets = [0u8; 16];
+                    octets.copy_from_slice(addr);
+                    IpAddr::V6(Ipv6Addr::from(octets))
+                };
+                dns_servers.push(addr);
+                data = &data[1 + addr_len..];
+            }
+
+            if data.is_empty() {
+                return Err(malformed());
+            }
+            let domain_count = data[0] as usize;
+            data = &data[1..];
+            for _ in 0..domain_count {
+                if data.is_empty() || data.len() < 1 + data[0] as usize {
+                    return Err(malformed());
+                }
+
+                let len = data[0] as usize;
+                let domain = std::str::from_utf8(&data[1..1 + len]).map_err(|_| malformed())?;
+                search_domains.push(domain.to_string());
+                data = &data[1 + len..];
+            }
+        }
+
+        Ok(DhcpState {
+            tun_addr, tun_gateway_addr, tun_netmask, tun_addr6, tun_gateway_addr6,
+            routes, dns_servers, search_domains,
+        })
+    }
+}
+
+
+#[test]
+fn test_dhcp_state_roundtrip() {
+    let dhcp_state = DhcpState {
+        tun_addr: Ipv4Address::new(10, 192, 168, 5),
+        tun_gateway_addr: Ipv4Address::new(10, 192, 168, 1),
+        tun_netmask: Ipv4Address::new(255, 255, 255, 0),
+        tun_addr6: Some(Ipv6Cidr::new(Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 5), 64)),
+        tun_gateway_addr6: Some(Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 1)),
+        routes: vec![
+            IpCidr::new(IpAddress::v4(10, 0, 0, 0), 8),
+            IpCidr::new(IpAddress::Ipv6(Ipv6Address::new(0xfd12, 0, 0, 0, 0, 0, 0, 0)), 48),
+        ],
+        dns_servers: vec![ "10.192.168.1".parse().unwrap(), "fd00::1".parse().unwrap() ],
+        search_domains: vec![ "vpn.example.com".to_string() ],
+    };
+
+    let payload = dhcp_state.to_bytes();
+    assert_eq!(DhcpState::parse(&payload).unwrap(), dhcp_state);
+    assert!(DhcpState::parse(&payload[..payload.len() - 1]).is_err());
+}
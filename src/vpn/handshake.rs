@@ -0,0 +1,320 @@
+// 握手协议
+//
+// 客户端和服务端各自持有一个长期的 Ed25519 身份密钥，
+// 双方交换临时的 X25519 公钥，并对握手内容签名，
+// 会话密钥由 X25519 共享密钥通过 HKDF 派生。
+//
+//      Client                                          Server
+//      HANDSHAKE_INIT  -------------------------------->
+//          client_static_key | client_ephemeral_key | timestamp | signature
+//
+//                      <--------------------------------  HANDSHAKE_RESP
+//          server_ephemeral_key | session_id | signature | sealed(tun_addr | gateway | netmask)
+//
+// 服务端只响应在白名单里面的客户端，其它的握手请求一律忽略。
+use crypto::{
+    Aes256Key, Identity, PublicKey,
+    SIGNATURE_SIZE, PUBLIC_KEY_SIZE, DH_KEY_SIZE,
+};
+
+use crate::vpn::{ HANDSHAKE_INIT_PACKET_SIGNATURE, HANDSHAKE_RESP_PACKET_SIGNATURE, };
+
+use std::time::{ SystemTime, UNIX_EPOCH, };
+
+
+const INIT_LABEL: &[u8] = b"exodus handshake init";
+const RESP_LABEL: &[u8] = b"exodus handshake resp";
+const CLIENT_TO_SERVER_LABEL: &[u8] = b"exodus client to server";
+const SERVER_TO_CLIENT_LABEL: &[u8] = b"exodus server to client";
+
+pub const HANDSHAKE_INIT_LEN: usize = 4 + PUBLIC_KEY_SIZE + DH_KEY_SIZE + 8 + SIGNATURE_SIZE;
+pub const HANDSHAKE_RESP_MIN_LEN: usize = 4 + DH_KEY_SIZE + 4 + SIGNATURE_SIZE;
+// 握手时间戳和本地时间允许的最大偏差（毫秒），超出的握手请求视为过期
+pub const HANDSHAKE_MAX_CLOCK_SKEW: u64 = 5 * 60 * 1000;
+
+
+/// 毫秒级的 UNIX 时间戳
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+
+#[derive(Clone)]
+pub struct HandshakeInit {
+    pub static_key: PublicKey,
+    pub ephemeral_key: [u8; DH_KEY_SIZE],
+    // 毫秒级的 UNIX 时间戳，服务端用它来拒绝被重放的握手请求
+    pub timestamp: u64,
+    pub signature: [u8; SIGNATURE_SIZE],
+}
+
+impl HandshakeInit {
+    pub fn new(identity: &Identity, server_key: &PublicKey, ephemeral_key: [u8; DH_KEY_SIZE]) -> Self {
+        let static_key = identity.public_key();
+        let timestamp = unix_timestamp();
+        let message = Self::signed_message(server_key, &static_key, &ephemeral_key, timestamp);
+        let signature = identity.sign(&message);
+
+        Self { static_key, ephemeral_key, timestamp, signature }
+    }
+
+    fn signed_message(server_key: &PublicKey,
+                      static_key: &PublicKey,
+                      ephemeral_key: &[u8; DH_KEY_SIZE],
+                      timestamp: u64) -> Vec<u8> {
+        let mut message = Vec::with_capacity(INIT_LABEL.len() + PUBLIC_KEY_SIZE * 2 + DH_KEY_SIZE + 8);
+        message.extend_from_slice(INIT_LABEL);
+        message.extend_from_slice(server_key.as_bytes());
+        message.extend_from_slice(static_key.as_bytes());
+        message.extend_from_slice(ephemeral_key);
+        message.extend_from_slice(&timestamp.to_be_bytes());
+        message
+    }
+
+    /// 验证客户端的签名，`server_key` 为服务端自己的身份公钥
+    pub fn verify(&self, server_key: &PublicKey) -> bool {
+        let message = Self::signed_message(server_key, &self.static_key, &self.ephemeral_key, self.timestamp);
+        self.static_key.verify(&message, &self.signature)
+    }
+
+    /// 检查时间戳：必须比该客户端上一次握手的时间戳新 (防重放)，
+    /// 并且和本地时间 `now` 的偏差不超过 `HANDSHAKE_MAX_CLOCK_SKEW` (防止服务端重启后被重放旧的握手请求)
+    pub fn is_fresh(&self, last_timestamp: Option<u64>, now: u64) -> bool {
+        if let Some(last_timestamp) = last_timestamp {
+            if self.timestamp <= last_timestamp {
+                return false;
+            }
+        }
+
+        let skew = if self.timestamp > now { self.timestamp - now } else { now - self.timestamp };
+        skew <= HANDSHAKE_MAX_CLOCK_SKEW
+    }
+
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        if message.len() != HANDSHAKE_INIT_LEN || message[..4] != HANDSHAKE_INIT_PACKET_SIGNATURE {
+            return None;
+        }
+
+        let message = &message[4..];
+        let static_key = PublicKey::from_bytes(&message[..PUBLIC_KEY_SIZE])?;
+        let message = &message[PUBLIC_KEY_SIZE..];
+
+        let mut ephemeral_key = [0u8; DH_KEY_SIZE];
+        ephemeral_key.copy_from_slice(&message[..DH_KEY_SIZE]);
+        let message = &message[DH_KEY_SIZE..];
+
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&message[..8]);
+        let timestamp = u64::from_be_bytes(timestamp);
+
+        let mut signature = [0u8; SIGNATURE_SIZE];
+        signature.copy_from_slice(&message[8..]);
+
+        Some(Self { static_key, ephemeral_key, timestamp, signature })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut message = Vec::with_capacity(HANDSHAKE_INIT_LEN);
+        message.extend_from_slice(&HANDSHAKE_INIT_PACKET_SIGNATURE);
+        message.extend_from_slice(self.static_key.as_bytes());
+        message.extend_from_slice(&self.ephemeral_key);
+        message.extend_from_slice(&self.timestamp.to_be_bytes());
+        message.extend_from_slice(&self.signature);
+        message
+    }
+}
+
+
+#[derive(Clone)]
+pub struct HandshakeResp {
+    pub ephemeral_key: [u8; DH_KEY_SIZE],
+    pub session_id: u32,
+    pub signature: [u8; SIGNATURE_SIZE],
+    // 使用服务端到客户端的会话密钥加密的地址分配信息
+    pub payload: Vec<u8>,
+}
+
+impl HandshakeResp {
+    pub fn new(identity: &Identity,
+               init: &HandshakeInit,
+               ephemeral_key: [u8; DH_KEY_SIZE],
+               session_id: u32,
+               payload: Vec<u8>) -> Self {
+        let message = Self::signed_message(init, &ephemeral_key, session_id);
+        let signature = identity.sign(&message);
+
+        Self { ephemeral_key, session_id, signature, payload }
+    }
+
+    fn signed_message(init: &HandshakeInit, ephemeral_key: &[u8; DH_KEY_SIZE], session_id: u32) -> Vec<u8> {
+        let mut message = Vec::with_capacity(RESP_LABEL.len() + PUBLIC_KEY_SIZE + DH_KEY_SIZE * 2 + 8 + 4);
+        message.extend_from_slice(RESP_LABEL);
+        message.extend_from_slice(init.static_key.as_bytes());
+        message.extend_from_slice(&init.ephemeral_key);
+        message.extend_from_slice(&init.timestamp.to_be_bytes());
+        message.extend_from_slice(ephemeral_key);
+        message.extend_from_slice(&session_id.to_be_bytes());
+        message
+    }
+
+    /// 验证服务端的签名，同时确认这是针对 `init` 的响应
+    pub fn verify(&self, server_key: &PublicKey, init: &HandshakeInit) -> bool {
+        let message = Self::signed_message(init, &self.ephemeral_key, self.session_id);
+        server_key.verify(&message, &self.signature)
+    }
+
+    pub fn parse(message: &[u8]) -> Option<Self> {
+        if message.len() < HANDSHAKE_RESP_MIN_LEN || message[..4] != HANDSHAKE_RESP_PACKET_SIGNATURE {
+            return None;
+        }
+
+        let message = &message[4..];
+        let mut ephemeral_key = [0u8; DH_KEY_SIZE];
+        ephemeral_key.copy_from_slice(&message[..DH_KEY_SIZE]);
+        let message = &message[DH_KEY_SIZE..];
+
+        let session_id = u32::from_be_bytes([message[0], message[1], message[2], message[3]]);
+        let message = &message[4..];
+
+        let mut signature = [0u8; SIGNATURE_SIZE];
+        signature.copy_from_slice(&message[..SIGNATURE_SIZE]);
+        let payload = message[SIGNATURE_SIZE..].to_vec();
+
+        Some(Self { ephemeral_key, session_id, signature, payload })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut message = Vec::with_capacity(HANDSHAKE_RESP_MIN_LEN + self.payload.len());
+        message.extend_from_slice(&HANDSHAKE_RESP_PACKET_SIGNATURE);
+        message.extend_from_slice(&self.ephemeral_key);
+        message.extend_from_slice(&self.session_id.to_be_bytes());
+        message.extend_from_slice(&self.signature);
+        message.extend_from_slice(&self.payload);
+        message
+    }
+}
+
+
+/// 从 X25519 共享密钥派生会话密钥，返回 (客户端到服务端, 服务端到客户端) 两个方向的密钥
+pub fn session_keys(shared_secret: &[u8; DH_KEY_SIZE],
+                    init_ephemeral_key: &[u8; DH_KEY_SIZE],
+                    resp_ephemeral_key: &[u8; DH_KEY_SIZE]) -> (Aes256Key, Aes256Key) {
+    let mut salt = [0u8; DH_KEY_SIZE * 2];
+    (&mut salt[..DH_KEY_SIZE]).copy_from_slice(init_ephemeral_key);
+    (&mut salt[DH_KEY_SIZE..]).copy_from_slice(resp_ephemeral_key);
+
+    let c2s = Aes256Key::derive(shared_secret, &salt, CLIENT_TO_SERVER_LABEL);
+    let s2c = Aes256Key::derive(shared_secret, &salt, SERVER_TO_CLIENT_LABEL);
+    (c2s, s2c)
+}
+
+
+#[cfg(test)]
+fn test_handshake_init(client: &Identity, server: &Identity) -> (HandshakeInit, crypto::EphemeralKey) {
+    let ephemeral_key = crypto::EphemeralKey::generate();
+    let init = HandshakeInit::new(client, &server.public_key(), ephemeral_key.public_key());
+    (init, ephemeral_key)
+}
+
+#[test]
+fn test_handshake_roundtrip() {
+    use crypto::{ AesSiv256, EphemeralKey, };
+
+    let client = Identity::generate();
+    let server = Identity::generate();
+
+    // Client -> Server
+    let (init, client_ephemeral_key) = test_handshake_init(&client, &server);
+    let init = HandshakeInit::parse(&init.to_bytes()).unwrap();
+    assert_eq!(init.static_key, client.public_key());
+    assert!(init.verify(&server.public_key()));
+    assert!(init.is_fresh(None, unix_timestamp()));
+
+    let server_ephemeral_key = EphemeralKey::generate();
+    let server_ephemeral_public_key = server_ephemeral_key.public_key();
+    let shared_secret = server_ephemeral_key.diffie_hellman(&init.ephemeral_key);
+    let (server_c2s, server_s2c) = session_keys(&shared_secret, &init.ephemeral_key, &server_ephemeral_public_key);
+    let payload = AesSiv256::new(server_s2c).seal(b"exodus");
+    let resp = HandshakeResp::new(&server, &init, server_ephemeral_public_key, 7, payload);
+
+    // Server -> Client
+    let resp = HandshakeResp::parse(&resp.to_bytes()).unwrap();
+    assert_eq!(resp.session_id, 7);
+    assert!(resp.verify(&server.public_key(), &init));
+
+    let shared_secret = client_ephemeral_key.diffie_hellman(&resp.ephemeral_key);
+    let (client_c2s, client_s2c) = session_keys(&shared_secret, &init.ephemeral_key, &resp.ephemeral_key);
+    assert_eq!(client_c2s.to_string(), server_c2s.to_string());
+    assert_eq!(client_s2c.to_string(), server_s2c.to_string());
+    assert_ne!(client_c2s.to_string(), client_s2c.to_string());
+    assert_eq!(AesSiv256::new(client_s2c).open(&resp.payload).unwrap(), b"exodus");
+}
+
+#[test]
+fn test_handshake_bad_signature() {
+    let client = Identity::generate();
+    let server = Identity::generate();
+
+    let (init, _) = test_handshake_init(&client, &server);
+    let mut message = init.to_bytes();
+    message[HANDSHAKE_INIT_LEN - 1] ^= 0x01;
+    assert!(!HandshakeInit::parse(&message).unwrap().verify(&server.public_key()));
+
+    let resp = HandshakeResp::new(&server, &init, crypto::EphemeralKey::generate().public_key(), 7, Vec::new());
+    let mut message = resp.to_bytes();
+    message[HANDSHAKE_RESP_MIN_LEN - 1] ^= 0x01;
+    assert!(!HandshakeResp::parse(&message).unwrap().verify(&server.public_key(), &init));
+
+    // 签名本身正确，但被篡改了会话 ID
+    let mut resp = resp;
+    resp.session_id += 1;
+    assert!(!resp.verify(&server.public_key(), &init));
+}
+
+#[test]
+fn test_handshake_wrong_server_key() {
+    let client = Identity::generate();
+    let server = Identity::generate();
+    let other = Identity::generate();
+
+    // 发给其它服务端的握手请求
+    let (init, _) = test_handshake_init(&client, &other);
+    assert!(!init.verify(&server.public_key()));
+
+    // 其它服务端签名的响应
+    let (init, _) = test_handshake_init(&client, &server);
+    let resp = HandshakeResp::new(&other, &init, crypto::EphemeralKey::generate().public_key(), 7, Vec::new());
+    assert!(!resp.verify(&server.public_key(), &init));
+
+    // 针对另一个握手请求的响应
+    let (init2, _) = test_handshake_init(&client, &server);
+    let resp = HandshakeResp::new(&server, &init, crypto::EphemeralKey::generate().public_key(), 7, Vec::new());
+    assert!(!resp.verify(&server.public_key(), &init2));
+}
+
+#[test]
+fn test_handshake_replay() {
+    let client = Identity::generate();
+    let server = Identity::generate();
+
+    let (mut init, _) = test_handshake_init(&client, &server);
+    let now = init.timestamp;
+    assert!(init.is_fresh(None, now));
+    assert!(init.is_fresh(Some(now - 1), now));
+
+    // 重放
+    assert!(!init.is_fresh(Some(now), now));
+    assert!(!init.is_fresh(Some(now + 1), now));
+
+    // 过期或者来自未来
+    assert!(init.is_fresh(None, now + HANDSHAKE_MAX_CLOCK_SKEW));
+    assert!(!init.is_fresh(None, now + HANDSHAKE_MAX_CLOCK_SKEW + 1));
+    assert!(!init.is_fresh(None, now - HANDSHAKE_MAX_CLOCK_SKEW - 1));
+
+    // 修改时间戳会导致签名失效
+    init.timestamp += 1;
+    assert!(!init.verify(&server.public_key()));
+}
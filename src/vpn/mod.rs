@@ -1,6 +1,19 @@
 
+use smoltcp::wire::{ IpAddress, IpProtocol, IpVersion, Ipv4Packet, Ipv6Packet, };
+
+use std::io::{ self, Read, Write, };
+use std::mem;
+use std::net::SocketAddrV6;
+use std::os::unix::io::FromRawFd;
+use std::time::Duration;
+
 mod client;
 mod server;
+mod handshake;
+mod session;
+mod pool;
+mod dhcp;
+mod system;
 
 pub use self::client::{VpnClientConfig, VpnClient};
 pub use self::server::{VpnServerConfig, VpnServer};
@@ -8,16 +21,40 @@ pub use self::server::{VpnServerConfig, VpnServer};
 pub const TAP_TOKEN: mio::Token    = mio::Token(10);
 pub const TUN_TOKEN: mio::Token    = mio::Token(11);
 pub const UDP_TOKEN: mio::Token    = mio::Token(12);
+pub const UDP6_TOKEN: mio::Token   = mio::Token(13);
+pub const ROUTE_TOKEN: mio::Token  = mio::Token(14);
+pub const NETLINK_TOKEN: mio::Token = mio::Token(15);
 
 
 pub const DEFAULT_VPN_SERVER_TUNNEL_PORT: u16  = 9050;
 pub const DEFAULT_VPN_SERVER_DHCP_PORT: u16    = 9051;
 
-pub const DHCP_REQ_PACKET_SIGNATURE: [u8; 4] = [255, 255, 255, 200];
-pub const DHCP_RES_PACKET_SIGNATURE: [u8; 4] = [255, 255, 255, 201];
+pub const HANDSHAKE_INIT_PACKET_SIGNATURE: [u8; 4] = [255, 255, 255, 200];
+pub const HANDSHAKE_RESP_PACKET_SIGNATURE: [u8; 4] = [255, 255, 255, 201];
 // NOTE: 同时也是 macOS 系统里面 TUN 的 IPv4Packet 签名
-pub const TUNNEL_PACKET_SIGNATURE: [u8; 4]   = [000, 000, 000, 002];
-pub const BYE_PACKET_SIGNATURE: [u8; 4]      = [255, 255, 255, 255];
+pub const TUNNEL_PACKET_SIGNATURE: [u8; 4]         = [000, 000, 000, 002];
+// NOTE: macOS 系统里面 TUN 的 IPv6Packet 签名 (AF_INET6)
+#[cfg(target_os = "macos")]
+pub const TUN_IPV6_PACKET_SIGNATURE: [u8; 4]       = [000, 000, 000, 030];
+pub const BYE_PACKET_SIGNATURE: [u8; 4]            = [255, 255, 255, 255];
+// 服务端请求客户端重新握手
+pub const REKEY_PACKET_SIGNATURE: [u8; 4]          = [255, 255, 255, 202];
+// 客户端定期发送的心跳包，服务端收到之后原样回复
+pub const KEEPALIVE_PACKET_SIGNATURE: [u8; 4]      = [255, 255, 255, 203];
+
+pub const DEFAULT_REKEY_AFTER_TIME: Duration = Duration::from_secs(120);
+pub const DEFAULT_REKEY_AFTER_BYTES: u64     = 1 << 30;
+// rekey 完成之后，旧的会话密钥继续有效的时间
+pub const REKEY_GRACE_TIME: Duration         = Duration::from_secs(10);
+// 没有收到握手响应时，重新发起握手的间隔
+pub const REKEY_RETRY_TIME: Duration         = Duration::from_secs(5);
+
+// 客户端检查出口地址是否发生变化的间隔
+pub const EGRESS_CHECK_INTERVAL: Duration      = Duration::from_secs(2);
+
+pub const DEFAULT_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+// 超过这个时间没有收到对方的任何数据包，则认为对方已经断开
+pub const DEFAULT_PEER_TIMEOUT: Duration       = Duration::from_secs(60);
 
 
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
@@ -27,3 +64,87 @@ pub enum InterfaceKind {
     Internet,
 }
 
+
+/// 解析 IP 数据包的协议、源地址以及目标地址，支持 IPv4 和 IPv6
+pub fn parse_ip_pkt(packet: &[u8]) -> Option<(IpProtocol, IpAddress, IpAddress)> {
+    match IpVersion::of_packet(packet) {
+        Ok(IpVersion::Ipv4) => {
+            let ipv4_packet = Ipv4Packet::new_checked(packet).ok()?;
+            Some((ipv4_packet.protocol(), ipv4_packet.src_addr().into(), ipv4_packet.dst_addr().into()))
+        },
+        Ok(IpVersion::Ipv6) => {
+            let ipv6_packet = Ipv6Packet::new_checked(packet).ok()?;
+            Some((ipv6_packet.next_header(), ipv6_packet.src_addr().into(), ipv6_packet.dst_addr().into()))
+        },
+        _ => None,
+    }
+}
+
+/// 从 TUN 设备读取一个 IP 数据包，数据包存放在 `buffer[4..4 + amt]`
+pub fn read_tun_pkt(tun_device: &mut tun::Device, buffer: &mut [u8]) -> Result<usize, io::Error> {
+    // NOTE: macOS 系统里面 TUN 设备读出来的数据包带有 4 个字节的协议族头部
+    #[cfg(target_os = "macos")]
+    let amt = tun_device.read(buffer)?.saturating_sub(4);
+    #[cfg(target_os = "linux")]
+    let amt = tun_device.read(&mut buffer[4..])?;
+
+    Ok(amt)
+}
+
+/// 把 IP 数据包写入 TUN 设备，在 macOS 系统里面 `buffer` 用来拼接协议族头部
+pub fn write_tun_pkt(tun_device: &mut tun::Device, buffer: &mut [u8], packet: &[u8]) -> Result<(), io::Error> {
+    #[cfg(target_os = "macos")]
+    {
+        let packet_signature = match IpVersion::of_packet(packet) {
+            Ok(IpVersion::Ipv6) => TUN_IPV6_PACKET_SIGNATURE,
+            _ => TUNNEL_PACKET_SIGNATURE,
+        };
+        (&mut buffer[..4]).copy_from_slice(&packet_signature);
+        (&mut buffer[4..packet.len() + 4]).copy_from_slice(packet);
+        tun_device.write(&buffer[..packet.len() + 4])?;
+    }
+    #[cfg(target_os = "linux")]
+    tun_device.write(packet)?;
+
+    Ok(())
+}
+
+/// 创建只接收 IPv6 数据包的 UDP Socket (IPV6_V6ONLY)，
+/// 这样就不会和监听同一个端口的 IPv4 Socket 冲突
+pub fn bind_udp_v6only(addr: &SocketAddrV6) -> Result<mio::net::UdpSocket, io::Error> {
+    unsafe {
+        let fd = libc::socket(libc::AF_INET6, libc::SOCK_DGRAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // NOTE: 出错返回时由 std 负责关闭 fd
+        let socket = std::net::UdpSocket::from_raw_fd(fd);
+
+        let on: libc::c_int = 1;
+        let ret = libc::setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY,
+                                   &on as *const libc::c_int as *const libc::c_void,
+                                   mem::size_of::<libc::c_int>() as libc::socklen_t);
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut sa: libc::sockaddr_in6 = mem::zeroed();
+        #[cfg(target_os = "macos")]
+        {
+            sa.sin6_len = mem::size_of::<libc::sockaddr_in6>() as u8;
+        }
+        sa.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+        sa.sin6_port = addr.port().to_be();
+        sa.sin6_flowinfo = addr.flowinfo();
+        sa.sin6_addr.s6_addr = addr.ip().octets();
+        sa.sin6_scope_id = addr.scope_id();
+
+        let ret = libc::bind(fd, &sa as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                             mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t);
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        mio::net::UdpSocket::from_socket(socket)
+    }
+}
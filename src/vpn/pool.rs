@@ -0,0 +1,326 @@
+use smoltcp::wire::Ipv4Address;
+
+use crypto::PublicKey;
+
+use std::collections::{ HashMap, HashSet, VecDeque, };
+use std::fs;
+use std::io::{ self, BufRead, Write, };
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+
+fn to_number(addr: Ipv4Address) -> u32 {
+    u32::from_be_bytes(addr.0)
+}
+
+fn to_addr(number: u32) -> Ipv4Address {
+    Ipv4Address::from(Ipv4Addr::from(number))
+}
+
+
+/// 隧道的地址池
+///
+/// 分配地址的时候优先使用从未分配过的地址，然后才使用回收的地址，
+/// 这样客户端断开之后，它的地址会尽量长时间地保留给它 (租约)。
+/// 租约可以保存到文件，服务端重启之后客户端仍然可以得到相同的地址。
+pub struct AddressPool {
+    // 动态分配的地址范围: [start, end)
+    start: u32,
+    end: u32,
+    // 大于等于 next 的地址从未分配过
+    next: u32,
+    // 回收的地址，取出时需要再次检查是否可用
+    free: VecDeque<u32>,
+    // `free` 中的地址，避免同一个地址重复排队
+    queued: HashSet<u32>,
+    // 正在使用的地址
+    allocated: HashMap<u32, PublicKey>,
+    // 动态分配的租约，客户端断开之后仍然保留，直到地址被分配给其它客户端
+    leases: HashMap<PublicKey, u32>,
+    lease_owners: HashMap<u32, PublicKey>,
+    // 固定分配给某个客户端的地址，不参与动态分配
+    static_leases: HashMap<PublicKey, u32>,
+    static_addrs: HashSet<u32>,
+    // 保留的地址范围 (包含两端)，不参与动态分配
+    reserved: Vec<(u32, u32)>,
+}
+
+impl AddressPool {
+    /// 创建一个动态分配范围为 `[start, end)` 的地址池
+    pub fn new(start: Ipv4Address, end: Ipv4Address) -> Self {
+        let start = to_number(start);
+        let end = to_number(end);
+
+        Self {
+            start,
+            end,
+            next: start,
+            free: VecDeque::new(),
+            queued: HashSet::new(),
+            allocated: HashMap::new(),
+            leases: HashMap::new(),
+            lease_owners: HashMap::new(),
+            static_leases: HashMap::new(),
+            static_addrs: HashSet::new(),
+            reserved: Vec::new(),
+        }
+    }
+
+    /// 保留 `[start, end]` 范围内的地址，这些地址不会再被动态分配
+    pub fn reserve(&mut self, start: Ipv4Address, end: Ipv4Address) {
+        let (start, end) = (to_number(start), to_number(end));
+        let leases = &mut self.leases;
+        self.lease_owners.retain(|addr, public_key| {
+            let is_reserved = *addr >= start && *addr <= end;
+            if is_reserved {
+                leases.remove(public_key);
+            }
+            !is_reserved
+        });
+
+        self.reserved.push((start, end));
+    }
+
+    /// 为客户端固定分配一个地址
+    pub fn add_static_lease(&mut self, public_key: PublicKey, addr: Ipv4Address) -> Result<(), io::Error> {
+        let addr = to_number(addr);
+        if self.static_addrs.contains(&addr) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "该地址已经固定分配给了其它客户端！"));
+        }
+
+        if let Some(public_key) = self.lease_owners.remove(&addr) {
+            self.leases.remove(&public_key);
+        }
+        if let Some(old_addr) = self.static_leases.insert(public_key, addr) {
+            self.static_addrs.remove(&old_addr);
+        }
+        self.static_addrs.insert(addr);
+
+        Ok(())
+    }
+
+    fn is_reserved(&self, addr: u32) -> bool {
+        self.reserved.iter().any(|(start, end)| addr >= *start && addr <= *end)
+    }
+
+    /// 该地址是否可以动态分配
+    fn is_available(&self, addr: u32) -> bool {
+        addr >= self.start && addr < self.end
+            && !self.allocated.contains_key(&addr)
+            && !self.static_addrs.contains(&addr)
+            && !self.is_reserved(addr)
+    }
+
+    fn push_free(&mut self, addr: u32) {
+        if self.queued.insert(addr) {
+            self.free.push_back(addr);
+        }
+    }
+
+    fn take_lease(&mut self, public_key: &PublicKey, addr: u32) -> Ipv4Address {
+        // NOTE: 客户端通过租约重新得到排队中的地址时，不从 `free` 中删除 (O(n))，
+        //       它仍然留在 `queued` 中，避免再次回收时重复排队，取出时因为已经分配而被跳过。
+        if let Some(old_owner) = self.lease_owners.insert(addr, *public_key) {
+            if &old_owner != public_key {
+                self.leases.remove(&old_owner);
+            }
+        }
+        if let Some(old_addr) = self.leases.insert(*public_key, addr) {
+            if old_addr != addr {
+                self.lease_owners.remove(&old_addr);
+            }
+        }
+
+        self.allocated.insert(addr, *public_key);
+        to_addr(addr)
+    }
+
+    /// 为客户端分配地址，已经分配过的客户端会得到相同的地址
+    pub fn allocate(&mut self, public_key: &PublicKey) -> Option<Ipv4Address> {
+        if let Some(addr) = self.static_leases.get(public_key).cloned() {
+            self.allocated.insert(addr, *public_key);
+            return Some(to_addr(addr));
+        }
+
+        if let Some(addr) = self.leases.get(public_key).cloned() {
+            if self.allocated.get(&addr) == Some(public_key) || self.is_available(addr) {
+                return Some(self.take_lease(public_key, addr));
+            }
+        }
+
+        while self.next < self.end {
+            let addr = self.next;
+            self.next += 1;
+
+            if !self.is_available(addr) {
+                continue;
+            }
+
+            if self.lease_owners.contains_key(&addr) {
+                // NOTE: 保留给其它客户端的租约，最后才会被使用
+                self.push_free(addr);
+                continue;
+            }
+
+            return Some(self.take_lease(public_key, addr));
+        }
+
+        while let Some(addr) = self.free.pop_front() {
+            self.queued.remove(&addr);
+            if self.is_available(addr) {
+                return Some(self.take_lease(public_key, addr));
+            }
+        }
+
+        None
+    }
+
+    /// 回收地址，租约仍然保留，直到该地址被分配给其它客户端
+    pub fn release(&mut self, addr: Ipv4Address) {
+        let addr = to_number(addr);
+        if self.allocated.remove(&addr).is_some() && self.is_available(addr) {
+            self.push_free(addr);
+        }
+    }
+
+    /// 从文件加载租约，文件格式为每行一个租约: `<公钥> <地址>`
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), io::Error> {
+        let file = fs::File::open(path)?;
+
+        for line in io::BufReader::new(file).lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let public_key = fields.next().and_then(|s| s.parse::<PublicKey>().ok());
+            let addr = fields.next().and_then(|s| s.parse::<Ipv4Addr>().ok());
+            let (public_key, addr) = match (public_key, addr) {
+                (Some(public_key), Some(addr)) => (public_key, u32::from(addr)),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("无法解析租约: {}", line))),
+            };
+
+            if self.static_leases.contains_key(&public_key) || !self.is_available(addr) || self.lease_owners.contains_key(&addr) {
+                continue;
+            }
+
+            self.leases.insert(public_key, addr);
+            self.lease_owners.insert(addr, public_key);
+        }
+
+        Ok(())
+    }
+
+    /// 把动态分配的租约保存到文件
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+
+        {
+            let mut file = fs::File::create(&tmp_path)?;
+            writeln!(file, "# exodus address leases: <public key> <address>")?;
+            for (public_key, addr) in self.leases.iter() {
+                writeln!(file, "{} {}", public_key, Ipv4Addr::from(*addr))?;
+            }
+            file.sync_all()?;
+        }
+
+        // NOTE: 先写入临时文件再重命名，避免写入过程中崩溃导致租约丢失
+        fs::rename(&tmp_path, path)
+    }
+}
+
+
+#[cfg(test)]
+fn test_key(n: u8) -> PublicKey {
+    PublicKey([n; 32])
+}
+
+#[test]
+fn test_address_pool_allocate() {
+    let mut pool = AddressPool::new(Ipv4Address::new(10, 0, 0, 5), Ipv4Address::new(10, 0, 0, 8));
+    assert_eq!(pool.allocate(&test_key(1)), Some(Ipv4Address::new(10, 0, 0, 5)));
+    assert_eq!(pool.allocate(&test_key(2)), Some(Ipv4Address::new(10, 0, 0, 6)));
+    // 同一个客户端得到相同的地址
+    assert_eq!(pool.allocate(&test_key(1)), Some(Ipv4Address::new(10, 0, 0, 5)));
+    assert_eq!(pool.allocate(&test_key(3)), Some(Ipv4Address::new(10, 0, 0, 7)));
+    assert_eq!(pool.allocate(&test_key(4)), None);
+
+    // 回收之后的地址仍然保留给原来的客户端
+    pool.release(Ipv4Address::new(10, 0, 0, 6));
+    assert_eq!(pool.allocate(&test_key(2)), Some(Ipv4Address::new(10, 0, 0, 6)));
+
+    // 地址池耗尽时，租约会被其它客户端取代
+    pool.release(Ipv4Address::new(10, 0, 0, 6));
+    assert_eq!(pool.allocate(&test_key(4)), Some(Ipv4Address::new(10, 0, 0, 6)));
+    assert_eq!(pool.allocate(&test_key(2)), None);
+}
+
+#[test]
+fn test_address_pool_reconnect() {
+    let mut pool = AddressPool::new(Ipv4Address::new(10, 0, 0, 5), Ipv4Address::new(10, 0, 0, 8));
+    assert_eq!(pool.allocate(&test_key(2)), Some(Ipv4Address::new(10, 0, 0, 5)));
+
+    // 同一个客户端反复断开、重新接入，回收队列不会增长
+    for _ in 0..100 {
+        let addr = pool.allocate(&test_key(1)).unwrap();
+        pool.release(addr);
+        assert_eq!(pool.free.len(), 1);
+        assert_eq!(pool.allocate(&test_key(1)), Some(addr));
+        assert_eq!(pool.free.len(), 1);
+        pool.release(addr);
+    }
+    assert_eq!(pool.free.len(), pool.queued.len());
+    assert_eq!(pool.free.len(), 1);
+
+    // 排队中的地址被原来的客户端重新得到之后，不会再分配给其它客户端
+    assert_eq!(pool.allocate(&test_key(1)), Some(Ipv4Address::new(10, 0, 0, 6)));
+    assert_eq!(pool.allocate(&test_key(3)), Some(Ipv4Address::new(10, 0, 0, 7)));
+    assert_eq!(pool.allocate(&test_key(4)), None);
+    assert!(pool.free.is_empty());
+    assert!(pool.queued.is_empty());
+
+    // 出队之后再次回收，地址重新排队
+    pool.release(Ipv4Address::new(10, 0, 0, 6));
+    assert_eq!(pool.allocate(&test_key(4)), Some(Ipv4Address::new(10, 0, 0, 6)));
+}
+
+#[test]
+fn test_address_pool_static_and_reserved() {
+    let mut pool = AddressPool::new(Ipv4Address::new(10, 0, 0, 1), Ipv4Address::new(10, 0, 0, 10));
+    pool.reserve(Ipv4Address::new(10, 0, 0, 1), Ipv4Address::new(10, 0, 0, 3));
+    pool.add_static_lease(test_key(9), Ipv4Address::new(10, 0, 0, 4)).unwrap();
+    assert!(pool.add_static_lease(test_key(8), Ipv4Address::new(10, 0, 0, 4)).is_err());
+
+    assert_eq!(pool.allocate(&test_key(1)), Some(Ipv4Address::new(10, 0, 0, 5)));
+    assert_eq!(pool.allocate(&test_key(9)), Some(Ipv4Address::new(10, 0, 0, 4)));
+
+    // 固定分配的地址回收之后，不会被动态分配
+    pool.release(Ipv4Address::new(10, 0, 0, 4));
+    for n in 2..7 {
+        assert_ne!(pool.allocate(&test_key(n)), Some(Ipv4Address::new(10, 0, 0, 4)));
+    }
+}
+
+#[test]
+fn test_address_pool_persistence() {
+    let path = std::env::temp_dir().join(format!("exodus-leases-{}.txt", std::process::id()));
+    let start = Ipv4Address::new(10, 0, 0, 1);
+    let end = Ipv4Address::new(10, 0, 0, 100);
+
+    let mut pool = AddressPool::new(start, end);
+    assert_eq!(pool.allocate(&test_key(1)), Some(Ipv4Address::new(10, 0, 0, 1)));
+    assert_eq!(pool.allocate(&test_key(2)), Some(Ipv4Address::new(10, 0, 0, 2)));
+    pool.save(&path).unwrap();
+
+    let mut pool = AddressPool::new(start, end);
+    pool.load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    // 新客户端不会占用已有租约的地址
+    assert_eq!(pool.allocate(&test_key(3)), Some(Ipv4Address::new(10, 0, 0, 3)));
+    assert_eq!(pool.allocate(&test_key(2)), Some(Ipv4Address::new(10, 0, 0, 2)));
+    assert_eq!(pool.allocate(&test_key(1)), Some(Ipv4Address::new(10, 0, 0, 1)));
+}
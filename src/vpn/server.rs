@@ -2,29 +2,54 @@ use mio;
 use smoltcp::wire::{
     PrettyPrinter,
     EthernetAddress, EthernetFrame, EthernetProtocol,
-    IpProtocol, IpVersion,
+    IpProtocol, IpVersion, IpAddress, IpCidr,
     Ipv4Cidr, Ipv4Address, Ipv4Packet,
+    Ipv6Cidr, Ipv6Address,
     TcpPacket, UdpPacket,
 };
 
+use crypto::{ AesSiv256, Identity, PublicKey, EphemeralKey, };
+
 use crate::signal;
 use crate::vpn::{
     InterfaceKind,
-    TAP_TOKEN, TUN_TOKEN, UDP_TOKEN,
-    DHCP_REQ_PACKET_SIGNATURE, DHCP_RES_PACKET_SIGNATURE,
-    TUNNEL_PACKET_SIGNATURE, BYE_PACKET_SIGNATURE,
+    TAP_TOKEN, TUN_TOKEN, UDP_TOKEN, UDP6_TOKEN, ROUTE_TOKEN, NETLINK_TOKEN,
+    HANDSHAKE_INIT_PACKET_SIGNATURE, HANDSHAKE_RESP_PACKET_SIGNATURE,
+    TUNNEL_PACKET_SIGNATURE, BYE_PACKET_SIGNATURE, REKEY_PACKET_SIGNATURE, KEEPALIVE_PACKET_SIGNATURE,
+    REKEY_GRACE_TIME, REKEY_RETRY_TIME,
+    parse_ip_pkt, read_tun_pkt, write_tun_pkt, bind_udp_v6only,
 };
+use crate::vpn::handshake::{ HandshakeInit, HandshakeResp, session_keys, unix_timestamp, };
+use crate::vpn::session::{ self, Session, };
+use crate::vpn::pool::AddressPool;
+use crate::vpn::dhcp::DhcpState;
+use crate::vpn::system::{ self, SystemConfig, };
+
+#[cfg(target_os = "linux")]
+use netlink::route::monitor::{ Monitor, Event, };
+#[cfg(target_os = "linux")]
+use netlink::route::RouteController;
+#[cfg(target_os = "linux")]
+use netlink::route::route::RouteRequest;
+#[cfg(target_os = "linux")]
+use netlink::socket::{ Pipeline, Ack, };
+#[cfg(target_os = "linux")]
+use netlink::packet::{ Protocol, Kind, Flags, MulticastGroups, RouteTable, };
 
 use std::collections::HashMap;
 use std::io::{self, Read, Write};
 use std::os::unix::io::AsRawFd;
-use std::net::{ IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, UdpSocket, };
+use std::net::{ IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpListener, UdpSocket, };
+use std::path::PathBuf;
+use std::time::{ Duration, Instant, };
 
 
 #[derive(Debug, Clone)]
 pub struct VpnServerConfig {
     pub tun_ifname: String,
     pub tun_cidr: Ipv4Cidr,
+    // 可选的 IPv6 地址池，客户端的 IPv6 地址与 IPv4 地址在各自网段内的偏移量相同
+    pub tun_cidr6: Option<Ipv6Cidr>,
     pub egress_iface_kind: InterfaceKind,
     pub egress_iface_name: String,
     pub egress_iface_addr: Ipv4Address,
@@ -35,20 +60,148 @@ pub struct VpnServerConfig {
     pub egress_iface_gateway_addr: Option<Ipv4Address>,
     pub egress_iface_gateway_hwaddr: Option<EthernetAddress>,
     pub tunnel_service_udp_port: u16,
+    // 同时在 IPv6 地址 ([::]) 上监听隧道服务
+    pub tunnel_service_dual_stack: bool,
     // pub dhcp_service_udp_port: u16,
+    // 服务端的身份密钥，客户端需要配置对应的公钥
+    pub identity: Identity,
+    // 允许接入的客户端公钥，其它客户端的握手请求会被忽略
+    pub authorized_keys: Vec<PublicKey>,
+    // 会话存在的时间或者传输的数据量超出限制之后，要求客户端重新握手
+    pub rekey_after_time: Duration,
+    pub rekey_after_bytes: u64,
+    // 超过这个时间没有收到客户端的数据包，则回收该客户端的地址
+    pub peer_timeout: Duration,
+    // 推送给客户端的路由，客户端没有配置路由时，只有这些地址的流量经过隧道
+    pub pushed_routes: Vec<IpCidr>,
+    // 推送给客户端的 DNS 服务器和搜索域
+    pub dns_servers: Vec<IpAddr>,
+    pub search_domains: Vec<String>,
+    // 固定分配给客户端的地址
+    pub static_leases: Vec<(PublicKey, Ipv4Address)>,
+    // 不参与动态分配的地址范围 (包含两端)
+    pub reserved_addrs: Vec<(Ipv4Address, Ipv4Address)>,
+    // 保存租约的文件，服务端重启之后客户端仍然可以得到相同的地址
+    pub lease_file: Option<PathBuf>,
+}
+
+struct Peer {
+    tun_addr   : Ipv4Address,
+    tun_addr6  : Option<Ipv6Address>,
+    public_key : PublicKey,
+    socket_addr: SocketAddr,
+    session    : Session,
+    // 重新握手之后，等待客户端确认的新会话
+    next_session: Option<Session>,
+    // rekey 完成之后，在宽限期内仍然可以使用的旧会话
+    previous_session: Option<(Session, Instant)>,
+    rekey_requested_at: Option<Instant>,
+    // 最后一次收到该客户端认证通过的数据包的时间
+    last_seen: Instant,
+}
+
+impl Peer {
+    fn has_session(&self, id: u32) -> bool {
+        self.session.id() == id
+            || self.next_session.as_ref().map(|session| session.id() == id).unwrap_or(false)
+            || self.previous_session.as_ref().map(|(session, _)| session.id() == id).unwrap_or(false)
+    }
+
+    /// 数据包认证通过之后，更新客户端的公网地址 (客户端切换网络之后地址会发生变化)
+    fn update_socket_addr(&mut self, socket_addr: SocketAddr) {
+        if self.socket_addr != socket_addr {
+            info!("[{}] 客户端地址变更: {} --> {}", self.tun_addr, self.socket_addr, socket_addr);
+            self.socket_addr = socket_addr;
+        }
+    }
+
+    fn seal(&mut self, packet_signature: [u8; 4], plaintext: &[u8]) -> Vec<u8> {
+        self.session.seal(packet_signature, plaintext)
+    }
+
+    fn open(&mut self, message: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let plaintext = self.open_with_session(message)?;
+        self.last_seen = Instant::now();
+
+        Ok(plaintext)
+    }
+
+    fn open_with_session(&mut self, message: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let id = session::session_id(message);
+
+        if Some(self.session.id()) == id {
+            return self.session.open(message);
+        }
+
+        if let Some(ref mut next_session) = self.next_session {
+            if Some(next_session.id()) == id {
+                // 收到了新会话的数据包，说明客户端已经完成握手
+                let plaintext = next_session.open(message)?;
+                let next_session = self.next_session.take().unwrap();
+                let previous_session = std::mem::replace(&mut self.session, next_session);
+                self.previous_session = Some((previous_session, Instant::now() + REKEY_GRACE_TIME));
+                self.rekey_requested_at = None;
+
+                info!("[{}] rekey 完成 (session {})", self.tun_addr, self.session.id());
+
+                return Ok(plaintext);
+            }
+        }
+
+        if let Some((ref mut previous_session, expire_at)) = self.previous_session {
+            if Some(previous_session.id()) == id && Instant::now() < expire_at {
+                return previous_session.open(message);
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::InvalidData, "unknown session"))
+    }
+}
+
+/// 隧道服务的 UDP Socket，开启双栈之后同时监听 IPv6 地址
+struct TunnelSocket {
+    ipv4: mio::net::UdpSocket,
+    ipv6: Option<mio::net::UdpSocket>,
+}
+
+impl TunnelSocket {
+    /// 根据目标地址的协议族选择发送数据包的 Socket
+    fn send_to(&self, buf: &[u8], target: &SocketAddr) -> Result<usize, io::Error> {
+        match (target, &self.ipv6) {
+            (SocketAddr::V6(_), Some(ipv6)) => ipv6.send_to(buf, target),
+            _ => self.ipv4.send_to(buf, target),
+        }
+    }
 }
 
 pub struct VpnServer {
     config  :        VpnServerConfig,
     tun_addr:        Ipv4Address,
     tun_netmask:     Ipv4Address,
-    dhcp_start_addr: u32,
-    dhcp_end_addr:   u32,
-    dhcp_next_addr:  u32,
-    neighbor  :      HashMap<Ipv4Address, SocketAddrV4>,
+    tun_addr6:       Option<Ipv6Address>,
+    pool:            AddressPool,
+    peers     :      HashMap<Ipv4Address, Peer>,
+    // 虚拟地址 (IPv4 以及 IPv6) 到客户端的映射
+    neighbor  :      HashMap<IpAddress, Ipv4Address>,
+    sessions  :      HashMap<u32, Ipv4Address>,
+    // 每个客户端最后一次握手的时间戳
+    handshake_timestamps: HashMap<PublicKey, u64>,
     buffer:          [u8; 2048],
     tun_device:      tun::Device,
-    udp_socket:      mio::net::UdpSocket,
+    udp_socket:      TunnelSocket,
+    system_config:   SystemConfig,
+    // 监听系统路由表的变化，VPN 网段的路由被删除时重新添加
+    #[cfg(target_os = "linux")]
+    route_monitor:   Monitor,
+    // 非阻塞地发送路由请求，避免阻塞事件循环
+    #[cfg(target_os = "linux")]
+    route_pipeline:  Pipeline,
+    // 事件溢出时重新读取路由表，复用同一个连接和缓冲区
+    #[cfg(target_os = "linux")]
+    route_controller: RouteController,
+    #[cfg(target_os = "linux")]
+    route_buffer:    [u8; netlink::packet::MAX_NL_LENGTH],
+    auth_failures:   u64,
 }
 
 impl VpnServer {
@@ -61,9 +214,35 @@ impl VpnServer {
         let tun_addr = Ipv4Addr::from(tun_cidr_start_number + 1);
         let tun_netmask = Ipv4Addr::from(tun_cidr.netmask());
 
-        let dhcp_start_addr = tun_cidr_start_number + 5;
-        let dhcp_end_addr   = tun_cidr_end_number - 5;
-        let dhcp_next_addr  = dhcp_start_addr;
+        let dhcp_start_addr = Ipv4Addr::from(tun_cidr_start_number + 5);
+        let dhcp_end_addr   = Ipv4Addr::from(tun_cidr_end_number - 5);
+        let mut pool = AddressPool::new(dhcp_start_addr.into(), dhcp_end_addr.into());
+        for (start, end) in config.reserved_addrs.iter() {
+            pool.reserve(*start, *end);
+        }
+        for (public_key, addr) in config.static_leases.iter() {
+            if !tun_cidr.contains_addr(addr) || *addr == Ipv4Address::from(tun_addr) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("固定分配的地址 {} 不可用！", addr)));
+            }
+            pool.add_static_lease(*public_key, *addr)?;
+        }
+        if let Some(ref lease_file) = config.lease_file {
+            match pool.load(lease_file) {
+                Ok(_) => info!("从 {} 加载租约", lease_file.display()),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => { },
+                Err(e) => return Err(e),
+            }
+        }
+
+        let tun_addr6 = match config.tun_cidr6 {
+            Some(tun_cidr6) => {
+                if 128 - tun_cidr6.prefix_len() < 32 - tun_cidr.prefix_len() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "IPv6 地址池小于 IPv4 地址池！"));
+                }
+                Some(ipv6_addr_at(&tun_cidr6, 1))
+            },
+            None => None,
+        };
 
         let mut tun_device = tun::Device::new(&config.tun_ifname)?;
         tun_device.set_address(tun_addr)?;
@@ -71,37 +250,33 @@ impl VpnServer {
         tun_device.set_destination(Ipv4Addr::new(0, 0, 0, 0))?;
         tun_device.set_mtu(1500)?;
         tun_device.enabled(true)?;
+        if let (Some(tun_addr6), Some(tun_cidr6)) = (tun_addr6, config.tun_cidr6) {
+            tun_device.add_address_v6(tun_addr6, tun_cidr6.prefix_len())?;
+        }
 
-        // NOTE:
-        // 这里需要为系统配置 静态路由
-        // 在未来，这个会通过 C 库自动实现
-        // 目前临时使用 命令行程序 去配置这些数据
-
+        // 配置转发、路由以及 NAT，退出时撤销
+        let mut system_config = SystemConfig::new();
+        #[cfg(target_os = "linux")]
+        {
+            let tun_network = IpAddr::from(Ipv4Addr::from(tun_cidr.address()));
+            system_config.enable_ipv4_forwarding()?;
+            system_config.add_route(tun_network, tun_cidr.prefix_len(), None, Some(&config.tun_ifname))?;
+            system_config.add_forward_accept(&config.tun_ifname)?;
+            system_config.add_masquerade(tun_cidr, &config.egress_iface_name)?;
+
+            if let Some(tun_cidr6) = config.tun_cidr6 {
+                let tun_network6 = IpAddr::from(Ipv6Addr::from(ipv6_addr_at(&tun_cidr6, 0)));
+                system_config.enable_ipv6_forwarding()?;
+                system_config.add_route(tun_network6, tun_cidr6.prefix_len(), None, Some(&config.tun_ifname))?;
+            }
+        }
+        #[cfg(target_os = "macos")]
         warn!("为系统路由表添加静态路由:
-        Linux:
-            # Clear iptables rules
-            sudo iptables -P INPUT ACCEPT;
-            sudo iptables -P FORWARD ACCEPT;
-            sudo iptables -P OUTPUT ACCEPT;
-            sudo iptables -t nat -F;
-            sudo iptables -t mangle -F;
-            sudo iptables -F;
-            sudo iptables -X;
-
-            sudo sysctl -w net.ipv4.conf.all.forwarding=1
-            sudo route add -net {} dev {}
-            sudo iptables -t nat -A POSTROUTING -s {} -o enp0s3 -j MASQUERADE
-            sudo iptables -A OUTPUT -o {} -j ACCEPT
-
         macOS:
             sudo route add -net {} -interface {}
             待补充 ...
-        ",
-        tun_cidr, &config.tun_ifname,
-        tun_cidr, &config.tun_ifname,
-        tun_cidr, &config.tun_ifname,);
+        ", tun_cidr, &config.tun_ifname);
 
-        std::thread::sleep(std::time::Duration::new(1, 0));
 
         if config.egress_iface_kind == InterfaceKind::Internet {
             // TODO: 一些网络环境没有以太网，直接接入了 因特网。
@@ -112,80 +287,284 @@ impl VpnServer {
             if config.egress_iface_hwaddr.is_none() || config.egress_iface_gateway_hwaddr.is_none() {
                 return Err(io::Error::new(io::ErrorKind::InvalidInput, "缺少以太网参数！"));
             }
+
+            // NOTE: 以太网模式下直接把数据包发往网关的 MAC 地址，固定网关的 ARP 记录，避免被系统更新或者过期
+            #[cfg(target_os = "linux")]
+            {
+                if let (Some(gateway_addr), Some(gateway_hwaddr)) = (config.egress_iface_gateway_addr, config.egress_iface_gateway_hwaddr) {
+                    let gateway_addr = IpAddr::from(Ipv4Addr::from(gateway_addr));
+                    system_config.add_static_neighbour(&config.egress_iface_name, gateway_addr, gateway_hwaddr)?;
+                }
+            }
         }
 
         let sa = SocketAddrV4::new(config.egress_iface_addr.into(), config.tunnel_service_udp_port);
         let mut udp_socket = mio::net::UdpSocket::bind(&sa.into())?;
+        let udp_socket6 = if config.tunnel_service_dual_stack {
+            let sa6 = SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, config.tunnel_service_udp_port, 0, 0);
+            Some(bind_udp_v6only(&sa6)?)
+        } else {
+            None
+        };
+        let udp_socket = TunnelSocket { ipv4: udp_socket, ipv6: udp_socket6 };
+
+        #[cfg(target_os = "linux")]
+        let route_monitor = {
+            let mut monitor = Monitor::new(MulticastGroups::RTMGRP_IPV4_ROUTE | MulticastGroups::RTMGRP_IPV6_ROUTE)?;
+            monitor.set_nonblock()?;
+            monitor
+        };
+        #[cfg(target_os = "linux")]
+        let route_pipeline = Pipeline::new(Protocol::NETLINK_ROUTE.into())?;
+        #[cfg(target_os = "linux")]
+        let route_controller = RouteController::new()?;
+        info!("server public key: {}", config.identity.public_key());
 
         Ok(VpnServer {
             config,
             tun_addr: tun_addr.into(),
             tun_netmask: tun_netmask.into(),
-            dhcp_start_addr,
-            dhcp_end_addr,
-            dhcp_next_addr,
+            tun_addr6,
+            pool,
+            peers: HashMap::new(),
             neighbor: HashMap::new(),
+            sessions: HashMap::new(),
+            handshake_timestamps: HashMap::new(),
             buffer: [0u8; 2048],
             tun_device,
             udp_socket,
+            system_config,
+            #[cfg(target_os = "linux")]
+            route_monitor,
+            #[cfg(target_os = "linux")]
+            route_pipeline,
+            #[cfg(target_os = "linux")]
+            route_controller,
+            #[cfg(target_os = "linux")]
+            route_buffer: netlink::packet::alloc(),
+            auth_failures: 0,
         })
     }
 
-    fn handle_dhcp_req(&mut self, remote_socket_addr: SocketAddrV4) -> Result<(), io::Error> {
-        let mut peer_tun_addr: Option<Ipv4Address> = None;
+    /// 认证失败而被丢弃的隧道数据包数量
+    pub fn auth_failures(&self) -> u64 {
+        self.auth_failures
+    }
 
-        for addr_num in self.dhcp_start_addr .. self.dhcp_end_addr {
-            let addr = Ipv4Address::from(std::net::Ipv4Addr::from(addr_num));
-            for (k, v) in &self.neighbor {
-                if &remote_socket_addr == v {
-                    return Ok(());
-                }
+    fn save_leases(&self) {
+        if let Some(ref lease_file) = self.config.lease_file {
+            if let Err(e) = self.pool.save(lease_file) {
+                warn!("无法保存租约到 {}: {}", lease_file.display(), e);
             }
-            if !self.neighbor.contains_key(&addr) {
-                peer_tun_addr = Some(addr);
-                break;
+        }
+    }
+
+    /// 客户端的 IPv6 地址，与 IPv4 地址在各自网段内的偏移量相同
+    fn peer_addr6(&self, tun_addr: Ipv4Address) -> Option<Ipv6Address> {
+        let tun_cidr = self.config.tun_cidr.network();
+        let offset = u32::from_be_bytes(tun_addr.0) - u32::from_be_bytes(tun_cidr.address().0);
+        self.config.tun_cidr6.map(|tun_cidr6| ipv6_addr_at(&tun_cidr6, offset))
+    }
+
+    fn alloc_session_id(&self) -> u32 {
+        loop {
+            let id = rand::random::<u32>();
+            if id != 0 && !self.sessions.contains_key(&id) {
+                return id;
             }
         }
+    }
 
-        let dhcp_addr = peer_tun_addr.unwrap_or(Ipv4Address::UNSPECIFIED);
+    fn handle_handshake_init(&mut self, remote_socket_addr: SocketAddr, pkt_amt: usize) -> Result<(), io::Error> {
+        let init = match HandshakeInit::parse(&self.buffer[..pkt_amt]) {
+            Some(init) => init,
+            None => {
+                trace!("[UDP] 畸形的握手数据包: {}", remote_socket_addr);
+                return Ok(());
+            },
+        };
+
+        // NOTE: 未认证的握手请求不做任何响应
+        if !self.config.authorized_keys.contains(&init.static_key) {
+            trace!("[UDP] 忽略未授权的握手请求: {} {}", remote_socket_addr, init.static_key);
+            return Ok(());
+        }
 
-        // 构建数据包
-        (&mut self.buffer[0..4]).copy_from_slice(&DHCP_RES_PACKET_SIGNATURE);
-        (&mut self.buffer[4..8]).copy_from_slice(&dhcp_addr.0);
+        if !init.verify(&self.config.identity.public_key()) {
+            trace!("[UDP] 忽略签名错误的握手请求: {} {}", remote_socket_addr, init.static_key);
+            return Ok(());
+        }
 
-        (&mut self.buffer[8..12]).copy_from_slice(&self.tun_addr.0);
-        (&mut self.buffer[12..16]).copy_from_slice(&self.tun_netmask.0);
+        let last_timestamp = self.handshake_timestamps.get(&init.static_key).cloned();
+        if !init.is_fresh(last_timestamp, unix_timestamp()) {
+            trace!("[UDP] 忽略重放或过期的握手请求: {} {}", remote_socket_addr, init.static_key);
+            return Ok(());
+        }
+        self.handshake_timestamps.insert(init.static_key, init.timestamp);
+
+        // 重新握手的客户端沿用原来的地址
+        let dhcp_addr = self.pool.allocate(&init.static_key).unwrap_or(Ipv4Address::UNSPECIFIED);
+
+        let ephemeral_key = EphemeralKey::generate();
+        let ephemeral_public_key = ephemeral_key.public_key();
+        let shared_secret = ephemeral_key.diffie_hellman(&init.ephemeral_key);
+        let (c2s_key, s2c_key) = session_keys(&shared_secret, &init.ephemeral_key, &ephemeral_public_key);
+
+        let dhcp_addr6 = if dhcp_addr != Ipv4Address::UNSPECIFIED { self.peer_addr6(dhcp_addr) } else { None };
+        let dhcp_state = DhcpState {
+            tun_addr: dhcp_addr,
+            tun_gateway_addr: self.tun_addr,
+            tun_netmask: self.tun_netmask,
+            tun_addr6: dhcp_addr6.and_then(|addr| self.config.tun_cidr6.map(|cidr| Ipv6Cidr::new(addr, cidr.prefix_len()))),
+            tun_gateway_addr6: dhcp_addr6.and(self.tun_addr6),
+            routes: self.config.pushed_routes.clone(),
+            dns_servers: self.config.dns_servers.clone(),
+            search_domains: self.config.search_domains.clone(),
+        };
+        let payload = dhcp_state.to_bytes();
+        let payload = AesSiv256::new(s2c_key).seal(&payload);
+
+        let session_id = self.alloc_session_id();
+        let resp = HandshakeResp::new(&self.config.identity, &init, ephemeral_public_key, session_id, payload);
+        if let Err(e) = self.udp_socket.send_to(&resp.to_bytes(), &remote_socket_addr) {
+            // NOTE: 例如出口网络变化时的 EHOSTUNREACH/EADDRNOTAVAIL，客户端会重新发起握手
+            warn!("[UDP] 发送握手响应到 {} 失败: {}", remote_socket_addr, e);
+        }
 
-        let message = &self.buffer[0..16];
-        self.udp_socket.send_to(&message, &(remote_socket_addr.into()))?;
-        
         if dhcp_addr != Ipv4Address::UNSPECIFIED {
-            debug!("为 {} 分配虚拟地址: {}", remote_socket_addr, dhcp_addr);
-            self.neighbor.insert(dhcp_addr, remote_socket_addr);
+            let session = Session::new(session_id, s2c_key, c2s_key);
+
+            match self.peers.get_mut(&dhcp_addr) {
+                Some(peer) => {
+                    // NOTE: 在客户端使用新会话发送数据之前，继续使用旧会话发送数据
+                    info!("[{}] 开始 rekey (session {})", dhcp_addr, session_id);
+                    peer.socket_addr = remote_socket_addr;
+                    peer.next_session = Some(session);
+                    peer.last_seen = Instant::now();
+                },
+                None => {
+                    debug!("为 {} ({}) 分配虚拟地址: {} {:?}", remote_socket_addr, init.static_key, dhcp_addr, dhcp_addr6);
+                    let peer = Peer {
+                        tun_addr: dhcp_addr,
+                        tun_addr6: dhcp_addr6,
+                        public_key: init.static_key,
+                        socket_addr: remote_socket_addr,
+                        session,
+                        next_session: None,
+                        previous_session: None,
+                        rekey_requested_at: None,
+                        last_seen: Instant::now(),
+                    };
+                    self.peers.insert(dhcp_addr, peer);
+                    self.save_leases();
+                    self.neighbor.insert(dhcp_addr.into(), dhcp_addr);
+                    if let Some(dhcp_addr6) = dhcp_addr6 {
+                        self.neighbor.insert(dhcp_addr6.into(), dhcp_addr);
+                    }
+                },
+            }
+
+            self.sessions.insert(session_id, dhcp_addr);
+        }
+
+        Ok(())
+    }
+
+    fn handle_bye(&mut self, remote_socket_addr: SocketAddr, pkt_amt: usize) -> Result<(), io::Error> {
+        let message = &self.buffer[..pkt_amt];
+        let peer_tun_addr = match session::session_id(&message).and_then(|id| self.sessions.get(&id)) {
+            Some(addr) => *addr,
+            None => return Ok(()),
+        };
+
+        let is_ok = match self.peers.get_mut(&peer_tun_addr) {
+            Some(peer) => peer.open(&message).is_ok(),
+            None => false,
+        };
+
+        if !is_ok {
+            self.auth_failures += 1;
+            debug!("[UDP] 丢弃来自 {} 的数据包: 认证失败 (累计 {} 个)", remote_socket_addr, self.auth_failures);
+            return Ok(());
+        }
+
+        if let Some(peer) = self.peers.remove(&peer_tun_addr) {
+            debug!("{} ({}) 断开连接", peer_tun_addr, remote_socket_addr);
+            self.pool.release(peer_tun_addr);
+            self.sessions.retain(|_, addr| addr != &peer_tun_addr);
+            self.neighbor.retain(|_, addr| addr != &peer_tun_addr);
         }
 
         Ok(())
     }
 
-    fn handle_tunnel_pkt(&mut self, remote_socket_addr: SocketAddrV4, pkt_amt: usize) -> Result<(), io::Error> {
-        let packet = &self.buffer[4..pkt_amt];
-        let ip_version = IpVersion::of_packet(&packet);
-        if ip_version != Ok(IpVersion::Ipv4) {
-            trace!("暂时只支持处理 IPv4 协议！");
+    fn handle_keepalive(&mut self, remote_socket_addr: SocketAddr, pkt_amt: usize) -> Result<(), io::Error> {
+        let message = &self.buffer[..pkt_amt];
+        let peer_tun_addr = session::session_id(&message).and_then(|id| self.sessions.get(&id)).cloned();
+        let peers = &mut self.peers;
+        let peer = match peer_tun_addr.and_then(|addr| peers.get_mut(&addr)) {
+            Some(peer) => peer,
+            None => return Ok(()),
+        };
+
+        if peer.open(&message).is_err() {
+            self.auth_failures += 1;
+            debug!("[UDP] 丢弃来自 {} 的数据包: 认证失败 (累计 {} 个)", remote_socket_addr, self.auth_failures);
             return Ok(());
         }
+        peer.update_socket_addr(remote_socket_addr);
 
-        let ipv4_packet = Ipv4Packet::new_unchecked(&packet);
-        let ipv4_protocol = ipv4_packet.protocol();
-        let src_ip = ipv4_packet.src_addr();
-        let dst_ip = ipv4_packet.dst_addr();
+        let message = peer.seal(KEEPALIVE_PACKET_SIGNATURE, &[]);
+        let _ = self.udp_socket.send_to(&message, &peer.socket_addr);
+
+        Ok(())
+    }
+
+    fn handle_tunnel_pkt(&mut self, remote_socket_addr: SocketAddr, pkt_amt: usize) -> Result<(), io::Error> {
+        let message = &self.buffer[..pkt_amt];
+        let peer_tun_addr = session::session_id(&message).and_then(|id| self.sessions.get(&id)).cloned();
+        let peers = &mut self.peers;
+        let packet = match peer_tun_addr.and_then(|addr| peers.get_mut(&addr)) {
+            Some(peer) => peer.open(&message).map(|packet| {
+                peer.update_socket_addr(remote_socket_addr);
+                packet
+            }),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)),
+        };
+        let packet = match packet {
+            Ok(packet) => packet,
+            Err(_) => {
+                self.auth_failures += 1;
+                debug!("[UDP] 丢弃来自 {} 的数据包: 认证失败 (累计 {} 个)", remote_socket_addr, self.auth_failures);
+                return Ok(());
+            }
+        };
+
+        if packet.is_empty() {
+            // 客户端用来确认新会话的空数据包
+            return Ok(());
+        }
+
+        let (ip_protocol, src_ip, dst_ip) = match parse_ip_pkt(&packet) {
+            Some(ip_info) => ip_info,
+            None => {
+                trace!("[UDP] 畸形的 IP 数据包！");
+                return Ok(());
+            },
+        };
+
+        let peer_tun_addr = peer_tun_addr.unwrap();
+        if self.neighbor.get(&src_ip) != Some(&peer_tun_addr) {
+            debug!("[UDP] 丢弃来自 {} 的数据包: 源地址 {} 与分配的地址不符", remote_socket_addr, src_ip);
+            return Ok(());
+        }
 
-        if self.config.tun_cidr.contains_addr(&dst_ip) {
+        if self.is_tun_network(&dst_ip) {
             // 子网路由，直接发送，不需要经过 TUN 设备中继
-            // TODO: 以后需要增加身份认证机制
-            if let Some(udp_socket_addr) = self.neighbor.get(&dst_ip) {
-                let message = &self.buffer[..packet.len() + 4];
-                let addr = (*udp_socket_addr).into();
+            let peers = &mut self.peers;
+            if let Some(peer) = self.neighbor.get(&dst_ip).and_then(|addr| peers.get_mut(addr)) {
+                let message = peer.seal(TUNNEL_PACKET_SIGNATURE, &packet);
+                let addr = peer.socket_addr;
                 let _ = self.udp_socket.send_to(&message, &addr);
             } else {
                 debug!("[TUN NETWORK] 无法路由该地址: {}", dst_ip);
@@ -193,67 +572,54 @@ impl VpnServer {
 
             return Ok(());
         }
-        
-        let std_src_ip: Ipv4Addr = src_ip.into();
-        if !std_src_ip.is_private() && !std_src_ip.is_global() {
-            debug!("[TAP NETWORK] 无法路由该地址: {}", dst_ip);
-            return Ok(());
+
+        if let IpAddress::Ipv4(src_ip) = src_ip {
+            let std_src_ip: Ipv4Addr = src_ip.into();
+            if !std_src_ip.is_private() && !std_src_ip.is_global() {
+                debug!("[TAP NETWORK] 无法路由该地址: {}", dst_ip);
+                return Ok(());
+            }
         }
-        
-        #[cfg(target_os = "macos")]
-        let packet = &self.buffer[..pkt_amt];
-        #[cfg(target_os = "macos")]
-        assert_eq!(&self.buffer[..4], TUNNEL_PACKET_SIGNATURE);
-        
-        trace!("[UDP] IPv4 {} {} --> {} ...", ipv4_protocol, src_ip, dst_ip);
-        self.tun_device.write(&packet)?;
+
+        trace!("[UDP] {} {} --> {} ...", ip_protocol, src_ip, dst_ip);
+
+        write_tun_pkt(&mut self.tun_device, &mut self.buffer, &packet)?;
 
         Ok(())
     }
 
-    pub fn handle_tun_pkt(&mut self) -> Result<(), io::Error> {
-        #[cfg(target_os = "macos")]
-        let amt = self.tun_device.read(&mut self.buffer)?;
+    /// 目标地址是否属于 VPN 的虚拟网段 (IPv4 或者 IPv6)
+    fn is_tun_network(&self, addr: &IpAddress) -> bool {
+        match addr {
+            IpAddress::Ipv4(addr) => self.config.tun_cidr.contains_addr(addr),
+            IpAddress::Ipv6(addr) => self.config.tun_cidr6.map(|cidr| cidr.contains_addr(addr)).unwrap_or(false),
+            _ => false,
+        }
+    }
 
-        #[cfg(target_os = "linux")]
-        &mut self.buffer[..4].copy_from_slice(&TUNNEL_PACKET_SIGNATURE);
-        #[cfg(target_os = "linux")]
-        let amt = self.tun_device.read(&mut self.buffer[4..])?;
-        
-        if amt <= 4 {
+    pub fn handle_tun_pkt(&mut self) -> Result<(), io::Error> {
+        let amt = read_tun_pkt(&mut self.tun_device, &mut self.buffer)?;
+        if amt == 0 {
             trace!("[TUN] 畸形的数据包！");
             return Ok(())
         }
 
-        let packet_signature = [
-            self.buffer[0], self.buffer[1], 
-            self.buffer[2], self.buffer[3], 
-        ];
-
-        #[cfg(target_os = "macos")]
-        assert_eq!(packet_signature, TUNNEL_PACKET_SIGNATURE);
-
-        #[cfg(target_os = "linux")]
-        let mut packet = &self.buffer[4..amt + 4];
-        #[cfg(target_os = "macos")]
-        let mut packet = &self.buffer[4..amt];
-
-        if Ok(IpVersion::Ipv4) != IpVersion::of_packet(&packet) {
-            trace!("[TUN] 暂时只支持处理 IPv4 协议！");
-            return Ok(());
-        }
-        
-        let ipv4_packet = Ipv4Packet::new_unchecked(&packet);
-        let ipv4_protocol = ipv4_packet.protocol();
-        let src_ip = ipv4_packet.src_addr();
-        let dst_ip = ipv4_packet.dst_addr();
+        let packet = &self.buffer[4..amt + 4];
+        let (ip_protocol, src_ip, dst_ip) = match parse_ip_pkt(&packet) {
+            Some(ip_info) => ip_info,
+            None => {
+                trace!("[TUN] 畸形的 IP 数据包！");
+                return Ok(());
+            },
+        };
 
-        if self.config.tun_cidr.contains_addr(&dst_ip) {
-            if let Some(udp_socket_addr) = self.neighbor.get(&dst_ip) {
-                let message = &self.buffer[..packet.len() + 4];
-                let addr = (*udp_socket_addr).into();
+        if self.is_tun_network(&dst_ip) {
+            let peers = &mut self.peers;
+            if let Some(peer) = self.neighbor.get(&dst_ip).and_then(|addr| peers.get_mut(addr)) {
+                let message = peer.seal(TUNNEL_PACKET_SIGNATURE, &packet);
+                let addr = peer.socket_addr;
 
-                trace!("[TUN] IPv4 {} {} --> {} ...", ipv4_protocol, src_ip, dst_ip);
+                trace!("[TUN] {} {} --> {} ...", ip_protocol, src_ip, dst_ip);
 
                 let _ = self.udp_socket.send_to(&message, &addr);
             } else {
@@ -267,32 +633,214 @@ impl VpnServer {
     }
 
 
+    fn maintain_sessions(&mut self) -> Result<(), io::Error> {
+        let now = Instant::now();
+        let rekey_after_time = self.config.rekey_after_time;
+        let rekey_after_bytes = self.config.rekey_after_bytes;
+        let mut rekey_requests = Vec::new();
+
+        // 回收长时间没有响应的客户端的地址
+        let peer_timeout = self.config.peer_timeout;
+        let expired_peers = self.peers.values()
+            .filter(|peer| now.duration_since(peer.last_seen) >= peer_timeout)
+            .map(|peer| peer.tun_addr)
+            .collect::<Vec<Ipv4Address>>();
+        for tun_addr in expired_peers {
+            if let Some(peer) = self.peers.remove(&tun_addr) {
+                info!("{} ({}) 超时未响应，回收地址", tun_addr, peer.socket_addr);
+                self.pool.release(tun_addr);
+                self.neighbor.retain(|_, addr| addr != &tun_addr);
+            }
+        }
+
+        for (tun_addr, peer) in self.peers.iter_mut() {
+            let is_expired = peer.previous_session.as_ref()
+                .map(|(_, expire_at)| now >= *expire_at)
+                .unwrap_or(false);
+            if is_expired {
+                peer.previous_session = None;
+            }
+
+            if peer.next_session.is_some() || !peer.session.needs_rekey(rekey_after_time, rekey_after_bytes) {
+                continue;
+            }
+
+            let is_due = peer.rekey_requested_at
+                .map(|requested_at| now.duration_since(requested_at) >= REKEY_RETRY_TIME)
+                .unwrap_or(true);
+            if is_due {
+                debug!("[{}] 请求客户端 rekey (已传输 {} 字节)", tun_addr, peer.session.bytes());
+                peer.rekey_requested_at = Some(now);
+                rekey_requests.push((peer.seal(REKEY_PACKET_SIGNATURE, &[]), peer.socket_addr));
+            }
+        }
+
+        for (message, socket_addr) in rekey_requests {
+            let _ = self.udp_socket.send_to(&message, &socket_addr);
+        }
+
+        // 清理已经失效的会话
+        let peers = &self.peers;
+        self.sessions.retain(|id, addr| peers.get(addr).map(|peer| peer.has_session(*id)).unwrap_or(false));
+
+        Ok(())
+    }
+
+    /// VPN 网段的路由 (添加在 TUN 设备上) 被删除时重新添加，只发送请求，不等待内核的响应。
+    /// 事件溢出 (ENOBUFS) 时重新读取路由表，找出缺失的 (或者不在 TUN 设备上的) 路由
+    #[cfg(target_os = "linux")]
+    fn handle_route_events(&mut self) -> Result<(), io::Error> {
+        let mut tun_networks = vec![
+            (IpCidr::Ipv4(self.config.tun_cidr.network()), IpAddr::from(Ipv4Addr::from(self.config.tun_cidr.network().address()))),
+        ];
+        if let Some(tun_cidr6) = self.config.tun_cidr6 {
+            let tun_network6 = ipv6_addr_at(&tun_cidr6, 0);
+            tun_networks.push((IpCidr::Ipv6(Ipv6Cidr::new(tun_network6, tun_cidr6.prefix_len())), IpAddr::from(Ipv6Addr::from(tun_network6))));
+        }
+
+        let ifindex = match system::ifindex(&self.config.tun_ifname) {
+            Ok(ifindex) => ifindex,
+            Err(e) => {
+                warn!("无法获取 {} 的接口序号: {}", self.config.tun_ifname, e);
+                return Ok(());
+            },
+        };
+
+        let mut removed_routes = Vec::new();
+        let mut resync = false;
+        loop {
+            match self.route_monitor.next_event() {
+                Ok(Event::DelRoute(route)) => {
+                    if route.table != RouteTable::RT_TABLE_MAIN || route.out_ifindex != Some(ifindex) {
+                        continue;
+                    }
+                    if let Some(tun_network) = tun_networks.iter().find(|(cidr, _)| Some(*cidr) == route.dst_cidr) {
+                        if !removed_routes.contains(tun_network) {
+                            removed_routes.push(*tun_network);
+                        }
+                    }
+                },
+                Ok(_) => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    // NOTE: 接收缓冲区溢出，部分事件已丢失，重新读取路由表
+                    warn!("路由表变化的事件溢出，重新读取路由表");
+                    resync = true;
+                    break;
+                },
+                Err(e) => {
+                    warn!("读取路由表变化失败: {}", e);
+                    break;
+                },
+            }
+        }
+
+        if resync {
+            match self.missing_routes(&tun_networks, ifindex) {
+                Ok(missing) => {
+                    for tun_network in missing {
+                        if !removed_routes.contains(&tun_network) {
+                            removed_routes.push(tun_network);
+                        }
+                    }
+                },
+                Err(e) => warn!("读取路由表失败: {}", e),
+            }
+        }
+
+        if removed_routes.is_empty() {
+            return Ok(());
+        }
+
+        for (cidr, network) in removed_routes {
+            let req = RouteRequest::new(network, cidr.prefix_len()).ifindex(ifindex);
+            let flags = Flags::NLM_F_CREATE | Flags::NLM_F_REPLACE;
+            match self.route_pipeline.submit(Kind::RTM_NEWROUTE, flags, |buffer| req.emit(buffer)) {
+                Ok(seq) => info!("路由 {} dev {} 被删除，重新添加 (seq {})", cidr, self.config.tun_ifname, seq),
+                Err(e) => warn!("无法重新添加路由 {} dev {}: {}", cidr, self.config.tun_ifname, e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 返回主路由表中不存在的 (或者不在 TUN 设备上的) 路由
+    #[cfg(target_os = "linux")]
+    fn missing_routes(&mut self, networks: &[(IpCidr, IpAddr)], ifindex: u32) -> Result<Vec<(IpCidr, IpAddr)>, io::Error> {
+        let mut missing = networks.to_vec();
+        for route in self.route_controller.routes(&mut self.route_buffer)? {
+            let route = route?;
+            if route.table != RouteTable::RT_TABLE_MAIN || route.out_ifindex != Some(ifindex) {
+                continue;
+            }
+            missing.retain(|(cidr, _)| Some(*cidr) != route.dst_cidr);
+        }
+
+        Ok(missing)
+    }
+
+    /// 读取路由请求的结果
+    #[cfg(target_os = "linux")]
+    fn handle_route_acks(&mut self) -> Result<(), io::Error> {
+        loop {
+            match self.route_pipeline.next_ack() {
+                Ok(Ack { seq, result: Ok(()) }) => debug!("路由请求 (seq {}) 已完成", seq),
+                Ok(Ack { seq, result: Err(e) }) => warn!("路由请求 (seq {}) 失败: {}", seq, e),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    debug!("读取路由请求的结果失败: {}", e);
+                    break;
+                },
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn run_forever(&mut self) -> Result<(), io::Error> {
         let mut events = mio::Events::with_capacity(2048);
         let poll = mio::Poll::new().unwrap();
 
-        poll.register(&self.udp_socket, UDP_TOKEN, mio::Ready::readable(), mio::PollOpt::edge())?;
+        poll.register(&self.udp_socket.ipv4, UDP_TOKEN, mio::Ready::readable(), mio::PollOpt::edge())?;
+        if let Some(ref udp_socket6) = self.udp_socket.ipv6 {
+            poll.register(udp_socket6, UDP6_TOKEN, mio::Ready::readable(), mio::PollOpt::edge())?;
+        }
         poll.register(&self.tun_device, TUN_TOKEN, mio::Ready::readable(), mio::PollOpt::edge())?;
+        #[cfg(target_os = "linux")]
+        {
+            poll.register(&self.route_monitor, ROUTE_TOKEN, mio::Ready::readable(), mio::PollOpt::edge())?;
+            poll.register(&self.route_pipeline, NETLINK_TOKEN, mio::Ready::readable(), mio::PollOpt::edge())?;
+        }
 
         let timeout = std::time::Duration::new(2, 0);
 
         loop {
             if !signal::is_running() {
-                // TODO: 清理系统配置
+                self.system_config.restore();
                 break;
             }
 
+            self.maintain_sessions()?;
+
             if let Err(_) = poll.poll(&mut events, Some(timeout)) {
                 continue;
             }
             
             for event in events.iter() {
                 match event.token() {
-                    UDP_TOKEN => {
-                        let (amt, remote_socket_addr) = self.udp_socket.recv_from(&mut self.buffer)?;
-                        let remote_socket_addr = match remote_socket_addr {
-                            SocketAddr::V4(v4_addr) => v4_addr,
-                            _ => unreachable!(),
+                    UDP_TOKEN | UDP6_TOKEN => {
+                        let udp_socket = match self.udp_socket.ipv6 {
+                            Some(ref udp_socket6) if event.token() == UDP6_TOKEN => udp_socket6,
+                            _ => &self.udp_socket.ipv4,
+                        };
+                        let (amt, remote_socket_addr) = match udp_socket.recv_from(&mut self.buffer) {
+                            Ok(ret) => ret,
+                            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                            Err(e) => {
+                                // NOTE: 例如 ICMP 端口不可达引起的 ECONNREFUSED，不影响其它客户端
+                                warn!("[UDP] 读取数据包失败: {}", e);
+                                continue;
+                            },
                         };
 
                         if amt < 4 {
@@ -305,32 +853,24 @@ impl VpnServer {
                         ];
 
                         match packet_signature {
-                            DHCP_REQ_PACKET_SIGNATURE => {
-                                // VPN 客户端请求分配内网地址
-                                self.handle_dhcp_req(remote_socket_addr)?;
+                            HANDSHAKE_INIT_PACKET_SIGNATURE => {
+                                // VPN 客户端发起握手，并请求分配内网地址
+                                self.handle_handshake_init(remote_socket_addr, amt)?;
                                 continue;
                             },
-                            DHCP_RES_PACKET_SIGNATURE => {
+                            HANDSHAKE_RESP_PACKET_SIGNATURE => {
                                 continue;
                             },
                             TUNNEL_PACKET_SIGNATURE => {
                                 self.handle_tunnel_pkt(remote_socket_addr, amt)?;
                                 continue;
                             },
+                            KEEPALIVE_PACKET_SIGNATURE => {
+                                self.handle_keepalive(remote_socket_addr, amt)?;
+                                continue;
+                            },
                             BYE_PACKET_SIGNATURE => {
-                                let mut peer_tun_addr: Option<Ipv4Address> = None;
-
-                                for (tun_ip, udp_addr) in &self.neighbor {
-                                    if udp_addr == &remote_socket_addr {
-                                        peer_tun_addr = Some(*tun_ip);
-                                        break;
-                                    }
-                                }
-
-                                if let Some(tun_addr) = peer_tun_addr {
-                                    self.neighbor.remove(&tun_addr);
-                                }
-                                
+                                self.handle_bye(remote_socket_addr, amt)?;
                                 continue;
                             },
                             _ => {
@@ -342,6 +882,14 @@ impl VpnServer {
                     TUN_TOKEN => {
                         self.handle_tun_pkt()?;
                     },
+                    #[cfg(target_os = "linux")]
+                    ROUTE_TOKEN => {
+                        self.handle_route_events()?;
+                    },
+                    #[cfg(target_os = "linux")]
+                    NETLINK_TOKEN => {
+                        self.handle_route_acks()?;
+                    },
                     _ => unreachable!(),
                 }
             }
@@ -351,3 +899,19 @@ impl VpnServer {
     }
 }
 
+
+/// 返回 IPv6 网段内偏移量为 `offset` 的地址
+fn ipv6_addr_at(cidr: &Ipv6Cidr, offset: u32) -> Ipv6Address {
+    let prefix_len = cidr.prefix_len() as u32;
+    let mask = if prefix_len == 0 { 0 } else { u128::max_value() << (128 - prefix_len) };
+    let network = u128::from_be_bytes(cidr.address().0) & mask;
+    Ipv6Address((network + offset as u128).to_be_bytes())
+}
+
+
+#[test]
+fn test_ipv6_addr_at() {
+    let cidr = Ipv6Cidr::new(Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x1234), 64);
+    assert_eq!(ipv6_addr_at(&cidr, 1), Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 1));
+    assert_eq!(ipv6_addr_at(&cidr, 0x10005), Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 1, 5));
+}
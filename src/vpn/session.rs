@@ -0,0 +1,227 @@
+use crypto::{ Aes256Key, AesSiv256, };
+
+use std::io;
+use std::time::{ Duration, Instant, };
+
+
+// 隧道数据包格式:
+//      packet_signature (4) | session_id (4) | counter (8) | ciphertext
+//
+// 每个数据包的计数器都不相同，并作为加密时的 nonce 使用，
+// 整个头部作为附加数据参与认证。
+pub const SESSION_HEADER_LEN: usize = 16;
+
+// 防重放窗口大小 (以 u64 为单位)，实际可以容忍的乱序范围为 (32 - 1) * 64 = 1984 个数据包
+const REPLAY_WINDOW_WORDS: usize = 32;
+const REPLAY_WINDOW_SIZE: u64    = (REPLAY_WINDOW_WORDS as u64 - 1) * 64;
+
+
+/// 解析数据包头部中的会话 ID
+pub fn session_id(message: &[u8]) -> Option<u32> {
+    if message.len() < SESSION_HEADER_LEN {
+        return None;
+    }
+
+    Some(u32::from_be_bytes([message[4], message[5], message[6], message[7]]))
+}
+
+/// 解析数据包头部中的计数器
+pub fn counter(message: &[u8]) -> Option<u64> {
+    if message.len() < SESSION_HEADER_LEN {
+        return None;
+    }
+
+    let mut counter = [0u8; 8];
+    counter.copy_from_slice(&message[8..16]);
+    Some(u64::from_be_bytes(counter))
+}
+
+
+/// 滑动窗口防重放过滤器 (RFC 6479)
+pub struct ReplayWindow {
+    // 已经收到的最大计数器 + 1，0 表示还没有收到任何数据包
+    next: u64,
+    bitmap: [u64; REPLAY_WINDOW_WORDS],
+}
+
+impl ReplayWindow {
+    pub fn new() -> Self {
+        Self { next: 0, bitmap: [0u64; REPLAY_WINDOW_WORDS] }
+    }
+
+    /// 检查该计数器是否可以接收，不会修改窗口状态
+    pub fn check(&self, counter: u64) -> bool {
+        if counter >= self.next {
+            return true;
+        }
+
+        if self.next - 1 - counter >= REPLAY_WINDOW_SIZE {
+            // 太旧了
+            return false;
+        }
+
+        let word = ((counter / 64) % REPLAY_WINDOW_WORDS as u64) as usize;
+        let bit = counter % 64;
+        self.bitmap[word] & (1 << bit) == 0
+    }
+
+    /// 标记该计数器已经收到，数据包需要在认证通过之后才能更新窗口
+    pub fn update(&mut self, counter: u64) -> bool {
+        if !self.check(counter) {
+            return false;
+        }
+
+        if counter >= self.next {
+            let block = counter / 64;
+            if self.next == 0 {
+                self.bitmap = [0u64; REPLAY_WINDOW_WORDS];
+            } else {
+                // 清空窗口向前滑动时移出的部分
+                let top_block = (self.next - 1) / 64;
+                let n = std::cmp::min(block - top_block, REPLAY_WINDOW_WORDS as u64);
+                for i in 1..n + 1 {
+                    self.bitmap[((top_block + i) % REPLAY_WINDOW_WORDS as u64) as usize] = 0;
+                }
+            }
+
+            self.next = counter + 1;
+        }
+
+        let word = ((counter / 64) % REPLAY_WINDOW_WORDS as u64) as usize;
+        let bit = counter % 64;
+        self.bitmap[word] |= 1 << bit;
+
+        true
+    }
+}
+
+
+/// 一次握手协商出来的会话
+pub struct Session {
+    id: u32,
+    sealer: AesSiv256,
+    opener: AesSiv256,
+    send_counter: u64,
+    replay_window: ReplayWindow,
+    created_at: Instant,
+    // 该会话加密和解密的数据量
+    bytes: u64,
+}
+
+impl Session {
+    pub fn new(id: u32, send_key: Aes256Key, recv_key: Aes256Key) -> Self {
+        Self {
+            id,
+            sealer: AesSiv256::new(send_key),
+            opener: AesSiv256::new(recv_key),
+            send_counter: 0,
+            replay_window: ReplayWindow::new(),
+            created_at: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// 会话存在的时间或者传输的数据量超出限制之后，需要重新协商密钥
+    pub fn needs_rekey(&self, after_time: Duration, after_bytes: u64) -> bool {
+        self.created_at.elapsed() >= after_time || self.bytes >= after_bytes
+    }
+
+    /// 加密数据，并加上数据包签名、会话 ID 以及计数器
+    pub fn seal(&mut self, packet_signature: [u8; 4], plaintext: &[u8]) -> Vec<u8> {
+        let counter = self.send_counter;
+        self.send_counter += 1;
+        self.bytes += plaintext.len() as u64;
+
+        let mut header = [0u8; SESSION_HEADER_LEN];
+        (&mut header[..4]).copy_from_slice(&packet_signature);
+        (&mut header[4..8]).copy_from_slice(&self.id.to_be_bytes());
+        (&mut header[8..16]).copy_from_slice(&counter.to_be_bytes());
+
+        let ciphertext = self.sealer.seal_with(&header[8..16], &header, plaintext);
+        let mut message = Vec::with_capacity(SESSION_HEADER_LEN + ciphertext.len());
+        message.extend_from_slice(&header);
+        message.extend_from_slice(&ciphertext);
+        message
+    }
+
+    /// 验证并解密数据包，`message` 需要包含完整的数据包头部
+    pub fn open(&mut self, message: &[u8]) -> Result<Vec<u8>, io::Error> {
+        if session_id(message) != Some(self.id) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "session id mismatch"));
+        }
+
+        let counter = counter(message).unwrap();
+        // NOTE: 先做一次廉价的检查，避免为重放的数据包做解密运算
+        if !self.replay_window.check(counter) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "replayed packet"));
+        }
+
+        let header = &message[..SESSION_HEADER_LEN];
+        let plaintext = self.opener.open_with(&header[8..16], header, &message[SESSION_HEADER_LEN..])?;
+
+        if !self.replay_window.update(counter) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "replayed packet"));
+        }
+
+        self.bytes += plaintext.len() as u64;
+
+        Ok(plaintext)
+    }
+}
+
+
+#[test]
+fn test_replay_window_in_order() {
+    let mut window = ReplayWindow::new();
+    for counter in 0..10_000 {
+        assert!(window.update(counter));
+    }
+}
+
+#[test]
+fn test_replay_window_out_of_order() {
+    let mut window = ReplayWindow::new();
+    assert!(window.update(10));
+    assert!(window.update(8));
+    assert!(window.update(9));
+    assert!(window.update(0));
+    assert!(window.update(100));
+    assert!(window.update(99));
+    assert!(window.update(11));
+}
+
+#[test]
+fn test_replay_window_duplicate() {
+    let mut window = ReplayWindow::new();
+    assert!(window.update(0));
+    assert!(!window.update(0));
+
+    assert!(window.update(5));
+    assert!(window.update(3));
+    assert!(!window.check(3));
+    assert!(!window.update(3));
+    assert!(!window.update(5));
+    assert!(window.update(4));
+}
+
+#[test]
+fn test_replay_window_too_old() {
+    let mut window = ReplayWindow::new();
+    assert!(window.update(REPLAY_WINDOW_SIZE + 100));
+    assert!(!window.check(0));
+    assert!(!window.update(100));
+    assert!(window.update(101));
+
+    // 窗口大幅度前移之后，旧窗口内的数据全部失效
+    assert!(window.update(REPLAY_WINDOW_SIZE * 10));
+    assert!(!window.update(REPLAY_WINDOW_SIZE + 200));
+    assert!(window.update(REPLAY_WINDOW_SIZE * 10 - 1));
+}
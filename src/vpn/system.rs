@@ -0,0 +1,452 @@
+use smoltcp::wire::{ IpCidr, Ipv4Cidr, EthernetAddress, };
+
+#[cfg(target_os = "linux")]
+use netlink::route::RouteController;
+#[cfg(target_os = "linux")]
+use netlink::route::route::RouteRequest;
+#[cfg(target_os = "linux")]
+use netlink::packet::{ RouteTable, RouteType, };
+#[cfg(target_os = "linux")]
+use sysconfig::firewall::{ Firewall, Rule as FirewallRule, };
+
+use std::ffi::CString;
+use std::io;
+use std::net::{ IpAddr, SocketAddr, };
+
+
+/// 对系统网络配置所做的一项修改
+#[derive(Debug, Clone)]
+enum Change {
+    Ipv4Forwarding,
+    Ipv6Forwarding,
+    Route { dst_addr: IpAddr, prefix_len: u8 },
+    #[cfg(target_os = "linux")]
+    Firewall { rule: FirewallRule },
+    #[cfg(target_os = "linux")]
+    Neighbour { ip_addr: IpAddr, ifindex: u32, previous: Option<sysconfig::neigh::Neigh> },
+    Dns { original: DnsConfig },
+    #[cfg(target_os = "macos")]
+    KillSwitch,
+}
+
+
+impl Change {
+    fn is_kill_switch(&self) -> bool {
+        match self {
+            #[cfg(target_os = "linux")]
+            Change::Firewall { rule: FirewallRule::KillSwitch { .. } } => true,
+            #[cfg(target_os = "macos")]
+            Change::KillSwitch => true,
+            _ => false,
+        }
+    }
+}
+
+
+/// 系统原来的 DNS 配置
+#[cfg(target_os = "linux")]
+type DnsConfig = sysconfig::dns::ResolverConfig;
+
+/// 系统原来的 DNS 配置
+#[cfg(target_os = "macos")]
+#[derive(Debug, Clone)]
+struct DnsConfig {
+    service_id: String,
+    nameservers: Vec<IpAddr>,
+    search_domains: Vec<String>,
+}
+
+
+pub fn ifindex(ifname: &str) -> Result<u32, io::Error> {
+    let name = CString::new(ifname).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if ifindex == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ifindex)
+}
+
+/// 删除接口上的地址
+#[cfg(target_os = "linux")]
+pub fn remove_address(ifname: &str, addr: IpAddr, prefix_len: u8) -> Result<(), io::Error> {
+    let ifindex = ifindex(ifname)?;
+    let mut buffer = [0u8; 1024];
+    RouteController::new()?.remove_addr(ifindex, addr, prefix_len, &mut buffer)
+}
+
+/// 删除接口上的地址
+#[cfg(target_os = "macos")]
+pub fn remove_address(ifname: &str, addr: IpAddr, _prefix_len: u8) -> Result<(), io::Error> {
+    // $ ifconfig <ifname> inet6 <addr> delete
+    let family = if addr.is_ipv4() { "inet" } else { "inet6" };
+    let output = std::process::Command::new("ifconfig")
+        .args(&[ifname, family, &addr.to_string(), "delete"])
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::new(io::ErrorKind::Other, stderr.trim().to_string()));
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn add_route(dst_addr: IpAddr, prefix_len: u8, gateway: Option<IpAddr>, ifindex: Option<u32>) -> Result<(), io::Error> {
+    let mut req = RouteRequest::new(dst_addr, prefix_len);
+    req.gateway = gateway;
+    req.ifindex = ifindex;
+
+    let mut buffer = [0u8; 1024 * 4];
+    RouteController::new()?.add_route(&req, &mut buffer)
+}
+
+#[cfg(target_os = "linux")]
+fn remove_route(dst_addr: IpAddr, prefix_len: u8) -> Result<(), io::Error> {
+    let mut buffer = [0u8; 1024 * 4];
+    RouteController::new()?.remove_route(dst_addr, prefix_len, RouteTable::RT_TABLE_MAIN, &mut buffer)
+}
+
+#[cfg(target_os = "macos")]
+fn add_route(dst_addr: IpAddr, prefix_len: u8, gateway: Option<IpAddr>, ifindex: Option<u32>) -> Result<(), io::Error> {
+    sysconfig::route::add(dst_addr, prefix_len, gateway, ifindex)
+}
+
+#[cfg(target_os = "macos")]
+fn remove_route(dst_addr: IpAddr, prefix_len: u8) -> Result<(), io::Error> {
+    sysconfig::route::delete(dst_addr, prefix_len)
+}
+
+#[cfg(target_os = "linux")]
+fn load_dns() -> Result<DnsConfig, io::Error> {
+    sysconfig::dns::load_resolver_config()
+}
+
+#[cfg(target_os = "linux")]
+fn set_dns(original: &DnsConfig, nameservers: &[IpAddr], search_domains: &[String]) -> Result<(), io::Error> {
+    let mut config = original.clone();
+    config.nameservers = nameservers.to_vec();
+    if !search_domains.is_empty() {
+        // NOTE: domain 和 search 是互斥的，以最后出现的为准
+        config.domains.clear();
+        config.search = search_domains.to_vec();
+    }
+
+    sysconfig::dns::save_resolver_config(&config)
+}
+
+#[cfg(target_os = "linux")]
+fn restore_dns(original: &DnsConfig) -> Result<(), io::Error> {
+    sysconfig::dns::save_resolver_config(original)
+}
+
+#[cfg(target_os = "macos")]
+fn load_dns() -> Result<DnsConfig, io::Error> {
+    let service = sysconfig::dns::get_network_global().service;
+    let dns = service.dns();
+
+    Ok(DnsConfig {
+        service_id: service.id(),
+        nameservers: dns.manually_specifying_addrs.unwrap_or_default(),
+        search_domains: dns.manually_specifying_search_domains.unwrap_or_default(),
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn set_service_dns(service_id: &str, nameservers: &[IpAddr], search_domains: &[String]) -> Result<(), io::Error> {
+    let service = sysconfig::dns::list_network_services()
+        .into_iter()
+        .find(|service| service.id() == service_id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "找不到网络服务！"))?;
+
+    if !service.set_dns_config(nameservers, search_domains)? {
+        return Err(io::Error::new(io::ErrorKind::Other, "无法设置 DNS！"));
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn set_dns(original: &DnsConfig, nameservers: &[IpAddr], search_domains: &[String]) -> Result<(), io::Error> {
+    set_service_dns(&original.service_id, nameservers, search_domains)
+}
+
+#[cfg(target_os = "macos")]
+fn restore_dns(original: &DnsConfig) -> Result<(), io::Error> {
+    set_service_dns(&original.service_id, &original.nameservers, &original.search_domains)
+}
+
+/// 系统默认路由的网关
+#[cfg(target_os = "linux")]
+pub fn default_gateway(ipv4: bool) -> Result<Option<IpAddr>, io::Error> {
+    let mut buffer = netlink::packet::alloc();
+    let mut route_controller = RouteController::new()?;
+
+    for route in route_controller.routes(&mut buffer)? {
+        let route = route?;
+        if route.kind != RouteType::RTN_UNICAST {
+            continue;
+        }
+
+        let is_default = route.dst_cidr.map(|cidr| cidr.prefix_len() == 0).unwrap_or(true);
+        match route.gateway {
+            Some(gateway) if is_default && gateway.is_ipv4() == ipv4 => return Ok(Some(gateway)),
+            _ => continue,
+        }
+    }
+
+    Ok(None)
+}
+
+/// 系统默认路由的网关
+#[cfg(target_os = "macos")]
+pub fn default_gateway(ipv4: bool) -> Result<Option<IpAddr>, io::Error> {
+    let mut buffer = Vec::with_capacity(8192);
+
+    for route in sysconfig::route::list(&mut buffer)? {
+        if route.dst.prefix_len() != 0 {
+            continue;
+        }
+
+        match route.gateway {
+            sysconfig::route::Addr::V4(gateway) if ipv4 => return Ok(Some(gateway.into())),
+            sysconfig::route::Addr::V6(gateway) if !ipv4 => return Ok(Some(gateway.into())),
+            _ => continue,
+        }
+    }
+
+    Ok(None)
+}
+
+
+/// 记录 VPN 对系统网络配置所做的修改，退出时 (或者 Drop 时) 按相反的顺序撤销
+///
+/// 只有真正由我们修改的配置才会被记录，例如系统原本已经开启了转发，
+/// 那么退出时也不会关闭它。
+///
+/// 只有调用 `restore` (正常断开) 时才会撤销 Kill Switch，出错退出 (Drop) 时保留它，
+/// 避免 VPN 断开后数据包绕过隧道离开本机。
+#[derive(Debug, Default)]
+pub struct SystemConfig {
+    changes: Vec<Change>,
+    #[cfg(target_os = "linux")]
+    firewall: Firewall,
+}
+
+impl SystemConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enable_ipv4_forwarding(&mut self) -> Result<(), io::Error> {
+        if sysconfig::ip_forwarding::ipv4_forwarding()? {
+            return Ok(());
+        }
+
+        if !sysconfig::ip_forwarding::enable_ipv4_forwarding()? {
+            return Err(io::Error::new(io::ErrorKind::Other, "无法开启 IPv4 转发！"));
+        }
+        info!("开启 IPv4 转发");
+        self.changes.push(Change::Ipv4Forwarding);
+
+        Ok(())
+    }
+
+    pub fn enable_ipv6_forwarding(&mut self) -> Result<(), io::Error> {
+        if sysconfig::ip_forwarding::ipv6_forwarding()? {
+            return Ok(());
+        }
+
+        if !sysconfig::ip_forwarding::enable_ipv6_forwarding()? {
+            return Err(io::Error::new(io::ErrorKind::Other, "无法开启 IPv6 转发！"));
+        }
+        info!("开启 IPv6 转发");
+        self.changes.push(Change::Ipv6Forwarding);
+
+        Ok(())
+    }
+
+    /// 添加路由，路由已经存在时不做任何修改
+    pub fn add_route(&mut self, dst_addr: IpAddr, prefix_len: u8, gateway: Option<IpAddr>, ifname: Option<&str>) -> Result<(), io::Error> {
+        let ifindex = match ifname {
+            Some(ifname) => Some(ifindex(ifname)?),
+            None => None,
+        };
+
+        match add_route(dst_addr, prefix_len, gateway, ifindex) {
+            Ok(_) => {
+                info!("添加路由: {}/{} via {:?} dev {:?}", dst_addr, prefix_len, gateway, ifname);
+                self.changes.push(Change::Route { dst_addr, prefix_len });
+                Ok(())
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                debug!("路由已经存在: {}/{}", dst_addr, prefix_len);
+                Ok(())
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 删除由我们添加的路由
+    pub fn remove_route(&mut self, dst_addr: IpAddr, prefix_len: u8) -> Result<(), io::Error> {
+        let pos = self.changes.iter().position(|change| match change {
+            Change::Route { dst_addr: addr, prefix_len: len } => *addr == dst_addr && *len == prefix_len,
+            _ => false,
+        });
+
+        if let Some(pos) = pos {
+            self.changes.remove(pos);
+            remove_route(dst_addr, prefix_len)?;
+            info!("删除路由: {}/{}", dst_addr, prefix_len);
+        }
+
+        Ok(())
+    }
+
+    /// 添加防火墙规则 (nftables 的 `inet exodus` 表)，规则已经存在时不做任何修改
+    #[cfg(target_os = "linux")]
+    pub fn add_firewall_rule(&mut self, rule: FirewallRule) -> Result<(), io::Error> {
+        if self.firewall.rules().contains(&rule) {
+            return Ok(());
+        }
+
+        self.firewall.add(rule.clone())?;
+        info!("添加防火墙规则: {:?}", rule);
+        self.changes.push(Change::Firewall { rule });
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn add_masquerade(&mut self, source: Ipv4Cidr, out_ifname: &str) -> Result<(), io::Error> {
+        self.add_firewall_rule(FirewallRule::Masquerade { source: IpCidr::Ipv4(source), out_ifname: out_ifname.to_string() })
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn add_forward_accept(&mut self, ifname: &str) -> Result<(), io::Error> {
+        for rule in FirewallRule::forward_accept(ifname) {
+            self.add_firewall_rule(rule)?;
+        }
+
+        Ok(())
+    }
+
+    /// 只允许经过隧道以及发往服务端的数据包离开本机
+    #[cfg(target_os = "linux")]
+    pub fn add_kill_switch(&mut self, tun_ifname: &str, server_addr: &SocketAddr) -> Result<(), io::Error> {
+        self.add_firewall_rule(FirewallRule::KillSwitch { tun_ifname: tun_ifname.to_string(), server_addr: *server_addr })
+    }
+
+    /// 只允许经过隧道以及发往服务端的数据包离开本机
+    #[cfg(target_os = "macos")]
+    pub fn add_kill_switch(&mut self, tun_ifname: &str, server_addr: &SocketAddr) -> Result<(), io::Error> {
+        if self.changes.iter().any(|change| match change { Change::KillSwitch => true, _ => false, }) {
+            return Ok(());
+        }
+
+        sysconfig::firewall::add_kill_switch(tun_ifname, server_addr)?;
+        info!("开启 Kill Switch: 只允许 {} 以及发往 {} 的数据包", tun_ifname, server_addr);
+        self.changes.push(Change::KillSwitch);
+
+        Ok(())
+    }
+
+    /// 添加一条静态的 ARP/NDP 记录，系统中已经存在的记录会被覆盖，撤销时恢复原来的记录
+    #[cfg(target_os = "linux")]
+    pub fn add_static_neighbour(&mut self, ifname: &str, ip_addr: IpAddr, hw_addr: EthernetAddress) -> Result<(), io::Error> {
+        let ifindex = ifindex(ifname)?;
+        let exists = self.changes.iter().any(|change| match change {
+            Change::Neighbour { ip_addr: addr, ifindex: index, .. } => *addr == ip_addr && *index == ifindex,
+            _ => false,
+        });
+
+        let previous = if exists { None } else { sysconfig::neigh::get(ip_addr, ifindex)? };
+        if let Some(ref neigh) = previous {
+            if neigh.link_addr == hw_addr && neigh.state == sysconfig::neigh::State::Permanent {
+                debug!("静态邻居记录已经存在: {} lladdr {} dev {}", ip_addr, hw_addr, ifname);
+                return Ok(());
+            }
+        }
+
+        sysconfig::neigh::replace(ip_addr, hw_addr, ifindex, sysconfig::neigh::State::Permanent)?;
+        match previous {
+            Some(ref neigh) => info!("添加静态邻居记录: {} lladdr {} dev {} (原来的记录: lladdr {} {:?})",
+                                     ip_addr, hw_addr, ifname, neigh.link_addr, neigh.state),
+            None => info!("添加静态邻居记录: {} lladdr {} dev {}", ip_addr, hw_addr, ifname),
+        }
+        if !exists {
+            self.changes.push(Change::Neighbour { ip_addr, ifindex, previous });
+        }
+
+        Ok(())
+    }
+
+    /// 设置系统的 DNS 服务器和搜索域，多次设置时只记录第一次修改之前的配置
+    pub fn set_dns(&mut self, nameservers: &[IpAddr], search_domains: &[String]) -> Result<(), io::Error> {
+        let original = self.changes.iter().find_map(|change| match change {
+            Change::Dns { original } => Some(original.clone()),
+            _ => None,
+        });
+
+        match original {
+            Some(original) => set_dns(&original, nameservers, search_domains)?,
+            None => {
+                let original = load_dns()?;
+                set_dns(&original, nameservers, search_domains)?;
+                self.changes.push(Change::Dns { original });
+            },
+        }
+        info!("设置 DNS: {:?} search {:?}", nameservers, search_domains);
+
+        Ok(())
+    }
+
+    /// 按相反的顺序撤销所有修改，出错时只记录日志，继续撤销其它修改
+    pub fn restore(&mut self) {
+        while let Some(change) = self.changes.pop() {
+            self.revert(change);
+        }
+    }
+
+    /// 撤销 Kill Switch 以外的所有修改
+    fn restore_except_kill_switch(&mut self) {
+        while let Some(change) = self.changes.pop() {
+            if change.is_kill_switch() {
+                warn!("没有正常断开，保留 Kill Switch: {:?}", change);
+                continue;
+            }
+
+            self.revert(change);
+        }
+    }
+
+    fn revert(&mut self, change: Change) {
+        let ret = match change {
+            Change::Ipv4Forwarding => sysconfig::ip_forwarding::disable_ipv4_forwarding().map(|_| ()),
+            Change::Ipv6Forwarding => sysconfig::ip_forwarding::disable_ipv6_forwarding().map(|_| ()),
+            Change::Route { dst_addr, prefix_len } => remove_route(dst_addr, prefix_len),
+            #[cfg(target_os = "linux")]
+            Change::Firewall { ref rule } => self.firewall.remove(rule),
+            #[cfg(target_os = "linux")]
+            Change::Neighbour { ip_addr, ifindex, previous: None } => sysconfig::neigh::delete(ip_addr, ifindex),
+            #[cfg(target_os = "linux")]
+            Change::Neighbour { previous: Some(ref neigh), .. } => {
+                sysconfig::neigh::replace(neigh.ip_addr, neigh.link_addr, neigh.link_index, neigh.state)
+            },
+            Change::Dns { ref original } => restore_dns(original),
+            #[cfg(target_os = "macos")]
+            Change::KillSwitch => sysconfig::firewall::remove_kill_switch(),
+        };
+
+        match ret {
+            Ok(_) => info!("撤销系统配置: {:?}", change),
+            Err(e) => error!("无法撤销系统配置 {:?}: {}", change, e),
+        }
+    }
+}
+
+impl Drop for SystemConfig {
+    fn drop(&mut self) {
+        self.restore_except_kill_switch();
+    }
+}